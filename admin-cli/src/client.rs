@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use serde_json::Value;
+
+/// [ApiClient] sends authenticated requests to the `/v1/admin` API of the microservice.
+pub struct ApiClient {
+    agent: ureq::Agent,
+    base_url: String,
+    admin_token: String,
+}
+
+impl ApiClient {
+    pub fn new(base_url: String, admin_token: String) -> Self {
+        Self {
+            agent: ureq::Agent::new(),
+            base_url,
+            admin_token,
+        }
+    }
+
+    pub fn get(&self, path: &str, query: &[(&str, Option<String>)]) -> anyhow::Result<Value> {
+        self.send(self.request("GET", path, query), None::<()>)
+    }
+
+    pub fn post(
+        &self,
+        path: &str,
+        query: &[(&str, Option<String>)],
+        body: impl Serialize,
+    ) -> anyhow::Result<Value> {
+        self.send(self.request("POST", path, query), Some(body))
+    }
+
+    pub fn put(&self, path: &str, body: impl Serialize) -> anyhow::Result<Value> {
+        self.send(self.request("PUT", path, &[]), Some(body))
+    }
+
+    pub fn patch(&self, path: &str, body: impl Serialize) -> anyhow::Result<Value> {
+        self.send(self.request("PATCH", path, &[]), Some(body))
+    }
+
+    pub fn delete(&self, path: &str) -> anyhow::Result<Value> {
+        self.send(self.request("DELETE", path, &[]), None::<()>)
+    }
+
+    fn request(&self, method: &str, path: &str, query: &[(&str, Option<String>)]) -> ureq::Request {
+        let mut request = self
+            .agent
+            .request(method, &format!("{}/v1/admin{path}", self.base_url))
+            .set("Authorization", &format!("Bearer {}", self.admin_token));
+
+        for (name, value) in query {
+            if let Some(value) = value {
+                request = request.query(name, value);
+            }
+        }
+
+        request
+    }
+
+    fn send(&self, request: ureq::Request, body: Option<impl Serialize>) -> anyhow::Result<Value> {
+        let url = request.url().to_string();
+        let result = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+
+        match result {
+            Ok(response) if response.status() == 204 => Ok(Value::Null),
+            Ok(response) => response
+                .into_json()
+                .with_context(|| format!("Invalid response from {url}")),
+            Err(ureq::Error::Status(status, response)) => Err(problem_error(status, response)),
+            Err(e) => Err(e).with_context(|| format!("Request to {url} failed")),
+        }
+    }
+}
+
+/// Turns an `application/problem+json` answer into an error that reads like the problem.
+fn problem_error(status: u16, response: ureq::Response) -> anyhow::Error {
+    let problem = response.into_json::<Value>().unwrap_or_default();
+
+    let title = problem["title"].as_str().unwrap_or("Request failed");
+    let mut message = match problem["detail"].as_str() {
+        Some(detail) => format!("{status} {title}: {detail}"),
+        None => format!("{status} {title}"),
+    };
+
+    // Failed imports list what conflicted.
+    for conflict in problem["conflicts"].as_array().into_iter().flatten() {
+        message.push_str(&format!(
+            "\n  {} {}: {}",
+            conflict["kind"].as_str().unwrap_or_default(),
+            conflict["key"].as_str().unwrap_or_default(),
+            conflict["reason"].as_str().unwrap_or_default(),
+        ));
+    }
+
+    anyhow!(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(status: u16, body: &str) -> String {
+        let response = ureq::Response::new(status, "", body).unwrap();
+        problem_error(status, response).to_string()
+    }
+
+    #[test]
+    fn requests_go_to_the_admin_api() {
+        let client = ApiClient::new("http://localhost:3000".to_string(), "secret".to_string());
+
+        let request = client.request(
+            "GET",
+            "/users",
+            &[
+                ("server_id_prefix", Some("kiwi".to_string())),
+                ("cursor", None),
+            ],
+        );
+        let url = request.request_url().unwrap();
+
+        assert_eq!(url.path(), "/v1/admin/users");
+        assert_eq!(url.query_pairs(), [("server_id_prefix", "kiwi")]);
+        assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+    }
+
+    #[test]
+    fn problems_read_like_errors() {
+        assert_eq!(
+            problem(
+                404,
+                r#"{"type": "about:blank", "title": "Not Found", "status": 404, "detail": "User not found"}"#
+            ),
+            "404 Not Found: User not found"
+        );
+        assert_eq!(
+            problem(500, r#"{"title": "Internal Server Error", "status": 500}"#),
+            "500 Internal Server Error"
+        );
+        assert_eq!(problem(502, "Bad Gateway"), "502 Request failed");
+    }
+
+    #[test]
+    fn import_problems_list_their_conflicts() {
+        let body = r#"{
+            "title": "Conflict",
+            "detail": "Import failed",
+            "conflicts": [
+                {"kind": "config", "key": "kiwitech:smp", "reason": "client id is taken"}
+            ]
+        }"#;
+
+        assert_eq!(
+            problem(409, body),
+            "409 Conflict: Import failed\n  config kiwitech:smp: client id is taken"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::{env, fs::File, io::BufReader, path::PathBuf};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+/// [Config] tells the CLI which microservice to talk to and how to authenticate.
+///
+/// Values given on the command line or through the environment win over the config file.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    pub base_url: Option<String>,
+    pub admin_token: Option<String>,
+}
+
+impl Config {
+    /// Loads the config file at `path`, or the default one if it exists.
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let Some(path) = path.or_else(default_path).filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+
+        let file = BufReader::new(
+            File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?,
+        );
+
+        serde_json::from_reader(file).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Returns the base URL and admin token, preferring `base_url` and `admin_token` if given.
+    pub fn resolve(
+        self,
+        base_url: Option<String>,
+        admin_token: Option<String>,
+    ) -> anyhow::Result<(String, String)> {
+        let Some(base_url) = base_url.or(self.base_url) else {
+            bail!("No base URL, set it with --url, CHATBRIDGE_URL or in the config file");
+        };
+        let Some(admin_token) = admin_token.or(self.admin_token) else {
+            bail!(
+                "No admin token, set it with --token, CHATBRIDGE_ADMIN_TOKEN or in the config file"
+            );
+        };
+
+        Ok((base_url.trim_end_matches('/').to_string(), admin_token))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chatbridge/admin.json`, or `~/.config/chatbridge/admin.json`.
+fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("chatbridge").join("admin.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `content` to a file in the temp dir that is unique to `name` and this process.
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "chatbridge-admin-{}-{name}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_the_config_file() {
+        let path = config_file(
+            "load",
+            r#"{"base_url": "https://chatbridge.example.com", "admin_token": "secret"}"#,
+        );
+
+        let config = Config::load(Some(path.clone())).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            config.base_url.as_deref(),
+            Some("https://chatbridge.example.com")
+        );
+        assert_eq!(config.admin_token.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn missing_files_give_an_empty_config() {
+        let config = Config::load(Some(PathBuf::from("/nonexistent/admin.json"))).unwrap();
+
+        assert!(config.base_url.is_none());
+        assert!(config.admin_token.is_none());
+    }
+
+    #[test]
+    fn invalid_files_name_the_file() {
+        let path = config_file("invalid", "base_url = 1");
+
+        let error = Config::load(Some(path.clone())).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            format!("Failed to parse {}", path.display())
+        );
+    }
+
+    #[test]
+    fn arguments_win_over_the_file() {
+        let config = || Config {
+            base_url: Some("https://file.example.com/".to_string()),
+            admin_token: Some("file".to_string()),
+        };
+
+        assert_eq!(
+            config().resolve(None, None).unwrap(),
+            ("https://file.example.com".to_string(), "file".to_string())
+        );
+        assert_eq!(
+            config()
+                .resolve(
+                    Some("http://localhost:3000".to_string()),
+                    Some("cli".to_string())
+                )
+                .unwrap(),
+            ("http://localhost:3000".to_string(), "cli".to_string())
+        );
+    }
+
+    #[test]
+    fn resolve_needs_a_url_and_a_token() {
+        let error = Config::default()
+            .resolve(None, Some("cli".to_string()))
+            .unwrap_err();
+        assert!(error.to_string().starts_with("No base URL"));
+
+        let error = Config::default()
+            .resolve(Some("http://localhost:3000".to_string()), None)
+            .unwrap_err();
+        assert!(error.to_string().starts_with("No admin token"));
+    }
+}
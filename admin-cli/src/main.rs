@@ -0,0 +1,938 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
+use serde_json::{json, Value};
+
+use crate::client::ApiClient;
+use crate::config::Config;
+use crate::output::{Format, Output};
+
+mod client;
+mod config;
+mod output;
+
+const USER_COLUMNS: &[(&str, &str)] = &[
+    ("SERVER", "server_id"),
+    ("SERVERS", "server_list"),
+    ("CIDRS", "allowed_cidrs"),
+    ("CLIENTS", "clients"),
+    ("CONNECTIONS", "connections"),
+];
+
+const TOKEN_COLUMNS: &[(&str, &str)] = &[
+    ("ID", "id"),
+    ("SERVER", "server_id"),
+    ("LABEL", "label"),
+    ("SCOPES", "scopes"),
+    ("CLIENTS", "client_ids"),
+    ("EXPIRES", "expires_at"),
+    ("LAST USED", "last_used_at"),
+    ("REVOKED", "revoked_at"),
+];
+
+const CONFIG_COLUMNS: &[(&str, &str)] =
+    &[("CLIENT", "identifier"), ("SUBSCRIPTIONS", "subscriptions")];
+
+const CONNECTION_COLUMNS: &[(&str, &str)] = &[
+    ("ID", "id"),
+    ("CLIENT", "identifier"),
+    ("ADDRESS", "address"),
+    ("CONNECTED", "connected_at"),
+    ("SPECTATOR", "spectator"),
+    ("RECEIVED", "messages_received"),
+    ("SENT", "messages_sent"),
+];
+
+const STATUS_COLUMNS: &[(&str, &str)] = &[
+    ("CLIENT", "identifier"),
+    ("ONLINE", "online"),
+    ("SINCE", "connected_since"),
+    ("LAST DISCONNECT", "last_disconnected_at"),
+    ("REASON", "disconnect_reason"),
+];
+
+const AUDIT_COLUMNS: &[(&str, &str)] = &[
+    ("ID", "id"),
+    ("TIME", "created_at"),
+    ("ACTOR", "actor"),
+    ("ACTION", "action"),
+    ("TARGET", "target"),
+    ("IP", "source_ip"),
+];
+
+const WEBHOOK_COLUMNS: &[(&str, &str)] = &[
+    ("CLIENT", "identifier"),
+    ("URL", "url"),
+    ("UPDATED", "updated_at"),
+];
+
+const DEAD_LETTER_COLUMNS: &[(&str, &str)] = &[
+    ("ID", "id"),
+    ("CLIENT", "identifier"),
+    ("URL", "url"),
+    ("ATTEMPTS", "attempts"),
+    ("FAILED", "failed_at"),
+    ("ERROR", "last_error"),
+];
+
+const ADMIN_COLUMNS: &[(&str, &str)] = &[
+    ("ID", "id"),
+    ("NAME", "name"),
+    ("ROLE", "role"),
+    ("SERVER", "server_id"),
+    ("CREATED", "created_at"),
+];
+
+const ADMIN_TOKEN_COLUMNS: &[(&str, &str)] = &[
+    ("ID", "id"),
+    ("LABEL", "label"),
+    ("EXPIRES", "expires_at"),
+    ("LAST USED", "last_used_at"),
+    ("REVOKED", "revoked_at"),
+];
+
+/// Manages a chatbridge microservice through its admin API.
+#[derive(Debug, Parser)]
+#[command(name = "chatbridge-admin", version)]
+struct Cli {
+    /// Config file with `base_url` and `admin_token`.
+    /// Defaults to `~/.config/chatbridge/admin.json`.
+    #[arg(long, env = "CHATBRIDGE_ADMIN_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// Base URL of the microservice, like `https://chatbridge.example.com`.
+    #[arg(long, env = "CHATBRIDGE_URL", global = true)]
+    url: Option<String>,
+    /// Admin token to authenticate with.
+    #[arg(
+        long,
+        env = "CHATBRIDGE_ADMIN_TOKEN",
+        global = true,
+        hide_env_values = true
+    )]
+    token: Option<String>,
+    #[arg(long, short, value_enum, default_value = "table", global = true)]
+    output: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Manage users, the servers that connect to the bridge.
+    #[command(subcommand)]
+    Users(UserCommand),
+    /// Manage the named API tokens of users.
+    #[command(subcommand)]
+    Tokens(TokenCommand),
+    /// Manage client configs.
+    #[command(subcommand)]
+    Configs(ConfigCommand),
+    /// Inspect and kick live websocket connections.
+    #[command(subcommand)]
+    Connections(ConnectionCommand),
+    /// Send a notice to connected clients.
+    Broadcast {
+        message: String,
+        /// Only notify the clients of this server.
+        #[arg(long)]
+        server_id: Option<String>,
+    },
+    /// Show the connection state of every configured client.
+    Status,
+    /// Show the audit log, newest first.
+    Audit(AuditArgs),
+    /// Inspect webhooks and replay deliveries that failed.
+    #[command(subcommand)]
+    Webhooks(WebhookCommand),
+    /// Manage admin accounts and their tokens.
+    #[command(subcommand)]
+    Admins(AdminCommand),
+    /// Write users and configs as JSON to FILE, or to stdout.
+    Export {
+        /// Include token hashes so that users keep their tokens after an import.
+        #[arg(long)]
+        include_token_hashes: bool,
+        file: Option<PathBuf>,
+    },
+    /// Import users and configs from an export.
+    Import {
+        file: PathBuf,
+        /// `merge` keeps what is not in the file, `replace` removes it.
+        #[arg(long, default_value = "merge")]
+        mode: String,
+        /// Only report what would change.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum UserCommand {
+    /// List users, a page at a time.
+    List {
+        /// Only users whose server_id starts with this.
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Only users that are subscribed to this server.
+        #[arg(long)]
+        subscribed_to: Option<String>,
+        #[arg(long)]
+        limit: Option<u32>,
+        /// Continue after the `next_cursor` of a previous page.
+        #[arg(long)]
+        cursor: Option<String>,
+        /// Fetch every page.
+        #[arg(long, conflicts_with = "cursor")]
+        all: bool,
+    },
+    Get {
+        server_id: String,
+    },
+    Add {
+        server_id: String,
+        /// Servers the user may subscribe to, separated by commas.
+        #[arg(long, value_delimiter = ',', required = true)]
+        servers: Vec<String>,
+        /// Primary token of the user.
+        #[arg(long = "auth-token")]
+        auth_token: String,
+        /// Addresses the tokens of the user may be used from.
+        #[arg(long = "cidr")]
+        cidrs: Vec<String>,
+    },
+    Update {
+        server_id: String,
+        #[arg(long, value_delimiter = ',')]
+        servers: Option<Vec<String>>,
+        /// Replaces the primary token of the user.
+        #[arg(long = "auth-token")]
+        auth_token: Option<String>,
+        /// Replaces the address allowlist.
+        #[arg(long = "cidr", conflicts_with = "clear_cidrs")]
+        cidrs: Vec<String>,
+        /// Removes the address allowlist.
+        #[arg(long)]
+        clear_cidrs: bool,
+    },
+    /// Delete a user with its tokens and configs.
+    Delete {
+        server_id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TokenCommand {
+    List {
+        #[arg(long)]
+        server_id: Option<String>,
+    },
+    /// Create a token. Its secret is only shown once.
+    Create {
+        server_id: String,
+        #[command(flatten)]
+        token: TokenArgs,
+    },
+    Update {
+        id: i64,
+        #[command(flatten)]
+        token: TokenArgs,
+    },
+    Revoke {
+        id: i64,
+    },
+    /// Replace a token with a new one with the same label, scopes and clients, then revoke it.
+    Rotate {
+        id: i64,
+    },
+}
+
+#[derive(Debug, Args)]
+struct TokenArgs {
+    #[arg(long)]
+    label: Option<String>,
+    /// ws_connect, config_read, config_write or spectate, separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    scopes: Option<Vec<String>>,
+    /// Clients the token may act as, separated by commas.
+    #[arg(long, value_delimiter = ',', conflicts_with = "all_clients")]
+    clients: Option<Vec<String>>,
+    /// Make the token valid for every client of its server again.
+    #[arg(long)]
+    all_clients: bool,
+    /// RFC 3339 timestamp, like `2025-01-01T00:00:00Z`.
+    #[arg(long, conflicts_with = "no_expiry")]
+    expires_at: Option<String>,
+    /// Remove the expiry of the token.
+    #[arg(long)]
+    no_expiry: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    List {
+        #[arg(long)]
+        server_id: Option<String>,
+    },
+    Get {
+        /// `server_id:client_id`
+        identifier: String,
+    },
+    /// Create or replace a config.
+    Set {
+        /// `server_id:client_id`
+        identifier: String,
+        /// Subscriptions, separated by commas.
+        #[arg(long, value_delimiter = ',', required = true)]
+        subscriptions: Vec<String>,
+        /// JSON file with the subscription templates.
+        #[arg(long)]
+        templates: Option<PathBuf>,
+    },
+    Delete {
+        /// `server_id:client_id`
+        identifier: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConnectionCommand {
+    List,
+    /// Close a connection. The reason is sent to the client.
+    Kick {
+        id: u64,
+        #[arg(long, default_value = "kicked by an admin")]
+        reason: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct AuditArgs {
+    #[arg(long)]
+    actor: Option<String>,
+    #[arg(long)]
+    action: Option<String>,
+    #[arg(long)]
+    target: Option<String>,
+    #[arg(long)]
+    server_id: Option<String>,
+    #[arg(long)]
+    limit: Option<u32>,
+    #[arg(long)]
+    cursor: Option<i64>,
+}
+
+#[derive(Debug, Subcommand)]
+enum WebhookCommand {
+    List {
+        #[arg(long)]
+        server_id: Option<String>,
+    },
+    /// List deliveries that failed on every attempt, newest first.
+    DeadLetters {
+        #[arg(long)]
+        server_id: Option<String>,
+        /// `server_id:client_id`
+        #[arg(long)]
+        identifier: Option<String>,
+        #[arg(long)]
+        limit: Option<u32>,
+        #[arg(long)]
+        cursor: Option<i64>,
+    },
+    /// Attempt a dead letter once more. It is removed if the delivery succeeds.
+    Replay { id: i64 },
+    /// Remove a dead letter without delivering it.
+    Discard { id: i64 },
+}
+
+#[derive(Debug, Subcommand)]
+enum AdminCommand {
+    List,
+    Add {
+        name: String,
+        /// superadmin, operator or server_admin.
+        #[arg(long)]
+        role: String,
+        /// Restrict the admin to a single server.
+        #[arg(long)]
+        server_id: Option<String>,
+    },
+    Delete {
+        id: i64,
+    },
+    /// List the tokens of an admin.
+    Tokens {
+        id: i64,
+    },
+    /// Create a token for an admin. Its secret is only shown once.
+    CreateToken {
+        id: i64,
+        #[arg(long)]
+        label: String,
+        #[arg(long)]
+        expires_at: Option<String>,
+    },
+    RevokeToken {
+        id: i64,
+        token_id: i64,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let (base_url, admin_token) = Config::load(cli.config)?.resolve(cli.url, cli.token)?;
+    let client = ApiClient::new(base_url, admin_token);
+    let out = Output { format: cli.output };
+
+    match cli.command {
+        Command::Users(command) => run_users(command, &client, &out),
+        Command::Tokens(command) => run_tokens(command, &client, &out),
+        Command::Configs(command) => run_configs(command, &client, &out),
+        Command::Connections(command) => run_connections(command, &client, &out),
+        Command::Broadcast { message, server_id } => {
+            let response = client.post(
+                "/broadcast",
+                &[],
+                json!({ "message": message, "server_id": server_id }),
+            )?;
+            out.done(
+                &format!("Delivered to {} connections", response["delivered"]),
+                &response,
+            );
+            Ok(())
+        }
+        Command::Status => {
+            out.list(&client.get("/status", &[])?, STATUS_COLUMNS);
+            Ok(())
+        }
+        Command::Audit(args) => {
+            let page = client.get(
+                "/audit",
+                &[
+                    ("actor", args.actor),
+                    ("action", args.action),
+                    ("target", args.target),
+                    ("server_id", args.server_id),
+                    ("limit", args.limit.map(|limit| limit.to_string())),
+                    ("cursor", args.cursor.map(|cursor| cursor.to_string())),
+                ],
+            )?;
+            print_page(&out, &page, "entries", AUDIT_COLUMNS);
+            Ok(())
+        }
+        Command::Webhooks(command) => run_webhooks(command, &client, &out),
+        Command::Admins(command) => run_admins(command, &client, &out),
+        Command::Export {
+            include_token_hashes,
+            file,
+        } => {
+            let document = client.get(
+                "/export",
+                &[(
+                    "include_token_hashes",
+                    Some(include_token_hashes.to_string()),
+                )],
+            )?;
+            let document = serde_json::to_string_pretty(&document)?;
+
+            match file {
+                Some(file) => fs::write(&file, document)
+                    .with_context(|| format!("Failed to write {}", file.display())),
+                None => {
+                    println!("{document}");
+                    Ok(())
+                }
+            }
+        }
+        Command::Import {
+            file,
+            mode,
+            dry_run,
+        } => {
+            let document = read_json(&file)?;
+            let report = client.post(
+                "/import",
+                &[("mode", Some(mode)), ("dry_run", Some(dry_run.to_string()))],
+                document,
+            )?;
+            out.item(&report);
+            Ok(())
+        }
+    }
+}
+
+fn run_users(command: UserCommand, client: &ApiClient, out: &Output) -> anyhow::Result<()> {
+    match command {
+        UserCommand::List {
+            prefix,
+            subscribed_to,
+            limit,
+            mut cursor,
+            all,
+        } => {
+            let mut users = Vec::new();
+
+            loop {
+                let page = client.get(
+                    "/users",
+                    &[
+                        ("server_id_prefix", prefix.clone()),
+                        ("subscribed_to", subscribed_to.clone()),
+                        ("limit", limit.map(|limit| limit.to_string())),
+                        ("cursor", cursor.clone()),
+                    ],
+                )?;
+
+                if !all {
+                    print_page(out, &page, "users", USER_COLUMNS);
+                    return Ok(());
+                }
+
+                users.extend(page["users"].as_array().cloned().unwrap_or_default());
+                match page["next_cursor"].as_str() {
+                    Some(next_cursor) => cursor = Some(next_cursor.to_string()),
+                    None => break,
+                }
+            }
+
+            out.list(&Value::Array(users), USER_COLUMNS);
+        }
+        UserCommand::Get { server_id } => {
+            out.item(&client.get(&format!("/users/{server_id}"), &[])?);
+        }
+        UserCommand::Add {
+            server_id,
+            servers,
+            auth_token,
+            cidrs,
+        } => {
+            let user = client.post(
+                "/users",
+                &[],
+                json!({
+                    "server_id": server_id,
+                    "server_list": servers,
+                    "auth_token": auth_token,
+                    "allowed_cidrs": cidrs,
+                }),
+            )?;
+            out.item(&user);
+        }
+        UserCommand::Update {
+            server_id,
+            servers,
+            auth_token,
+            cidrs,
+            clear_cidrs,
+        } => {
+            let allowed_cidrs = if clear_cidrs {
+                Some(Vec::new())
+            } else {
+                Some(cidrs).filter(|cidrs| !cidrs.is_empty())
+            };
+
+            let user = client.patch(
+                &format!("/users/{server_id}"),
+                json!({
+                    "server_list": servers,
+                    "auth_token": auth_token,
+                    "allowed_cidrs": allowed_cidrs,
+                }),
+            )?;
+            out.item(&user);
+        }
+        UserCommand::Delete { server_id } => {
+            let user = client.delete(&format!("/users/{server_id}"))?;
+            out.done(&format!("Deleted user {server_id}"), &user);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_tokens(command: TokenCommand, client: &ApiClient, out: &Output) -> anyhow::Result<()> {
+    match command {
+        TokenCommand::List { server_id } => {
+            out.list(
+                &client.get("/tokens", &[("server_id", server_id)])?,
+                TOKEN_COLUMNS,
+            );
+        }
+        TokenCommand::Create { server_id, token } => {
+            let Some(label) = token.label else {
+                bail!("A new token needs a --label");
+            };
+            let Some(scopes) = token.scopes else {
+                bail!("A new token needs --scopes");
+            };
+
+            let created = client.post(
+                "/tokens",
+                &[],
+                json!({
+                    "server_id": server_id,
+                    "label": label,
+                    "scopes": scopes,
+                    "client_ids": token.clients,
+                    "expires_at": token.expires_at,
+                }),
+            )?;
+            print_created_token(out, &created);
+        }
+        TokenCommand::Update { id, token } => {
+            let updated = client.patch(&format!("/tokens/{id}"), token_update_body(token))?;
+            out.item(&updated);
+        }
+        TokenCommand::Revoke { id } => {
+            let revoked = client.delete(&format!("/tokens/{id}"))?;
+            out.done(&format!("Revoked token {id}"), &revoked);
+        }
+        TokenCommand::Rotate { id } => {
+            let tokens = client.get("/tokens", &[])?;
+            let Some(old) = tokens
+                .as_array()
+                .and_then(|tokens| tokens.iter().find(|token| token["id"] == id))
+            else {
+                bail!("Token {id} not found");
+            };
+            if !old["revoked_at"].is_null() {
+                bail!("Token {id} is already revoked");
+            }
+
+            // Labels are unique per server, so the old token gives up its label first.
+            let label = old["label"].as_str().unwrap_or_default();
+            client.patch(
+                &format!("/tokens/{id}"),
+                json!({ "label": format!("{label} (rotated {id})") }),
+            )?;
+
+            let created = client.post(
+                "/tokens",
+                &[],
+                json!({
+                    "server_id": old["server_id"],
+                    "label": label,
+                    "scopes": old["scopes"],
+                    "client_ids": old["client_ids"],
+                    "expires_at": old["expires_at"],
+                }),
+            );
+            let created = match created {
+                Ok(created) => created,
+                Err(e) => {
+                    client.patch(&format!("/tokens/{id}"), json!({ "label": label }))?;
+                    return Err(e);
+                }
+            };
+
+            client.delete(&format!("/tokens/{id}")).with_context(|| {
+                format!(
+                    "Created token {}, but failed to revoke {id}",
+                    created["token"]["id"]
+                )
+            })?;
+
+            print_created_token(out, &created);
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the body of a token update. Missing fields are kept, `null` removes the restriction.
+fn token_update_body(token: TokenArgs) -> Value {
+    let mut body = json!({
+        "label": token.label,
+        "scopes": token.scopes,
+    });
+    if token.all_clients {
+        body["client_ids"] = Value::Null;
+    } else if let Some(clients) = token.clients {
+        body["client_ids"] = json!(clients);
+    }
+    if token.no_expiry {
+        body["expires_at"] = Value::Null;
+    } else if let Some(expires_at) = token.expires_at {
+        body["expires_at"] = json!(expires_at);
+    }
+
+    body
+}
+
+fn run_configs(command: ConfigCommand, client: &ApiClient, out: &Output) -> anyhow::Result<()> {
+    match command {
+        ConfigCommand::List { server_id } => {
+            out.list(
+                &client.get("/configs", &[("server_id", server_id)])?,
+                CONFIG_COLUMNS,
+            );
+        }
+        ConfigCommand::Get { identifier } => {
+            out.item(&client.get(&format!("/configs/{identifier}"), &[])?);
+        }
+        ConfigCommand::Set {
+            identifier,
+            subscriptions,
+            templates,
+        } => {
+            let templates = templates
+                .map(|file| read_json(&file))
+                .transpose()?
+                .unwrap_or_else(|| json!({}));
+
+            let config = client.put(
+                &format!("/configs/{identifier}"),
+                json!({ "subscriptions": subscriptions, "templates": templates }),
+            )?;
+            out.item(&config);
+        }
+        ConfigCommand::Delete { identifier } => {
+            let config = client.delete(&format!("/configs/{identifier}"))?;
+            out.done(&format!("Deleted config of {identifier}"), &config);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_connections(
+    command: ConnectionCommand,
+    client: &ApiClient,
+    out: &Output,
+) -> anyhow::Result<()> {
+    match command {
+        ConnectionCommand::List => {
+            out.list(&client.get("/connections", &[])?, CONNECTION_COLUMNS);
+        }
+        ConnectionCommand::Kick { id, reason } => {
+            let connection = client.post(
+                &format!("/connections/{id}/kick"),
+                &[],
+                json!({ "reason": reason }),
+            )?;
+            out.done(&format!("Kicked connection {id}"), &connection);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_webhooks(command: WebhookCommand, client: &ApiClient, out: &Output) -> anyhow::Result<()> {
+    match command {
+        WebhookCommand::List { server_id } => {
+            out.list(
+                &client.get("/webhooks", &[("server_id", server_id)])?,
+                WEBHOOK_COLUMNS,
+            );
+        }
+        WebhookCommand::DeadLetters {
+            server_id,
+            identifier,
+            limit,
+            cursor,
+        } => {
+            let page = client.get(
+                "/webhooks/dead-letters",
+                &[
+                    ("server_id", server_id),
+                    ("identifier", identifier),
+                    ("limit", limit.map(|limit| limit.to_string())),
+                    ("cursor", cursor.map(|cursor| cursor.to_string())),
+                ],
+            )?;
+            print_page(out, &page, "dead_letters", DEAD_LETTER_COLUMNS);
+        }
+        WebhookCommand::Replay { id } => {
+            let result = client.post(
+                &format!("/webhooks/dead-letters/{id}/replay"),
+                &[],
+                json!({}),
+            )?;
+            if result["delivered"].as_bool() != Some(true) {
+                bail!(
+                    "Dead letter {id} was not delivered: {}",
+                    result["error"].as_str().unwrap_or("unknown error")
+                );
+            }
+            out.done(&format!("Delivered dead letter {id}"), &result);
+        }
+        WebhookCommand::Discard { id } => {
+            let dead_letter = client.delete(&format!("/webhooks/dead-letters/{id}"))?;
+            out.done(&format!("Discarded dead letter {id}"), &dead_letter);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_admins(command: AdminCommand, client: &ApiClient, out: &Output) -> anyhow::Result<()> {
+    match command {
+        AdminCommand::List => {
+            out.list(&client.get("/admins", &[])?, ADMIN_COLUMNS);
+        }
+        AdminCommand::Add {
+            name,
+            role,
+            server_id,
+        } => {
+            let admin = client.post(
+                "/admins",
+                &[],
+                json!({ "name": name, "role": role, "server_id": server_id }),
+            )?;
+            out.item(&admin);
+        }
+        AdminCommand::Delete { id } => {
+            let admin = client.delete(&format!("/admins/{id}"))?;
+            out.done(&format!("Deleted admin {id}"), &admin);
+        }
+        AdminCommand::Tokens { id } => {
+            out.list(
+                &client.get(&format!("/admins/{id}/tokens"), &[])?,
+                ADMIN_TOKEN_COLUMNS,
+            );
+        }
+        AdminCommand::CreateToken {
+            id,
+            label,
+            expires_at,
+        } => {
+            let created = client.post(
+                &format!("/admins/{id}/tokens"),
+                &[],
+                json!({ "label": label, "expires_at": expires_at }),
+            )?;
+            print_created_token(out, &created);
+        }
+        AdminCommand::RevokeToken { id, token_id } => {
+            let revoked = client.delete(&format!("/admins/{id}/tokens/{token_id}"))?;
+            out.done(&format!("Revoked token {token_id} of admin {id}"), &revoked);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the `field` rows of a paginated response and how to get the next page.
+fn print_page(out: &Output, page: &Value, field: &str, columns: &[(&str, &str)]) {
+    if out.format == Format::Json {
+        return out.json(page);
+    }
+
+    out.list(&page[field], columns);
+    if let Some(next_cursor) = next_cursor(page) {
+        eprintln!("More results, continue with --cursor {next_cursor}");
+    }
+}
+
+/// Returns the `next_cursor` of a page. Users are paged by server ID, everything else by ID.
+fn next_cursor(page: &Value) -> Option<String> {
+    match &page["next_cursor"] {
+        Value::String(next_cursor) => Some(next_cursor.clone()),
+        Value::Number(next_cursor) => Some(next_cursor.to_string()),
+        _ => None,
+    }
+}
+
+fn print_created_token(out: &Output, created: &Value) {
+    if out.format == Format::Json {
+        return out.json(created);
+    }
+
+    out.item(&created["token"]);
+    println!();
+    println!(
+        "Secret (shown only once): {}",
+        created["secret"].as_str().unwrap_or_default()
+    );
+}
+
+fn read_json(file: &PathBuf) -> anyhow::Result<Value> {
+    let content =
+        fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["chatbridge-admin"].iter().chain(args))
+    }
+
+    fn token_update(args: &[&str]) -> Value {
+        let args = ["tokens", "update", "7"].iter().chain(args).copied();
+        match parse(&args.collect::<Vec<_>>()).unwrap().command {
+            Command::Tokens(TokenCommand::Update { token, .. }) => token_update_body(token),
+            command => panic!("Expected a token update, got {command:?}"),
+        }
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn token_updates_only_send_what_changes() {
+        assert_eq!(
+            token_update(&["--label", "bot"]),
+            json!({ "label": "bot", "scopes": null })
+        );
+        assert_eq!(
+            token_update(&[
+                "--clients",
+                "smp,creative",
+                "--expires-at",
+                "2030-01-01T00:00:00Z"
+            ]),
+            json!({
+                "label": null,
+                "scopes": null,
+                "client_ids": ["smp", "creative"],
+                "expires_at": "2030-01-01T00:00:00Z",
+            })
+        );
+    }
+
+    #[test]
+    fn token_updates_can_remove_restrictions() {
+        let body = token_update(&["--all-clients", "--no-expiry"]);
+
+        assert!(body["client_ids"].is_null());
+        assert!(body["expires_at"].is_null());
+        assert_eq!(body.as_object().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn conflicting_token_arguments_are_rejected() {
+        assert!(parse(&["tokens", "update", "7", "--clients", "smp", "--all-clients"]).is_err());
+        assert!(parse(&["tokens", "update", "7", "--expires-at", "x", "--no-expiry"]).is_err());
+    }
+
+    #[test]
+    fn next_cursor_reads_strings_and_ids() {
+        assert_eq!(
+            next_cursor(&json!({ "next_cursor": "kiwitech" })).as_deref(),
+            Some("kiwitech")
+        );
+        assert_eq!(
+            next_cursor(&json!({ "next_cursor": 42 })).as_deref(),
+            Some("42")
+        );
+        assert_eq!(next_cursor(&json!({ "next_cursor": null })), None);
+    }
+}
@@ -0,0 +1,167 @@
+use clap::ValueEnum;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+/// [Output] prints API responses either as aligned tables or as the JSON they came in.
+pub struct Output {
+    pub format: Format,
+}
+
+impl Output {
+    /// Prints `rows` with one column per `(header, field)`.
+    pub fn list(&self, rows: &Value, columns: &[(&str, &str)]) {
+        if self.format == Format::Json {
+            return self.json(rows);
+        }
+
+        for line in table(rows, columns) {
+            println!("{line}");
+        }
+    }
+
+    /// Prints a single object as `field: value` lines.
+    pub fn item(&self, item: &Value) {
+        if self.format == Format::Json {
+            return self.json(item);
+        }
+
+        let Some(fields) = item.as_object() else {
+            return println!("{}", cell(item));
+        };
+
+        let width = fields.keys().map(String::len).max().unwrap_or_default();
+        for (field, value) in fields {
+            println!("{field:width$}  {}", cell(value));
+        }
+    }
+
+    /// Prints `message` for tables, or `value` for JSON.
+    pub fn done(&self, message: &str, value: &Value) {
+        match self.format {
+            Format::Table => println!("{message}"),
+            Format::Json => self.json(value),
+        }
+    }
+
+    pub fn json(&self, value: &Value) {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+        );
+    }
+}
+
+/// Lays out `rows` as lines of aligned columns, headers first.
+fn table(rows: &Value, columns: &[(&str, &str)]) -> Vec<String> {
+    let rows = rows.as_array().map(Vec::as_slice).unwrap_or_default();
+    let cells = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|(_, field)| cell(&row[field]))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (header, _))| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain([header.len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let headers = columns.iter().map(|(header, _)| header.to_string());
+    [line(headers, &widths)]
+        .into_iter()
+        .chain(cells.into_iter().map(|row| line(row.into_iter(), &widths)))
+        .collect()
+}
+
+fn line(cells: impl Iterator<Item = String>, widths: &[usize]) -> String {
+    cells
+        .zip(widths)
+        .map(|(cell, width)| format!("{cell:width$}"))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_string()
+}
+
+/// Renders a JSON value for a table cell. Identifiers are shown as `server_id:client_id`.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::Array(values) if values.is_empty() => "-".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(cell).collect::<Vec<_>>().join(","),
+        Value::Object(fields) => match (&fields.get("server_id"), &fields.get("client_id")) {
+            (Some(Value::String(server_id)), Some(Value::String(client_id))) => {
+                format!("{server_id}:{client_id}")
+            }
+            _ => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn cells_render_json_values() {
+        assert_eq!(cell(&Value::Null), "-");
+        assert_eq!(cell(&json!([])), "-");
+        assert_eq!(cell(&json!("kiwitech")), "kiwitech");
+        assert_eq!(cell(&json!(["kiwitech", "discord"])), "kiwitech,discord");
+        assert_eq!(cell(&json!(3)), "3");
+        assert_eq!(cell(&json!(true)), "true");
+        assert_eq!(
+            cell(&json!({ "server_id": "kiwitech", "client_id": "smp" })),
+            "kiwitech:smp"
+        );
+        assert_eq!(cell(&json!({ "other": 1 })), r#"{"other":1}"#);
+    }
+
+    #[test]
+    fn table_aligns_columns() {
+        let rows = json!([
+            { "server_id": "kiwitech", "clients": 12 },
+            { "server_id": "ab", "clients": null },
+        ]);
+
+        assert_eq!(
+            table(&rows, &[("SERVER", "server_id"), ("CLIENTS", "clients")]),
+            ["SERVER    CLIENTS", "kiwitech  12", "ab        -",]
+        );
+    }
+
+    #[test]
+    fn table_counts_characters_not_bytes() {
+        let rows = json!([{ "name": "äöü" }, { "name": "abcd" }]);
+
+        assert_eq!(
+            table(&rows, &[("NAME", "name"), ("ID", "id")]),
+            ["NAME  ID", "äöü   -", "abcd  -"]
+        );
+    }
+
+    #[test]
+    fn table_of_nothing_has_only_headers() {
+        assert_eq!(table(&Value::Null, &[("ID", "id")]), ["ID"]);
+    }
+}
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS client_status
+(
+    identifier           TEXT NOT NULL UNIQUE PRIMARY KEY,
+    last_connected_at    TEXT,
+    last_disconnected_at TEXT,
+    disconnect_reason    TEXT
+);
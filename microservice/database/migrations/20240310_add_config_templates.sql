@@ -0,0 +1 @@
+ALTER TABLE configs ADD COLUMN templates TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS tokens
+(
+    id           INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    server_id    TEXT    NOT NULL,
+    label        TEXT    NOT NULL,
+    token_hash   TEXT    NOT NULL,
+    scopes       TEXT    NOT NULL,
+    created_at   TEXT    NOT NULL,
+    last_used_at TEXT,
+    expires_at   TEXT,
+    revoked_at   TEXT,
+    UNIQUE (server_id, label)
+);
+
+CREATE INDEX IF NOT EXISTS tokens_server_id ON tokens (server_id);
@@ -0,0 +1,2 @@
+-- NULL means the token is valid for every client of its server.
+ALTER TABLE tokens ADD COLUMN client_ids TEXT;
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS admins
+(
+    id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name       TEXT    NOT NULL UNIQUE,
+    role       TEXT    NOT NULL,
+    server_id  TEXT,
+    created_at TEXT    NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS admin_tokens
+(
+    id           INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    admin_id     INTEGER NOT NULL REFERENCES admins (id) ON DELETE CASCADE,
+    label        TEXT    NOT NULL,
+    token_hash   TEXT    NOT NULL,
+    created_at   TEXT    NOT NULL,
+    last_used_at TEXT,
+    expires_at   TEXT,
+    revoked_at   TEXT
+);
+
+CREATE INDEX IF NOT EXISTS admin_tokens_admin_id ON admin_tokens (admin_id);
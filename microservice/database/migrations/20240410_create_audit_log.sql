@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS audit_log
+(
+    id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created_at TEXT    NOT NULL,
+    actor      TEXT    NOT NULL,
+    action     TEXT    NOT NULL,
+    target     TEXT    NOT NULL,
+    server_id  TEXT,
+    before     TEXT,
+    after      TEXT,
+    source_ip  TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_server_id ON audit_log (server_id);
+
+-- The audit log is append-only.
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+    BEFORE UPDATE
+    ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+    BEFORE DELETE
+    ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
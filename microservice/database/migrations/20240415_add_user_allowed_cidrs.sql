@@ -0,0 +1,2 @@
+-- JSON array of CIDR ranges the user's tokens may be used from. NULL allows every address.
+ALTER TABLE users ADD COLUMN allowed_cidrs TEXT;
@@ -0,0 +1,2 @@
+-- Speeds up counting and listing the clients of a server.
+CREATE INDEX IF NOT EXISTS configs_server_id ON configs (server_id);
@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS webhooks
+(
+    identifier TEXT NOT NULL PRIMARY KEY REFERENCES configs (identifier) ON DELETE CASCADE,
+    server_id  TEXT NOT NULL,
+    url        TEXT NOT NULL,
+    secret     TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_server_id ON webhooks (server_id);
+
+-- Deliveries that failed on every attempt. They outlive their webhook so that nothing is lost.
+CREATE TABLE IF NOT EXISTS webhook_dead_letters
+(
+    id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    identifier TEXT    NOT NULL,
+    server_id  TEXT    NOT NULL,
+    url        TEXT    NOT NULL,
+    payload    TEXT    NOT NULL,
+    attempts   INTEGER NOT NULL,
+    last_error TEXT    NOT NULL,
+    created_at TEXT    NOT NULL,
+    failed_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_dead_letters_server_id ON webhook_dead_letters (server_id);
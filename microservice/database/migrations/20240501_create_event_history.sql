@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS event_history
+(
+    seq        INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created_at TEXT    NOT NULL,
+    source     TEXT    NOT NULL,
+    server_id  TEXT    NOT NULL,
+    kind       TEXT    NOT NULL,
+    event      TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS event_history_created_at ON event_history (created_at);
+CREATE INDEX IF NOT EXISTS event_history_server_id ON event_history (server_id, seq);
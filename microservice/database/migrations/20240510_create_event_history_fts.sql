@@ -0,0 +1,24 @@
+-- Full-text index over what players said, kept in sync with event_history by the triggers below.
+CREATE VIRTUAL TABLE IF NOT EXISTS event_history_fts USING fts5(message);
+
+CREATE TRIGGER IF NOT EXISTS event_history_fts_insert
+    AFTER INSERT
+    ON event_history
+    WHEN json_extract(new.event, '$.message') IS NOT NULL
+BEGIN
+    INSERT INTO event_history_fts (rowid, message) VALUES (new.seq, json_extract(new.event, '$.message'));
+END;
+
+CREATE TRIGGER IF NOT EXISTS event_history_fts_delete
+    AFTER DELETE
+    ON event_history
+BEGIN
+    DELETE FROM event_history_fts WHERE rowid = old.seq;
+END;
+
+INSERT INTO event_history_fts (rowid, message)
+SELECT seq, json_extract(event, '$.message')
+FROM event_history
+WHERE json_extract(event, '$.message') IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS event_history_player ON event_history (server_id, json_extract(event, '$.player') COLLATE NOCASE);
@@ -0,0 +1,16 @@
+-- Deliveries that were not made yet, so that retries survive a restart. They go away with their
+-- webhook, and are moved to webhook_dead_letters once every attempt failed.
+CREATE TABLE IF NOT EXISTS webhook_deliveries
+(
+    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    identifier      TEXT    NOT NULL REFERENCES webhooks (identifier) ON DELETE CASCADE,
+    kind            TEXT    NOT NULL,
+    payload         TEXT    NOT NULL,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    last_error      TEXT,
+    created_at      TEXT    NOT NULL,
+    next_attempt_at TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_identifier ON webhook_deliveries (identifier);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_next_attempt_at ON webhook_deliveries (next_attempt_at);
@@ -0,0 +1,160 @@
+//! Command line mode to move users and configs between hosts without starting the server:
+//!
+//! ```text
+//! chatbridge-websockets export [--include-token-hashes] [FILE]
+//! chatbridge-websockets import [--mode merge|replace] [--dry-run] FILE
+//! ```
+//!
+//! Only `DATABASE_URL` needs to be set. Exports go to stdout without a `FILE`, import reports
+//! always do.
+
+use std::env::var;
+use std::net::{IpAddr, Ipv4Addr};
+
+use anyhow::Context;
+use serde_json::json;
+use sqlx::sqlite::SqlitePoolOptions;
+
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::transfer::{ExportDocument, ImportMode, ImportOptions, TransferModelController};
+
+/// Actor of audit log entries written from the command line.
+const CLI_ACTOR: &str = "cli";
+
+#[derive(Debug)]
+pub enum Command {
+    Export {
+        include_token_hashes: bool,
+        path: Option<String>,
+    },
+    Import {
+        options: ImportOptions,
+        path: String,
+    },
+}
+
+impl Command {
+    /// Parses the arguments after the program name. Returns `None` without any, which starts the
+    /// server.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let Some(command) = args.next() else {
+            return Ok(None);
+        };
+
+        match command.as_str() {
+            "export" => {
+                let mut include_token_hashes = false;
+                let mut path = None;
+
+                for arg in args {
+                    match arg.as_str() {
+                        "--include-token-hashes" => include_token_hashes = true,
+                        _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+                        _ => return Err(anyhow::anyhow!("Unexpected argument: {arg}")),
+                    }
+                }
+
+                Ok(Some(Command::Export {
+                    include_token_hashes,
+                    path,
+                }))
+            }
+            "import" => {
+                let mut options = ImportOptions::default();
+                let mut path = None;
+
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--dry-run" => options.dry_run = true,
+                        "--mode" => {
+                            options.mode = match args.next().as_deref() {
+                                Some("merge") => ImportMode::Merge,
+                                Some("replace") => ImportMode::Replace,
+                                _ => {
+                                    return Err(anyhow::anyhow!(
+                                        "`--mode` takes `merge` or `replace`"
+                                    ))
+                                }
+                            }
+                        }
+                        _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+                        _ => return Err(anyhow::anyhow!("Unexpected argument: {arg}")),
+                    }
+                }
+
+                let path = path.ok_or_else(|| anyhow::anyhow!("`import` needs a FILE"))?;
+
+                Ok(Some(Command::Import { options, path }))
+            }
+            _ => Err(anyhow::anyhow!(
+                "Unknown command: {command}, expected `export` or `import`"
+            )),
+        }
+    }
+}
+
+pub async fn run(command: Command) -> anyhow::Result<()> {
+    let database_url = var("DATABASE_URL").context("`DATABASE_URL` is not set")?;
+    let db_pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await?;
+
+    match command {
+        Command::Export {
+            include_token_hashes,
+            path,
+        } => {
+            let document = TransferModelController::export(include_token_hashes, &db_pool).await?;
+            let json = serde_json::to_string_pretty(&document)?;
+
+            match &path {
+                Some(path) => std::fs::write(path, json + "\n")
+                    .with_context(|| format!("Failed to write {path}"))?,
+                None => println!("{json}"),
+            }
+
+            AuditModelController::record(
+                cli_entry("export").after(json!({
+                    "include_token_hashes": include_token_hashes,
+                    "users": document.users.len(),
+                    "configs": document.configs.len(),
+                    "webhooks": document.webhooks.len(),
+                    "tokens": document.tokens.as_ref().map(Vec::len),
+                })),
+                &db_pool,
+            )
+            .await;
+        }
+        Command::Import { options, path } => {
+            let document: ExportDocument = serde_json::from_str(
+                &std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {path}"))?,
+            )
+            .with_context(|| format!("{path} is not an export document"))?;
+
+            let report = TransferModelController::import(document, &options, &db_pool).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+
+            if report.applied {
+                AuditModelController::record(cli_entry("import").after(&report), &db_pool).await;
+            }
+
+            if !report.conflicts.is_empty() && !report.dry_run {
+                return Err(anyhow::anyhow!(
+                    "Import has {} conflicts, nothing was applied",
+                    report.conflicts.len()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Audit log entries from the command line have no source address.
+fn cli_entry(action: &'static str) -> NewAuditEntry {
+    let mut entry = NewAuditEntry::new(CLI_ACTOR, action, "*", IpAddr::V4(Ipv4Addr::LOCALHOST));
+    entry.source_ip = None;
+    entry
+}
@@ -8,8 +8,34 @@ pub struct Config {
     pub SERVER_PORT: u16,
     pub DATABASE_URL: String,
     pub DATABASE_MAX_CONNECTIONS: u8,
-    pub ADMIN_TOKEN: String,
+    /// Token of a bootstrap superadmin, for setting up the admin accounts in the database.
+    pub ADMIN_TOKEN: Option<String>,
+    /// Global salt of the legacy SHA-256 token hashes. New hashes use Argon2id with per-record salts.
     pub SALT: String,
+    /// PEM certificate chain. Together with `TLS_KEY_PATH` it turns on TLS.
+    pub TLS_CERT_PATH: Option<String>,
+    /// PEM private key of the certificate.
+    pub TLS_KEY_PATH: Option<String>,
+    /// PEM CA certificates that client certificates are verified against.
+    pub TLS_CLIENT_CA_PATH: Option<String>,
+    /// Reject TLS connections without a client certificate signed by `TLS_CLIENT_CA_PATH`.
+    pub TLS_CLIENT_AUTH_REQUIRED: bool,
+    /// Browser origins that may call the REST routes, or `*` for any. Empty turns CORS off.
+    pub CORS_ALLOWED_ORIGINS: Vec<String>,
+    /// How often a webhook delivery is attempted before it becomes a dead letter.
+    pub WEBHOOK_MAX_ATTEMPTS: u32,
+    /// Wait after the first failed webhook delivery. It doubles with every further failure.
+    pub WEBHOOK_RETRY_DELAY_MS: u64,
+    /// How long a webhook endpoint may take to answer.
+    pub WEBHOOK_TIMEOUT_MS: u64,
+    /// Deliveries that may wait for a webhook at once. Further events become dead letters right
+    /// away, so an endpoint that is down for long cannot fill the database.
+    pub WEBHOOK_MAX_PENDING: u32,
+    /// Allows webhooks on loopback, private and link-local addresses, e.g. for a bot on the same
+    /// host. Off by default, since anyone who can set a webhook could reach internal services.
+    pub WEBHOOK_ALLOW_PRIVATE_TARGETS: bool,
+    /// How long published events are kept for resuming streams. `0` keeps them forever.
+    pub HISTORY_RETENTION_DAYS: u32,
 }
 
 impl Config {
@@ -21,8 +47,55 @@ impl Config {
         let DB_URL = var("DATABASE_URL").expect("`DB_URL` is not set");
         let DB_MAX_CONNECTIONS =
             var("DATABASE_MAX_CONNECTIONS").expect("`DB_MAX_CONNECTIONS` is not set");
-        let ADMIN_TOKEN = var("ADMIN_TOKEN").expect("`ADMIN_TOKEN` is not set");
+        let ADMIN_TOKEN = var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
         let SALT = var("SALT").expect("`SALT` is not set");
+        let TLS_CERT_PATH = var("TLS_CERT_PATH").ok().filter(|path| !path.is_empty());
+        let TLS_KEY_PATH = var("TLS_KEY_PATH").ok().filter(|path| !path.is_empty());
+        let TLS_CLIENT_CA_PATH = var("TLS_CLIENT_CA_PATH")
+            .ok()
+            .filter(|path| !path.is_empty());
+        let TLS_CLIENT_AUTH_REQUIRED = var("TLS_CLIENT_AUTH_REQUIRED")
+            .map(|required| required.parse::<bool>())
+            .unwrap_or(Ok(false))?;
+        let CORS_ALLOWED_ORIGINS = var("CORS_ALLOWED_ORIGINS")
+            .map(|origins| Config::parse_list(&origins))
+            .unwrap_or_default();
+        let WEBHOOK_MAX_ATTEMPTS = var("WEBHOOK_MAX_ATTEMPTS")
+            .map(|attempts| attempts.parse::<u32>())
+            .unwrap_or(Ok(5))?;
+        let WEBHOOK_RETRY_DELAY_MS = var("WEBHOOK_RETRY_DELAY_MS")
+            .map(|delay| delay.parse::<u64>())
+            .unwrap_or(Ok(1000))?;
+        let WEBHOOK_TIMEOUT_MS = var("WEBHOOK_TIMEOUT_MS")
+            .map(|timeout| timeout.parse::<u64>())
+            .unwrap_or(Ok(10_000))?;
+        let WEBHOOK_MAX_PENDING = var("WEBHOOK_MAX_PENDING")
+            .map(|pending| pending.parse::<u32>())
+            .unwrap_or(Ok(1000))?;
+        let WEBHOOK_ALLOW_PRIVATE_TARGETS = var("WEBHOOK_ALLOW_PRIVATE_TARGETS")
+            .map(|allowed| allowed.parse::<bool>())
+            .unwrap_or(Ok(false))?;
+        let HISTORY_RETENTION_DAYS = var("HISTORY_RETENTION_DAYS")
+            .map(|days| days.parse::<u32>())
+            .unwrap_or(Ok(30))?;
+
+        if TLS_CERT_PATH.is_some() != TLS_KEY_PATH.is_some() {
+            return Err(anyhow::anyhow!(
+                "`TLS_CERT_PATH` and `TLS_KEY_PATH` must be set together"
+            ));
+        }
+
+        if TLS_CLIENT_CA_PATH.is_some() && TLS_CERT_PATH.is_none() {
+            return Err(anyhow::anyhow!(
+                "`TLS_CLIENT_CA_PATH` needs `TLS_CERT_PATH` and `TLS_KEY_PATH`"
+            ));
+        }
+
+        if TLS_CLIENT_AUTH_REQUIRED && TLS_CLIENT_CA_PATH.is_none() {
+            return Err(anyhow::anyhow!(
+                "`TLS_CLIENT_AUTH_REQUIRED` needs `TLS_CLIENT_CA_PATH`"
+            ));
+        }
 
         let SERVER_IP = Config::parse_ip(SERVER_IP).expect("IP Address wrong format");
         let SERVER_PORT = SERVER_PORT.parse::<u16>()?;
@@ -36,10 +109,77 @@ impl Config {
             DATABASE_MAX_CONNECTIONS: DB_MAX_CONNECTIONS,
             ADMIN_TOKEN,
             SALT,
+            TLS_CERT_PATH,
+            TLS_KEY_PATH,
+            TLS_CLIENT_CA_PATH,
+            TLS_CLIENT_AUTH_REQUIRED,
+            CORS_ALLOWED_ORIGINS,
+            WEBHOOK_MAX_ATTEMPTS,
+            WEBHOOK_RETRY_DELAY_MS,
+            WEBHOOK_TIMEOUT_MS,
+            WEBHOOK_MAX_PENDING,
+            WEBHOOK_ALLOW_PRIVATE_TARGETS,
+            HISTORY_RETENTION_DAYS,
         })
     }
 
+    /// Returns true if the listener should use TLS.
+    pub fn tls_enabled(&self) -> bool {
+        self.TLS_CERT_PATH.is_some() && self.TLS_KEY_PATH.is_some()
+    }
+
     fn parse_ip(ip_string: String) -> anyhow::Result<Ipv4Addr> {
         Ok(Ipv4Addr::from_str(&ip_string)?)
     }
+
+    /// Splits a comma separated list, dropping blank entries.
+    fn parse_list(list: &str) -> Vec<String> {
+        list.split(',')
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// A config for tests, with the defaults of [Config::load_from_env] and no TLS, CORS or
+    /// bootstrap admin.
+    pub fn for_tests() -> Self {
+        Self {
+            RUST_LOG: "info".to_string(),
+            SERVER_IP: Ipv4Addr::LOCALHOST,
+            SERVER_PORT: 0,
+            DATABASE_URL: "sqlite::memory:".to_string(),
+            DATABASE_MAX_CONNECTIONS: 1,
+            ADMIN_TOKEN: None,
+            SALT: "salt".to_string(),
+            TLS_CERT_PATH: None,
+            TLS_KEY_PATH: None,
+            TLS_CLIENT_CA_PATH: None,
+            TLS_CLIENT_AUTH_REQUIRED: false,
+            CORS_ALLOWED_ORIGINS: Vec::new(),
+            WEBHOOK_MAX_ATTEMPTS: 5,
+            WEBHOOK_RETRY_DELAY_MS: 1000,
+            WEBHOOK_TIMEOUT_MS: 10_000,
+            WEBHOOK_MAX_PENDING: 1000,
+            WEBHOOK_ALLOW_PRIVATE_TARGETS: false,
+            HISTORY_RETENTION_DAYS: 30,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_comma_separated_lists() {
+        assert_eq!(
+            Config::parse_list(" https://a.example, https://b.example ,,"),
+            ["https://a.example", "https://b.example"]
+        );
+        assert!(Config::parse_list(" , ").is_empty());
+        assert!(Config::parse_list("").is_empty());
+    }
 }
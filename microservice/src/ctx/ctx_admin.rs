@@ -0,0 +1,198 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// [AdminRole] decides what an admin may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// May do everything, including managing other admins.
+    Superadmin,
+    /// May view everything, kick connections and broadcast notices.
+    Operator,
+    /// May view and manage a single server.
+    ServerAdmin,
+}
+
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminRole::Superadmin => "superadmin",
+            AdminRole::Operator => "operator",
+            AdminRole::ServerAdmin => "server_admin",
+        }
+    }
+}
+
+impl FromStr for AdminRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "superadmin" => Ok(AdminRole::Superadmin),
+            "operator" => Ok(AdminRole::Operator),
+            "server_admin" => Ok(AdminRole::ServerAdmin),
+            _ => Err(anyhow::anyhow!("Invalid admin role: {s}")),
+        }
+    }
+}
+
+/// [AdminPermission] is what a route asks of an admin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminPermission {
+    /// Read users, configs, tokens and connections.
+    View,
+    /// Kick live connections.
+    Kick,
+    /// Send notices to live connections.
+    Broadcast,
+    /// Create, change and delete users, configs and tokens.
+    Manage,
+    /// Create and delete admins.
+    ManageAdmins,
+}
+
+/// [AdminCtx] represents the admin a request was authenticated as.
+#[derive(Debug, Clone)]
+pub struct AdminCtx {
+    /// The id of the admin, or `None` for the bootstrap `ADMIN_TOKEN`.
+    pub admin_id: Option<i64>,
+    pub name: String,
+    pub role: AdminRole,
+    /// The server a [AdminRole::ServerAdmin] is limited to.
+    pub server_id: Option<String>,
+}
+
+impl AdminCtx {
+    pub fn new(
+        admin_id: Option<i64>,
+        name: impl Into<String>,
+        role: AdminRole,
+        server_id: Option<String>,
+    ) -> Self {
+        Self {
+            admin_id,
+            name: name.into(),
+            role,
+            server_id,
+        }
+    }
+
+    /// The superadmin behind the `ADMIN_TOKEN` from the environment.
+    pub fn bootstrap() -> Self {
+        Self::new(None, "bootstrap", AdminRole::Superadmin, None)
+    }
+
+    /// Returns true if the admin may do `permission` on `server_id`.
+    ///
+    /// Pass `None` as `server_id` for actions that are not about a single server. Server admins
+    /// are never allowed those.
+    pub fn can(&self, permission: AdminPermission, server_id: Option<&str>) -> bool {
+        match self.role {
+            AdminRole::Superadmin => true,
+            AdminRole::Operator => {
+                matches!(
+                    permission,
+                    AdminPermission::View | AdminPermission::Kick | AdminPermission::Broadcast
+                )
+            }
+            AdminRole::ServerAdmin => {
+                permission != AdminPermission::ManageAdmins
+                    && server_id.is_some()
+                    && server_id == self.server_id.as_deref()
+            }
+        }
+    }
+
+    /// Returns true if the admin may add `entry` to the `server_list` of a user, which lets the
+    /// user's clients subscribe to it.
+    ///
+    /// Server admins may only link their own server, otherwise they could read the chat of any
+    /// other server.
+    pub fn can_link(&self, entry: &str) -> bool {
+        match self.role {
+            AdminRole::Superadmin => true,
+            AdminRole::Operator => false,
+            AdminRole::ServerAdmin => {
+                let server_id = entry
+                    .split_once(':')
+                    .map_or(entry, |(server_id, _)| server_id);
+                Some(server_id) == self.server_id.as_deref()
+            }
+        }
+    }
+
+    /// Returns how this admin shows up in the audit log, e.g. `admin:alice`.
+    pub fn actor(&self) -> String {
+        format!("admin:{}", self.name)
+    }
+
+    /// Returns the server this admin is limited to, if any.
+    ///
+    /// Listings use it to only show what the admin may see.
+    pub fn scope(&self) -> Option<&str> {
+        match self.role {
+            AdminRole::ServerAdmin => self.server_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_admin() -> AdminCtx {
+        AdminCtx::new(
+            Some(1),
+            "alice",
+            AdminRole::ServerAdmin,
+            Some("kiwitech".to_string()),
+        )
+    }
+
+    #[test]
+    fn superadmin_can_do_everything() {
+        let admin = AdminCtx::bootstrap();
+
+        assert!(admin.can(AdminPermission::ManageAdmins, None));
+        assert!(admin.can(AdminPermission::Manage, Some("kiwitech")));
+        assert!(admin.can_link("other:smp"));
+    }
+
+    #[test]
+    fn operator_can_only_view_kick_and_broadcast() {
+        let admin = AdminCtx::new(Some(2), "bob", AdminRole::Operator, None);
+
+        assert!(admin.can(AdminPermission::View, None));
+        assert!(admin.can(AdminPermission::Kick, Some("kiwitech")));
+        assert!(admin.can(AdminPermission::Broadcast, None));
+        assert!(!admin.can(AdminPermission::Manage, Some("kiwitech")));
+        assert!(!admin.can(AdminPermission::ManageAdmins, None));
+        assert!(!admin.can_link("kiwitech"));
+    }
+
+    #[test]
+    fn server_admin_is_limited_to_its_server() {
+        let admin = server_admin();
+
+        assert!(admin.can(AdminPermission::Manage, Some("kiwitech")));
+        assert!(admin.can(AdminPermission::View, Some("kiwitech")));
+        assert!(!admin.can(AdminPermission::Manage, Some("other")));
+        assert!(!admin.can(AdminPermission::View, None));
+        assert!(!admin.can(AdminPermission::ManageAdmins, Some("kiwitech")));
+        assert_eq!(admin.scope(), Some("kiwitech"));
+    }
+
+    #[test]
+    fn server_admin_can_only_link_its_server() {
+        let admin = server_admin();
+
+        assert!(admin.can_link("kiwitech"));
+        assert!(admin.can_link("kiwitech:smp"));
+        assert!(!admin.can_link("other"));
+        assert!(!admin.can_link("other:smp"));
+        assert!(!admin.can_link("kiwitech2"));
+    }
+}
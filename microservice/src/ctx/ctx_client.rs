@@ -3,9 +3,11 @@ use std::str::FromStr;
 use axum::async_trait;
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
-use axum::http::StatusCode;
 use serde::Deserialize;
 use serde_with::serde_derive::Serialize;
+use utoipa::ToSchema;
+
+use crate::error::Error;
 
 /// ClientID represents a unique identifier for a client.
 ///
@@ -14,7 +16,7 @@ use serde_with::serde_derive::Serialize;
 /// The server ID is used to identify the server that the client is connected to. The client ID is used to identify the client.
 ///
 /// The format of the client ID is `server_id:client_id`.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
 pub struct Identifier {
     pub server_id: String,
     pub client_id: String,
@@ -44,6 +46,13 @@ impl Identifier {
     pub fn client_id(&self) -> &str {
         &self.client_id
     }
+
+    /// Returns true if a subscription entry covers this [Identifier].
+    ///
+    /// A subscription either names a whole server (`kiwitech`) or a single client (`kiwitech:smp`).
+    pub fn matches_subscription(&self, subscription: &str) -> bool {
+        subscription == self.server_id || *subscription == self.to_string()
+    }
 }
 
 // impl From<(String, String)> for Identifier {
@@ -121,24 +130,31 @@ impl ClientCtx {
     pub fn identifier(&self) -> &Identifier {
         &self.identifier
     }
+
+    /// Returns how this client shows up in the audit log, e.g. `client:kiwitech:smp`.
+    pub fn actor(&self) -> String {
+        format!("client:{}", self.identifier)
+    }
 }
 
 #[async_trait]
 impl<S: Send + Sync> FromRequestParts<S> for ClientCtx {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         let client_id_str = parts
             .headers
             .get("X-Client-ID")
             .and_then(|header| header.to_str().ok())
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing X-Client-ID header"))?;
+            .ok_or_else(|| Error::Unauthorized("Missing X-Client-ID header".to_string()))?;
 
         let client_id = Identifier::from_str(client_id_str);
 
         match client_id {
             Ok(client_id) => Ok(Self::new(client_id)),
-            Err(_) => Err((StatusCode::UNAUTHORIZED, "Invalid X-Client-ID header")),
+            Err(_) => Err(Error::Unauthorized(
+                "Invalid X-Client-ID header".to_string(),
+            )),
         }
     }
 }
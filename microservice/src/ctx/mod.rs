@@ -1 +1,2 @@
+pub mod ctx_admin;
 pub mod ctx_client;
@@ -1,8 +1,89 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use sha2::Digest;
 use sqlx::pool::PoolConnection;
 use sqlx::{Sqlite, SqlitePool};
+use subtle::ConstantTimeEq;
 
-pub fn hash_password(password: String, salt: &str) -> String {
+/// Hashes an auth token with Argon2id and a fresh random salt.
+///
+/// The result is a PHC string (`$argon2id$v=19$...`) that carries its own salt and parameters.
+/// Argon2 is slow on purpose, so it runs on the blocking thread pool.
+pub async fn hash_token(token: &str) -> anyhow::Result<String> {
+    let token = token.to_string();
+
+    tokio::task::spawn_blocking(move || hash_token_blocking(&token)).await?
+}
+
+fn hash_token_blocking(token: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(token.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to hash token: {e}"))
+}
+
+/// Generates a random secret of 32 bytes, hex encoded.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Outcome of [verify_token].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenVerification {
+    Valid,
+    /// The token matches a legacy SHA-256 hash and should be rehashed with [hash_token].
+    ValidLegacy,
+    Invalid,
+}
+
+/// Checks `token` against a stored hash in constant time.
+///
+/// Hashes that are not PHC strings are legacy SHA-256 hashes salted with the global `legacy_salt`.
+/// Like [hash_token], this runs on the blocking thread pool.
+pub async fn verify_token(token: &str, stored_hash: &str, legacy_salt: &str) -> TokenVerification {
+    let (token, stored_hash, legacy_salt) = (
+        token.to_string(),
+        stored_hash.to_string(),
+        legacy_salt.to_string(),
+    );
+
+    tokio::task::spawn_blocking(move || verify_token_blocking(&token, &stored_hash, &legacy_salt))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("Failed to verify token: {e}");
+            TokenVerification::Invalid
+        })
+}
+
+fn verify_token_blocking(token: &str, stored_hash: &str, legacy_salt: &str) -> TokenVerification {
+    if stored_hash.starts_with('$') {
+        let Ok(parsed_hash) = PasswordHash::new(stored_hash) else {
+            tracing::error!("Stored token hash is not a valid PHC string");
+            return TokenVerification::Invalid;
+        };
+
+        return match Argon2::default().verify_password(token.as_bytes(), &parsed_hash) {
+            Ok(()) => TokenVerification::Valid,
+            Err(_) => TokenVerification::Invalid,
+        };
+    }
+
+    let legacy_hash = hash_password_legacy(token, legacy_salt);
+
+    if bool::from(legacy_hash.as_bytes().ct_eq(stored_hash.as_bytes())) {
+        TokenVerification::ValidLegacy
+    } else {
+        TokenVerification::Invalid
+    }
+}
+
+/// The SHA-256 scheme tokens were hashed with before Argon2id. Only used to verify old hashes.
+fn hash_password_legacy(password: &str, salt: &str) -> String {
     let mut hasher = sha2::Sha256::new();
     hasher.update(password);
     hasher.update(salt.as_bytes());
@@ -17,3 +98,79 @@ pub async fn acquire_connection(db_pool: &SqlitePool) -> anyhow::Result<PoolConn
         anyhow::anyhow!(error_msg)
     })
 }
+
+/// An in-memory database with every migration applied, for tests.
+///
+/// The pool keeps its single connection open, since the database is gone once it closes.
+#[cfg(test)]
+pub async fn test_pool() -> SqlitePool {
+    let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    sqlx::migrate!("./database/migrations")
+        .run(&db_pool)
+        .await
+        .unwrap();
+
+    db_pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verify_token_accepts_argon2_hash() {
+        let hash = hash_token("secret").await.unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(
+            verify_token("secret", &hash, "salt").await,
+            TokenVerification::Valid
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_token_rejects_wrong_token() {
+        let hash = hash_token("secret").await.unwrap();
+
+        assert_eq!(
+            verify_token("guess", &hash, "salt").await,
+            TokenVerification::Invalid
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_token_flags_legacy_hash_for_rehash() {
+        let hash = hash_password_legacy("secret", "salt");
+
+        assert_eq!(
+            verify_token("secret", &hash, "salt").await,
+            TokenVerification::ValidLegacy
+        );
+        assert_eq!(
+            verify_token("secret", &hash, "other").await,
+            TokenVerification::Invalid
+        );
+        assert_eq!(
+            verify_token("guess", &hash, "salt").await,
+            TokenVerification::Invalid
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_token_rejects_garbage_hash() {
+        for hash in ["$argon2id$garbage", "$", "", "not a hash"] {
+            assert_eq!(
+                verify_token("secret", hash, "salt").await,
+                TokenVerification::Invalid,
+                "{hash:?}"
+            );
+        }
+    }
+}
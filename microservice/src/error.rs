@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use crate::message::{RpcError, RpcErrorCode};
+
+/// Media type of RFC 7807 problem details.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// [Error] is what model controllers, middlewares and routes fail with.
+///
+/// Every variant maps to a status code and is sent as an RFC 7807 problem, so clients never have
+/// to compare error messages.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("Too many failed authentication attempts, retry in {}s", .0.as_secs())]
+    TooManyRequests(Duration),
+    /// A request to another client failed, see [RpcErrorCode] for the reasons.
+    #[error("{}", .0.message)]
+    Rpc(RpcError),
+    /// Anything the caller cannot do something about. The details are only logged.
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::Rpc(error) => match error.code {
+                RpcErrorCode::TargetOffline => StatusCode::NOT_FOUND,
+                RpcErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                RpcErrorCode::Forbidden => StatusCode::FORBIDDEN,
+                RpcErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+                RpcErrorCode::Remote => StatusCode::BAD_GATEWAY,
+            },
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        Error::Internal(e.into())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Internal(e.into())
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status_code = self.status_code();
+        let mut extensions = Map::new();
+
+        let detail = match &self {
+            Error::Internal(e) => {
+                tracing::error!("Internal error: {e:#}");
+                None
+            }
+            Error::Rpc(error) => {
+                extensions.insert("code".to_string(), serde_json::json!(error.code));
+                Some(self.to_string())
+            }
+            _ => Some(self.to_string()),
+        };
+
+        let mut response = problem_response(status_code, detail, extensions);
+
+        match &self {
+            Error::Unauthorized(_) => {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            Error::TooManyRequests(retry_after) => {
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(retry_after.as_secs().max(1)),
+                );
+            }
+            _ => {}
+        }
+
+        response
+    }
+}
+
+/// [Problem] is an RFC 7807 problem details object.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Extension members that depend on the problem, like `code` of a failed request.
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub extensions: Map<String, Value>,
+}
+
+/// Builds an `application/problem+json` response for `status_code`.
+pub fn problem_response(
+    status_code: StatusCode,
+    detail: Option<String>,
+    extensions: Map<String, Value>,
+) -> Response {
+    let problem = Problem {
+        problem_type: "about:blank",
+        title: status_code
+            .canonical_reason()
+            .unwrap_or("Unknown Error")
+            .to_string(),
+        status: status_code.as_u16(),
+        detail,
+        extensions,
+    };
+
+    let mut response = (status_code, Json(problem)).into_response();
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+
+    response
+}
+
+/// Adds context to database errors and turns unique constraint violations into
+/// [Error::Conflict].
+pub trait DbContext<T> {
+    fn db_context(self, message: &'static str) -> Result<T>;
+}
+
+impl<T> DbContext<T> for std::result::Result<T, sqlx::Error> {
+    fn db_context(self, message: &'static str) -> Result<T> {
+        self.map_err(|e| match e {
+            sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+                Error::Conflict(format!("{message}: it already exists"))
+            }
+            e => Error::Internal(anyhow::Error::new(e).context(message)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+    use crate::database_utils::test_pool;
+
+    async fn problem(error: Error) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status_code = response.status();
+
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status_code, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn maps_errors_to_problems_with_their_status() {
+        for (error, status_code) in [
+            (Error::BadRequest("bad".into()), StatusCode::BAD_REQUEST),
+            (Error::Forbidden("no".into()), StatusCode::FORBIDDEN),
+            (Error::NotFound("gone".into()), StatusCode::NOT_FOUND),
+            (Error::Conflict("taken".into()), StatusCode::CONFLICT),
+        ] {
+            let detail = error.to_string();
+            let (status, problem) = problem(error).await;
+
+            assert_eq!(status, status_code);
+            assert_eq!(problem["type"], "about:blank");
+            assert_eq!(problem["title"], status_code.canonical_reason().unwrap());
+            assert_eq!(problem["status"], status_code.as_u16());
+            assert_eq!(problem["detail"], detail);
+        }
+    }
+
+    #[tokio::test]
+    async fn internal_errors_hide_their_details() {
+        let (status, problem) = problem(anyhow::anyhow!("secret database path").into()).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(problem.get("detail").is_none());
+    }
+
+    #[tokio::test]
+    async fn rpc_errors_carry_their_code() {
+        for (code, status_code) in [
+            (RpcErrorCode::TargetOffline, StatusCode::NOT_FOUND),
+            (RpcErrorCode::Timeout, StatusCode::GATEWAY_TIMEOUT),
+            (RpcErrorCode::Forbidden, StatusCode::FORBIDDEN),
+            (RpcErrorCode::BadRequest, StatusCode::BAD_REQUEST),
+            (RpcErrorCode::Remote, StatusCode::BAD_GATEWAY),
+        ] {
+            let (status, problem) = problem(Error::Rpc(RpcError::new(code, "failed"))).await;
+
+            assert_eq!(status, status_code);
+            assert_eq!(problem["code"], serde_json::json!(code));
+            assert_eq!(problem["detail"], "failed");
+        }
+    }
+
+    #[test]
+    fn auth_errors_tell_how_to_retry() {
+        let response = Error::Unauthorized("Invalid token".into()).into_response();
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+        let response = Error::TooManyRequests(Duration::from_secs(30)).into_response();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+
+        // A lockout that is about to end still asks for at least a second.
+        let response = Error::TooManyRequests(Duration::from_millis(200)).into_response();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+
+    #[tokio::test]
+    async fn unique_violations_are_conflicts() {
+        let db_pool = test_pool().await;
+        let insert = || {
+            sqlx::query("INSERT INTO client_status (identifier) VALUES ('kiwitech:smp');")
+                .execute(&db_pool)
+        };
+
+        insert().await.db_context("Failed to add").unwrap();
+        assert!(matches!(
+            insert().await.db_context("Failed to add"),
+            Err(Error::Conflict(message)) if message == "Failed to add: it already exists"
+        ));
+
+        let other = sqlx::query("SELECT * FROM nonexistent;")
+            .execute(&db_pool)
+            .await
+            .db_context("Failed to select");
+        assert!(matches!(other, Err(Error::Internal(_))));
+    }
+}
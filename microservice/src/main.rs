@@ -2,29 +2,71 @@
 #![allow(unused, dead_code)] // for development
 
 use std::net::{IpAddr, SocketAddr};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use axum::Router;
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use axum::{routing::get, Router};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
-use tokio::{main, sync::Mutex as TokioMutex};
+use tokio::{
+    main,
+    sync::{broadcast, mpsc::UnboundedSender, Mutex as TokioMutex},
+};
 use tracing::info;
 use tracing_subscriber::EnvFilter;
+use utoipa::ToSchema;
 
 use crate::config::Config;
 use crate::ctx::ctx_client::Identifier;
-use crate::routes::{admin::admin_routes, config::config_routes, websocket::websocket_routes};
+use crate::message::{Event, Frame};
+use crate::middleware::auth_guard::AuthGuard;
+use crate::model::config::{ClientConfig, SubscriptionTemplates};
+use crate::model::history::{HistoryEntry, HistoryModelController};
+use crate::routes::{
+    admin::{admin_routes, admin_v1_routes},
+    auth::auth_routes,
+    config::{config_routes, config_v1_routes},
+    events::event_routes,
+    history::history_routes,
+    openapi::handle_openapi,
+    presence::presence_routes,
+    rpc::rpc_routes,
+    webhooks::webhook_routes,
+    websocket::websocket_routes,
+};
+use crate::webhook::WebhookDispatcher;
+use crate::websocket::presence::PresenceTracker;
+use crate::websocket::rpc::RpcBroker;
+use crate::websocket::template::render_event;
+use crate::websocket::ticket::TicketIssuer;
 
+mod cli;
 mod config;
 mod ctx;
 mod database_utils;
+mod error;
 mod message;
 mod middleware;
 mod model;
 mod routes;
+mod tls;
+mod webhook;
 mod websocket;
 
 type ActiveConnections = Arc<TokioMutex<Vec<ActiveConnection>>>;
 
+/// Events a stream may fall behind by before it has to catch up from the history.
+const EVENT_FEED_CAPACITY: usize = 1024;
+
+/// How often events older than `HISTORY_RETENTION_DAYS` are deleted.
+const HISTORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Longest reason a close frame can carry, in bytes.
+const MAX_CLOSE_REASON_LEN: usize = 123;
+
 #[main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -32,6 +74,10 @@ async fn main() -> anyhow::Result<()> {
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
+    if let Some(command) = cli::Command::parse(std::env::args().skip(1))? {
+        return cli::run(command).await;
+    }
+
     let config = Config::load_from_env()?;
 
     let db_pool = SqlitePoolOptions::new()
@@ -39,19 +85,71 @@ async fn main() -> anyhow::Result<()> {
         .connect(&config.DATABASE_URL)
         .await?;
 
-    let app_state = AppState::new(db_pool, config);
+    let app_state = AppState::new(db_pool, config)?;
+    spawn_history_pruner(&app_state);
+    app_state.webhooks.spawn_worker(&app_state.db_pool);
 
-    let app = Router::new()
-        .merge(websocket_routes(app_state.clone()))
+    let v1_routes = Router::new()
+        .route("/openapi.json", get(handle_openapi))
+        .nest("/auth", auth_routes(app_state.clone()))
+        .merge(config_v1_routes(app_state.clone()))
+        .merge(webhook_routes(app_state.clone()))
+        .nest("/rpc", rpc_routes(app_state.clone()))
+        .nest("/events", event_routes(app_state.clone()))
+        .nest("/history", history_routes(app_state.clone()))
+        .nest("/presence", presence_routes(app_state.clone()))
+        .nest("/admin", admin_v1_routes(app_state.clone()));
+
+    // The unversioned routes are kept as aliases until clients moved to `/v1`.
+    let legacy_routes = Router::new()
+        .nest("/auth", auth_routes(app_state.clone()))
         .nest("/config", config_routes(app_state.clone()))
+        .nest("/rpc", rpc_routes(app_state.clone()))
+        .nest("/presence", presence_routes(app_state.clone()))
         .nest("/admin", admin_routes(app_state.clone()))
-        .fallback(routes::not_found::handle_404);
+        .layer(axum::middleware::map_response(
+            middleware::mw_deprecated::mw_deprecated,
+        ));
+
+    // `/events` and `/history` never had an unversioned predecessor, so they are not marked as
+    // deprecated.
+    let mut rest_routes = Router::new()
+        .nest("/v1", v1_routes)
+        .nest("/events", event_routes(app_state.clone()))
+        .nest("/history", history_routes(app_state.clone()))
+        .merge(legacy_routes);
+
+    if let Some(cors) = middleware::cors::cors_layer(&app_state.config)? {
+        rest_routes = rest_routes.layer(cors);
+    }
 
-    let listener = tokio::net::TcpListener::bind(SocketAddr::new(
+    let app = Router::new()
+        .merge(websocket_routes(app_state.clone()))
+        .merge(rest_routes)
+        .fallback(routes::not_found::handle_404)
+        .layer(axum::middleware::map_response(
+            middleware::mw_problem_json::mw_problem_json,
+        ));
+
+    let address = SocketAddr::new(
         IpAddr::V4(app_state.config.SERVER_IP),
         app_state.config.SERVER_PORT,
-    ))
-    .await?;
+    );
+
+    if app_state.config.tls_enabled() {
+        let rustls_config = tls::load_rustls_config(&app_state.config)?;
+        tls::spawn_reloader(app_state.config.clone(), rustls_config.clone())?;
+
+        info!("Listening on {} with TLS", address);
+
+        axum_server::bind_rustls(address, rustls_config)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await?;
+
+        return Ok(());
+    }
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
 
     info!("Listening on {}", listener.local_addr()?);
 
@@ -64,34 +162,305 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Deletes events older than `HISTORY_RETENTION_DAYS` from the history every hour.
+fn spawn_history_pruner(app_state: &AppState) {
+    let retention_days = app_state.config.HISTORY_RETENTION_DAYS;
+    if retention_days == 0 {
+        return;
+    }
+
+    let db_pool = app_state.db_pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HISTORY_PRUNE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let before = Utc::now() - chrono::Duration::days(retention_days.into());
+            match HistoryModelController::prune(before, &db_pool).await {
+                Ok(0) => {}
+                Ok(pruned) => info!("Pruned {pruned} events from the history"),
+                Err(e) => tracing::error!("Failed to prune the history: {e}"),
+            }
+        }
+    });
+}
+
 #[derive(Debug, Clone)]
 struct AppState {
     db_pool: SqlitePool,
     config: Config,
     active_connections: ActiveConnections,
+    rpc_broker: RpcBroker,
+    presence: PresenceTracker,
+    tickets: TicketIssuer,
+    auth_guard: AuthGuard,
+    webhooks: WebhookDispatcher,
+    /// Every event as it is stored in the history, for `/events/stream`.
+    event_feed: broadcast::Sender<HistoryEntry>,
+    /// Held while an event is stored in the history and sent to `event_feed`, so that the feed
+    /// is in the order of the history.
+    event_feed_lock: Arc<TokioMutex<()>>,
 }
 
 impl AppState {
-    pub fn new(db_pool: SqlitePool, config: Config) -> Self {
-        Self {
+    pub fn new(db_pool: SqlitePool, config: Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            webhooks: WebhookDispatcher::new(&config)?,
             db_pool,
             config,
             active_connections: Arc::new(TokioMutex::new(Vec::new())),
-        }
+            rpc_broker: RpcBroker::default(),
+            presence: PresenceTracker::default(),
+            tickets: TicketIssuer::default(),
+            auth_guard: AuthGuard::default(),
+            event_feed: broadcast::channel(EVENT_FEED_CAPACITY).0,
+            event_feed_lock: Arc::default(),
+        })
     }
 }
 
+#[cfg(test)]
+impl AppState {
+    /// An [AppState] for tests, on a fresh in-memory database.
+    async fn for_tests() -> Self {
+        Self::new(database_utils::test_pool().await, Config::for_tests()).unwrap()
+    }
+}
+
+/// Cuts `reason` down to what fits into a close frame, without splitting a character.
+fn close_reason(reason: &str) -> &str {
+    let mut end = reason.len().min(MAX_CLOSE_REASON_LEN);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug, Clone)]
 struct ActiveConnection {
+    connection_id: u64,
     identifier: Identifier,
     subscriptions: Vec<String>,
+    templates: SubscriptionTemplates,
+    /// Whether the client asked for events to be rendered into text with its templates.
+    render: bool,
+    /// Spectators only receive events. They never publish, answer RPCs or count as online.
+    spectator: bool,
+    address: SocketAddr,
+    connected_at: DateTime<Utc>,
+    sender: UnboundedSender<Message>,
+    stats: Arc<ConnectionStats>,
+}
+
+/// [ConnectionStats] are shared by every clone of an [ActiveConnection].
+#[derive(Debug, Default)]
+struct ConnectionStats {
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    /// Set when an admin kicked the connection, and used as its disconnect reason.
+    kick_reason: Mutex<Option<String>>,
 }
 
 impl ActiveConnection {
-    fn new(identifier: impl Into<Identifier>, subscriptions: Vec<String>) -> Self {
+    fn new(
+        config: ClientConfig,
+        render: bool,
+        spectator: bool,
+        address: SocketAddr,
+        sender: UnboundedSender<Message>,
+    ) -> Self {
         Self {
-            identifier: identifier.into(),
-            subscriptions,
+            connection_id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            identifier: config.identifier,
+            subscriptions: config.subscriptions,
+            templates: config.templates,
+            render,
+            spectator,
+            address,
+            connected_at: Utc::now(),
+            sender,
+            stats: Arc::default(),
         }
     }
+
+    /// Counts a message the client sent.
+    fn record_received(&self) {
+        self.stats.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Closes the connection on behalf of an admin. Returns false if it is already gone.
+    fn kick(&self, reason: &str) -> bool {
+        *self
+            .stats
+            .kick_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(format!("kicked: {reason}"));
+
+        self.sender
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: close_reason(reason).to_string().into(),
+            })))
+            .is_ok()
+    }
+
+    /// Returns the disconnect reason if an admin kicked the connection.
+    fn kick_reason(&self) -> Option<String> {
+        self.stats
+            .kick_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            id: self.connection_id,
+            identifier: self.identifier.clone(),
+            address: self.address,
+            connected_at: self.connected_at,
+            subscriptions: self.subscriptions.clone(),
+            spectator: self.spectator,
+            messages_received: self.stats.messages_received.load(Ordering::Relaxed),
+            messages_sent: self.stats.messages_sent.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns true if this connection wants to receive events from `source`.
+    fn is_subscribed_to(&self, source: &Identifier) -> bool {
+        self.subscriptions
+            .iter()
+            .any(|sub| source.matches_subscription(sub))
+    }
+
+    /// Queues an [Event] from `source` for this connection, rendered if the client asked for it.
+    fn send_event(&self, source: Option<&Identifier>, event: &Event) -> bool {
+        let text = match (self.render, source) {
+            (true, Some(source)) => {
+                render_event(&self.templates, &self.subscriptions, source, event)
+            }
+            _ => None,
+        };
+
+        self.send(&Frame::Event {
+            source: source.cloned(),
+            event: event.clone(),
+            text,
+        })
+    }
+
+    /// Queues a [Frame] for this connection. Returns false if the connection is already gone.
+    fn send(&self, frame: &Frame) -> bool {
+        match frame.to_message() {
+            Ok(message) => {
+                let sent = self.sender.send(message).is_ok();
+
+                if sent {
+                    self.stats.messages_sent.fetch_add(1, Ordering::Relaxed);
+                }
+
+                sent
+            }
+            Err(e) => {
+                tracing::error!("Failed to serialize frame for {}: {e}", self.identifier);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl ActiveConnection {
+    /// A connection of `identifier` for tests, together with the receiving end of what is queued
+    /// for it.
+    fn for_tests(
+        identifier: &str,
+        subscriptions: &[&str],
+    ) -> (Self, tokio::sync::mpsc::UnboundedReceiver<Message>) {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let config = ClientConfig {
+            identifier: identifier.parse().unwrap(),
+            subscriptions: subscriptions.iter().map(|sub| sub.to_string()).collect(),
+            templates: SubscriptionTemplates::new(),
+        };
+
+        let connection = Self::new(
+            config,
+            false,
+            false,
+            "127.0.0.1:4000".parse().unwrap(),
+            sender,
+        );
+
+        (connection, receiver)
+    }
+}
+
+/// Returns the next [Frame] queued for a connection made with [ActiveConnection::for_tests].
+#[cfg(test)]
+fn queued_frame(receiver: &mut tokio::sync::mpsc::UnboundedReceiver<Message>) -> Option<Frame> {
+    match receiver.try_recv().ok()? {
+        Message::Text(text) => Some(serde_json::from_str(&text).unwrap()),
+        message => panic!("Expected a text message, got {message:?}"),
+    }
+}
+
+/// [ConnectionInfo] describes a live connection for admins.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+struct ConnectionInfo {
+    id: u64,
+    identifier: Identifier,
+    #[schema(value_type = String)]
+    address: SocketAddr,
+    connected_at: DateTime<Utc>,
+    subscriptions: Vec<String>,
+    spectator: bool,
+    messages_received: u64,
+    messages_sent: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_events_only_for_connections_that_asked_for_it() {
+        let source = "kiwitech:smp".parse::<Identifier>().unwrap();
+        let event = Event::Join {
+            player: "Steve".to_string(),
+        };
+
+        let (mut conn, mut receiver) = ActiveConnection::for_tests("other:smp", &["kiwitech"]);
+        assert!(conn.send_event(Some(&source), &event));
+        assert!(matches!(
+            queued_frame(&mut receiver),
+            Some(Frame::Event { text: None, .. })
+        ));
+
+        conn.render = true;
+        assert!(conn.send_event(Some(&source), &event));
+        let Some(Frame::Event { text, .. }) = queued_frame(&mut receiver) else {
+            panic!("Expected an event frame");
+        };
+        assert_eq!(text.as_deref(), Some("[smp] Steve joined the game"));
+    }
+
+    #[test]
+    fn close_reason_keeps_short_reasons() {
+        assert_eq!(close_reason("spamming"), "spamming");
+        assert_eq!(close_reason(""), "");
+    }
+
+    #[test]
+    fn close_reason_cuts_at_a_char_boundary() {
+        let ascii = "a".repeat(200);
+        assert_eq!(close_reason(&ascii).len(), MAX_CLOSE_REASON_LEN);
+
+        // 'ä' takes two bytes, so 123 bytes would end in the middle of one.
+        let umlauts = "ä".repeat(100);
+        assert_eq!(close_reason(&umlauts), "ä".repeat(61));
+    }
 }
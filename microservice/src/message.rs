@@ -1,12 +1,265 @@
 use std::{net::SocketAddr, ops::ControlFlow};
 
 use axum::extract::ws::Message;
-use tracing::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
 
-pub fn process_message(message: Message, address: SocketAddr) -> ControlFlow<(), ()> {
+use crate::ctx::ctx_client::Identifier;
+
+/// [Frame] is the envelope of everything that travels over the websocket.
+///
+/// Frames are JSON objects tagged with a `type` field, for example:
+///
+/// ```json
+/// {"type": "event", "event": {"kind": "chat", "player": "Steve", "message": "hi"}}
+/// {"type": "request", "id": "1", "target": "kiwitech:smp", "method": "list_players"}
+/// {"type": "response", "id": "1", "result": ["Steve", "Alex"]}
+/// ```
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Frame {
+    /// An event that gets fanned out to every subscriber of the source.
+    ///
+    /// The `source` is always set by the server, anything a client sends is overwritten.
+    Event {
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<Identifier>,
+        event: Event,
+        /// The event rendered with the receiver's templates, for clients that asked for it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+    /// A request to a single target client. See [RpcRequest].
+    Request(RpcRequest),
+    /// The answer to a previous [Frame::Request]. See [RpcResponse].
+    Response(RpcResponse),
+}
+
+impl Frame {
+    /// Serializes the frame into a websocket text message.
+    pub fn to_message(&self) -> anyhow::Result<Message> {
+        Ok(Message::Text(serde_json::to_string(self)?))
+    }
+}
+
+/// [Event] is the payload of a [Frame::Event].
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    Chat {
+        player: String,
+        message: String,
+    },
+    /// A player joined the source.
+    Join {
+        player: String,
+    },
+    /// A player left the source.
+    Leave {
+        player: String,
+    },
+    /// Every player currently online on the source. Replaces whatever was known before, so
+    /// clients should send it after (re)connecting.
+    PlayerList {
+        players: Vec<String>,
+    },
+    /// A message from `player` to `recipient` on the `target` client.
+    ///
+    /// It is delivered to `target` only, and only if `target` and the source are linked by a
+    /// subscription in either direction.
+    PrivateMessage {
+        player: String,
+        /// `server_id:client_id`
+        #[serde_as(as = "DisplayFromStr")]
+        #[schema(value_type = String)]
+        target: Identifier,
+        recipient: String,
+        message: String,
+    },
+    /// The source connected to the bridge. Only ever sent by the server.
+    Online,
+    /// The source lost its last connection to the bridge. Only ever sent by the server.
+    Offline {
+        reason: String,
+    },
+    /// Something the client sent could not be handled. Only ever sent by the server, without
+    /// a source.
+    Error {
+        message: String,
+    },
+    /// A notice from the bridge operators. Only ever sent by the server, without a source.
+    Notice {
+        message: String,
+    },
+}
+
+impl Event {
+    /// Returns the `kind` tag of the event.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Chat { .. } => "chat",
+            Event::Join { .. } => "join",
+            Event::Leave { .. } => "leave",
+            Event::PlayerList { .. } => "player_list",
+            Event::PrivateMessage { .. } => "private_message",
+            Event::Online => "online",
+            Event::Offline { .. } => "offline",
+            Event::Error { .. } => "error",
+            Event::Notice { .. } => "notice",
+        }
+    }
+
+    /// Returns the player the event is about, if it is about a single one.
+    pub fn player(&self) -> Option<&str> {
+        match self {
+            Event::Chat { player, .. }
+            | Event::Join { player }
+            | Event::Leave { player }
+            | Event::PrivateMessage { player, .. } => Some(player),
+            _ => None,
+        }
+    }
+
+    /// Returns the text of the event, if it has one.
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Event::Chat { message, .. }
+            | Event::PrivateMessage { message, .. }
+            | Event::Error { message }
+            | Event::Notice { message } => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Returns true for events that only the server may publish.
+    pub fn is_system(&self) -> bool {
+        matches!(
+            self,
+            Event::Online | Event::Offline { .. } | Event::Error { .. } | Event::Notice { .. }
+        )
+    }
+}
+
+/// [RpcRequest] asks the `target` client something, like the players online or the current TPS.
+///
+/// The `id` is chosen by the requester and is echoed back in the matching [RpcResponse].
+/// When the server forwards a request to the target, it replaces the `id` with its own and sets
+/// `source` to the requester.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub id: String,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Identifier>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub target: Identifier,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    /// How long to wait for the answer in milliseconds. Defaults to [crate::websocket::rpc::DEFAULT_TIMEOUT].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// [RpcResponse] answers an [RpcRequest] with the same `id`.
+///
+/// Exactly one of `result` and `error` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn from_result(id: impl Into<String>, result: Result<Value, RpcError>) -> Self {
+        match result {
+            Ok(value) => Self {
+                id: id.into(),
+                result: Some(value),
+                error: None,
+            },
+            Err(error) => Self {
+                id: id.into(),
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value, RpcError> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(error),
+            (Some(value), None) => Ok(value),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: RpcErrorCode,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: RpcErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcErrorCode {
+    /// The target is not connected.
+    TargetOffline,
+    /// The target did not answer in time.
+    Timeout,
+    /// The requester is not allowed to talk to the target.
+    Forbidden,
+    /// The request itself was malformed.
+    BadRequest,
+    /// The target answered with an error of its own.
+    #[serde(other)]
+    Remote,
+}
+
+/// Processes a raw websocket message and returns the [Frame] it contains, if any.
+///
+/// Breaks with the reason the client gave when it closes the connection.
+pub fn process_message(
+    message: Message,
+    address: SocketAddr,
+) -> ControlFlow<String, Option<Frame>> {
     match message {
         Message::Text(t) => {
             debug!(">>> {address} sent str: {t:?}");
+
+            return match serde_json::from_str::<Frame>(&t) {
+                Ok(frame) => ControlFlow::Continue(Some(frame)),
+                Err(e) => {
+                    warn!(">>> {address} sent an invalid frame: {e}");
+                    ControlFlow::Continue(None)
+                }
+            };
         }
 
         Message::Binary(d) => {
@@ -19,10 +272,14 @@ pub fn process_message(message: Message, address: SocketAddr) -> ControlFlow<(),
                     ">>> {} sent close with code {} and reason `{}`",
                     address, cf.code, cf.reason
                 );
+
+                if !cf.reason.is_empty() {
+                    return ControlFlow::Break(cf.reason.into_owned());
+                }
             } else {
                 debug!(">>> {address} somehow sent close message without CloseFrame");
             }
-            return ControlFlow::Break(());
+            return ControlFlow::Break("closed by client".to_string());
         }
 
         Message::Pong(v) => {
@@ -43,5 +300,75 @@ pub fn process_message(message: Message, address: SocketAddr) -> ControlFlow<(),
             debug!(">>> {address} sent ping with {v:?}");
         }
     }
-    ControlFlow::Continue(())
+    ControlFlow::Continue(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:4000".parse().unwrap()
+    }
+
+    #[test]
+    fn parses_request_and_response_frames() {
+        let message = Message::Text(
+            r#"{"type": "request", "id": "1", "target": "kiwitech:smp", "method": "list_players"}"#
+                .to_string(),
+        );
+        let ControlFlow::Continue(Some(Frame::Request(request))) =
+            process_message(message, address())
+        else {
+            panic!("Expected a request frame");
+        };
+        assert_eq!(request.target.to_string(), "kiwitech:smp");
+        assert_eq!(request.params, Value::Null);
+        assert_eq!(request.timeout_ms, None);
+
+        let message =
+            Message::Text(r#"{"type": "response", "id": "1", "result": ["Steve"]}"#.to_string());
+        let ControlFlow::Continue(Some(Frame::Response(response))) =
+            process_message(message, address())
+        else {
+            panic!("Expected a response frame");
+        };
+        assert_eq!(response.into_result().unwrap(), json!(["Steve"]));
+    }
+
+    #[test]
+    fn invalid_frames_are_skipped() {
+        let message = Message::Text(r#"{"type": "request", "id": "1"}"#.to_string());
+
+        assert!(matches!(
+            process_message(message, address()),
+            ControlFlow::Continue(None)
+        ));
+    }
+
+    #[test]
+    fn responses_with_an_error_fail() {
+        let response = RpcResponse {
+            id: "1".to_string(),
+            result: Some(json!(1)),
+            error: Some(RpcError::new(RpcErrorCode::Remote, "no such method")),
+        };
+        assert_eq!(
+            response.into_result().unwrap_err().code,
+            RpcErrorCode::Remote
+        );
+
+        let response: RpcResponse =
+            serde_json::from_str(r#"{"id": "1", "error": {"code": "weird", "message": "?"}}"#)
+                .unwrap();
+        assert_eq!(
+            response.into_result().unwrap_err().code,
+            RpcErrorCode::Remote
+        );
+
+        let response = RpcResponse::from_result("1", Ok(Value::Null));
+        assert_eq!(response.into_result().unwrap(), Value::Null);
+    }
 }
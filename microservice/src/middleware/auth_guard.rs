@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use sqlx::SqlitePool;
+
+use crate::error::{Error, Result};
+use crate::model::user::UserModelController;
+
+/// Failures that are allowed before a key gets locked out.
+const FREE_ATTEMPTS: u32 = 5;
+
+/// Lockout after the first failure past [FREE_ATTEMPTS]. It doubles with every further failure.
+const BASE_LOCKOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound of a single lockout.
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Failures are forgotten this long after the last one.
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// Keys with failures that are remembered at once. The oldest are forgotten first, so that
+/// attempts from many addresses cannot grow memory without bound.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// An address that authenticated for a server is trusted this long after the last success.
+const TRUST_MEMORY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Addresses that are trusted at once, the ones that authenticated longest ago are forgotten first.
+const MAX_TRUSTED_ADDRESSES: usize = 10_000;
+
+/// What failed authentication attempts are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockoutKey {
+    Address(IpAddr),
+    Server(String),
+}
+
+/// [AuthGuard] counts failed authentication attempts and locks out addresses and servers that
+/// keep failing, with a lockout that doubles on every further failure.
+///
+/// A server that is locked out still accepts addresses that authenticated for it before, so that
+/// anyone guessing tokens for a server cannot lock out its own clients.
+#[derive(Debug, Clone, Default)]
+pub struct AuthGuard {
+    failures: Arc<Mutex<HashMap<LockoutKey, FailureRecord>>>,
+    /// When an address last authenticated for a server.
+    trusted: Arc<Mutex<HashMap<(String, IpAddr), Instant>>>,
+}
+
+#[derive(Debug)]
+struct FailureRecord {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl AuthGuard {
+    /// Returns how long the longest lockout among `keys` still lasts, if any.
+    ///
+    /// The lockout of a [LockoutKey::Server] is ignored if the [LockoutKey::Address] among `keys`
+    /// authenticated for that server before.
+    pub fn locked_for(&self, keys: &[LockoutKey]) -> Option<Duration> {
+        let now = Instant::now();
+        let failures = self.failures();
+
+        keys.iter()
+            .filter(|key| !self.is_trusted(key, keys, now))
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max()
+    }
+
+    /// Counts a failed attempt against every key and locks out those with too many failures.
+    ///
+    /// Only pass a [LockoutKey::Server] for servers that exist, see [failure_keys].
+    pub fn record_failure(&self, keys: &[LockoutKey]) {
+        let now = Instant::now();
+        let mut failures = self.failures();
+
+        failures.retain(|_, record| now - record.last_failure < FAILURE_MEMORY);
+
+        for key in keys {
+            if !failures.contains_key(key) && failures.len() >= MAX_TRACKED_KEYS {
+                forget_oldest(&mut failures, |record| record.last_failure);
+            }
+
+            let record = failures.entry(key.clone()).or_insert(FailureRecord {
+                count: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+
+            record.count += 1;
+            record.last_failure = now;
+
+            if record.count > FREE_ATTEMPTS {
+                let exponent = (record.count - FREE_ATTEMPTS - 1).min(16);
+                let lockout = BASE_LOCKOUT.saturating_mul(1 << exponent).min(MAX_LOCKOUT);
+
+                record.locked_until = Some(now + lockout);
+
+                tracing::warn!(
+                    "Locked out {:?} for {}s after {} failed authentication attempts",
+                    key,
+                    lockout.as_secs(),
+                    record.count
+                );
+            }
+        }
+    }
+
+    /// Forgets the failures of every key after a successful authentication, and trusts the
+    /// address for the server from now on.
+    pub fn record_success(&self, keys: &[LockoutKey]) {
+        let now = Instant::now();
+
+        let mut failures = self.failures();
+        for key in keys {
+            failures.remove(key);
+        }
+        drop(failures);
+
+        let Some((server_id, address)) = server_and_address(keys) else {
+            return;
+        };
+
+        let mut trusted = self.trusted();
+        trusted.retain(|_, last_success| now - *last_success < TRUST_MEMORY);
+
+        let pair = (server_id.to_string(), address);
+        if !trusted.contains_key(&pair) && trusted.len() >= MAX_TRUSTED_ADDRESSES {
+            forget_oldest(&mut trusted, |last_success| *last_success);
+        }
+        trusted.insert(pair, now);
+    }
+
+    /// Returns true if `key` is a server that the address among `keys` authenticated for.
+    fn is_trusted(&self, key: &LockoutKey, keys: &[LockoutKey], now: Instant) -> bool {
+        let LockoutKey::Server(_) = key else {
+            return false;
+        };
+
+        server_and_address(keys).is_some_and(|(server_id, address)| {
+            self.trusted()
+                .get(&(server_id.to_string(), address))
+                .is_some_and(|last_success| now - *last_success < TRUST_MEMORY)
+        })
+    }
+
+    fn failures(&self) -> MutexGuard<'_, HashMap<LockoutKey, FailureRecord>> {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn trusted(&self) -> MutexGuard<'_, HashMap<(String, IpAddr), Instant>> {
+        self.trusted.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Rejects the request with `429 Too Many Requests` if any of `keys` is locked out.
+    pub fn ensure_unlocked(&self, keys: &[LockoutKey]) -> Result<()> {
+        match self.locked_for(keys) {
+            Some(remaining) => {
+                tracing::error!(
+                    "Rejected authentication attempt of locked out {:?} for another {}s",
+                    keys,
+                    remaining.as_secs()
+                );
+                Err(Error::TooManyRequests(remaining))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the server and the address among `keys`, if there are both.
+fn server_and_address(keys: &[LockoutKey]) -> Option<(&str, IpAddr)> {
+    let server_id = keys.iter().find_map(|key| match key {
+        LockoutKey::Server(server_id) => Some(server_id.as_str()),
+        _ => None,
+    })?;
+    let address = keys.iter().find_map(|key| match key {
+        LockoutKey::Address(address) => Some(*address),
+        _ => None,
+    })?;
+
+    Some((server_id, address))
+}
+
+/// Removes the entry of `map` that `last_seen` says is the oldest.
+fn forget_oldest<K: Clone + Eq + Hash, V>(
+    map: &mut HashMap<K, V>,
+    last_seen: impl Fn(&V) -> Instant,
+) {
+    if let Some(oldest) = map
+        .iter()
+        .min_by_key(|(_, value)| last_seen(value))
+        .map(|(key, _)| key.clone())
+    {
+        map.remove(&oldest);
+    }
+}
+
+/// Returns the keys a rejected token for `server_id` from `address` counts against.
+///
+/// Servers that do not exist are left out. Anyone can claim any server in `X-Client-ID`, and
+/// counting those would let them fill the [AuthGuard] with made up servers.
+pub async fn failure_keys(
+    address: IpAddr,
+    server_id: &str,
+    db_pool: &SqlitePool,
+) -> Vec<LockoutKey> {
+    let mut keys = vec![LockoutKey::Address(address)];
+
+    if UserModelController::get_user_by_id(server_id, db_pool)
+        .await
+        .is_ok()
+    {
+        keys.push(LockoutKey::Server(server_id.to_string()));
+    }
+
+    keys
+}
+
+/// Rejects the request with `403 Forbidden` if the tokens of `server_id` may not be used from
+/// `address`.
+pub async fn ensure_address_allowed(
+    server_id: &str,
+    address: IpAddr,
+    db_pool: &SqlitePool,
+) -> Result<()> {
+    if !UserModelController::is_address_allowed(server_id, address, db_pool).await? {
+        tracing::error!("Token of {server_id} used from {address}, which is not allowed");
+        return Err(Error::Forbidden(format!(
+            "Tokens of {server_id} may not be used from {address}"
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "203.0.113.7";
+
+    fn keys() -> [LockoutKey; 2] {
+        [
+            LockoutKey::Address(ADDRESS.parse().unwrap()),
+            LockoutKey::Server("kiwitech".to_string()),
+        ]
+    }
+
+    #[test]
+    fn locks_out_after_the_free_attempts() {
+        let guard = AuthGuard::default();
+
+        for _ in 0..FREE_ATTEMPTS {
+            guard.record_failure(&keys());
+        }
+        assert!(guard.ensure_unlocked(&keys()).is_ok());
+
+        guard.record_failure(&keys());
+        let locked_for = guard.locked_for(&keys()).unwrap();
+        assert!(locked_for <= BASE_LOCKOUT && locked_for > BASE_LOCKOUT / 2);
+        assert!(matches!(
+            guard.ensure_unlocked(&keys()),
+            Err(Error::TooManyRequests(_))
+        ));
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        let guard = AuthGuard::default();
+
+        for _ in 0..FREE_ATTEMPTS + 2 {
+            guard.record_failure(&keys());
+        }
+        let locked_for = guard.locked_for(&keys()).unwrap();
+        assert!(locked_for > BASE_LOCKOUT && locked_for <= BASE_LOCKOUT * 2);
+
+        for _ in 0..30 {
+            guard.record_failure(&keys());
+        }
+        assert!(guard.locked_for(&keys()).unwrap() <= MAX_LOCKOUT);
+    }
+
+    #[test]
+    fn success_resets_the_failures() {
+        let guard = AuthGuard::default();
+
+        for _ in 0..FREE_ATTEMPTS {
+            guard.record_failure(&keys());
+        }
+        guard.record_success(&keys());
+        guard.record_failure(&keys());
+
+        assert!(guard.locked_for(&keys()).is_none());
+    }
+
+    #[test]
+    fn locked_server_still_accepts_trusted_addresses() {
+        let guard = AuthGuard::default();
+        guard.record_success(&keys());
+
+        let attacker = [
+            LockoutKey::Address("198.51.100.1".parse().unwrap()),
+            LockoutKey::Server("kiwitech".to_string()),
+        ];
+        for _ in 0..=FREE_ATTEMPTS {
+            guard.record_failure(&attacker);
+        }
+
+        assert!(guard.ensure_unlocked(&attacker).is_err());
+        assert!(guard.ensure_unlocked(&keys()).is_ok());
+
+        let stranger = [
+            LockoutKey::Address("198.51.100.2".parse().unwrap()),
+            LockoutKey::Server("kiwitech".to_string()),
+        ];
+        assert!(guard.ensure_unlocked(&stranger).is_err());
+    }
+
+    #[test]
+    fn forgets_the_oldest_keys_when_full() {
+        let guard = AuthGuard::default();
+
+        for i in 0..MAX_TRACKED_KEYS + 10 {
+            guard.record_failure(&[LockoutKey::Server(format!("server{i}"))]);
+        }
+
+        let failures = guard.failures();
+        assert_eq!(failures.len(), MAX_TRACKED_KEYS);
+        assert!(failures.contains_key(&LockoutKey::Server(format!(
+            "server{}",
+            MAX_TRACKED_KEYS + 9
+        ))));
+    }
+}
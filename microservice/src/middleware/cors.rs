@@ -0,0 +1,127 @@
+use anyhow::Context;
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::config::Config;
+
+/// Builds the CORS layer of the REST routes from `CORS_ALLOWED_ORIGINS`.
+///
+/// Returns `None` if no origins are configured, so browsers on other origins are turned away.
+pub fn cors_layer(config: &Config) -> anyhow::Result<Option<CorsLayer>> {
+    if config.CORS_ALLOWED_ORIGINS.is_empty() {
+        return Ok(None);
+    }
+
+    let allow_origin = if config
+        .CORS_ALLOWED_ORIGINS
+        .iter()
+        .any(|origin| origin == "*")
+    {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = config
+            .CORS_ALLOWED_ORIGINS
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .with_context(|| format!("Invalid origin in `CORS_ALLOWED_ORIGINS`: {origin}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        AllowOrigin::list(origins)
+    };
+
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                HeaderName::from_static("x-client-id"),
+            ])
+            .expose_headers([header::RETRY_AFTER, header::WWW_AUTHENTICATE]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn config(origins: &[&str]) -> Config {
+        Config {
+            CORS_ALLOWED_ORIGINS: origins.iter().map(|origin| origin.to_string()).collect(),
+            ..Config::for_tests()
+        }
+    }
+
+    /// Sends a preflight request from `origin` and returns the origin the layer allowed, if any.
+    async fn allowed_origin(layer: CorsLayer, origin: &str) -> Option<String> {
+        let app = Router::new().route("/", get(|| async {})).layer(layer);
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-client-id")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|origin| origin.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn is_off_without_origins() {
+        assert!(cors_layer(&config(&[])).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_origins() {
+        let error = cors_layer(&config(&["https://a.example\n"])).unwrap_err();
+
+        assert!(error.to_string().contains("CORS_ALLOWED_ORIGINS"));
+    }
+
+    #[tokio::test]
+    async fn allows_only_the_listed_origins() {
+        let layer = cors_layer(&config(&["https://a.example"]))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            allowed_origin(layer.clone(), "https://a.example").await,
+            Some("https://a.example".to_string())
+        );
+        assert_eq!(allowed_origin(layer, "https://evil.example").await, None);
+    }
+
+    #[tokio::test]
+    async fn a_wildcard_allows_every_origin() {
+        let layer = cors_layer(&config(&["https://a.example", "*"]))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            allowed_origin(layer, "https://evil.example").await,
+            Some("*".to_string())
+        );
+    }
+}
@@ -1,3 +1,7 @@
+pub mod auth_guard;
+pub mod cors;
 pub mod mw_auth_admin;
 pub mod mw_auth_client;
 pub mod mw_auth_websocket;
+pub mod mw_deprecated;
+pub mod mw_problem_json;
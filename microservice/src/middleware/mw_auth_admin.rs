@@ -1,32 +1,70 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::{Request, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Request, State},
     middleware::Next,
     response::Response,
 };
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
+use subtle::ConstantTimeEq;
 
+use crate::ctx::ctx_admin::AdminCtx;
+use crate::error::{Error, Result};
+use crate::middleware::auth_guard::LockoutKey;
+use crate::model::admin::AdminModelController;
 use crate::{config::Config, AppState};
 
 pub async fn mw_admin_auth(
     State(app_state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    req: Request,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    mut req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-    if authorize_user(&app_state.config, &auth).is_ok() {
-        return Ok(next.run(req).await);
-    }
+) -> Result<Response> {
+    let Some(TypedHeader(auth)) = auth else {
+        return Err(Error::Unauthorized(
+            "Missing Authorization header".to_string(),
+        ));
+    };
+
+    let lockout_keys = [LockoutKey::Address(address.ip())];
+    app_state.auth_guard.ensure_unlocked(&lockout_keys)?;
+
+    let admin_ctx = match authorize_admin(&app_state, &auth).await {
+        Ok(admin_ctx) => admin_ctx,
+        Err(e @ Error::Unauthorized(_)) => {
+            app_state.auth_guard.record_failure(&lockout_keys);
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
 
-    Err(StatusCode::UNAUTHORIZED)
+    app_state.auth_guard.record_success(&lockout_keys);
+
+    req.extensions_mut().insert(admin_ctx);
+
+    Ok(next.run(req).await)
 }
 
-fn authorize_user(config: &Config, auth_token: &Authorization<Bearer>) -> anyhow::Result<()> {
-    if auth_token.0.token() != config.ADMIN_TOKEN {
-        tracing::error!("Unauthorized Admin Request Attempt");
-        return Err(anyhow::anyhow!("Unauthorized Admin Request"));
+async fn authorize_admin(
+    app_state: &AppState,
+    auth_token: &Authorization<Bearer>,
+) -> Result<AdminCtx> {
+    if is_bootstrap_token(&app_state.config, auth_token) {
+        return Ok(AdminCtx::bootstrap());
     }
 
-    Ok(())
+    AdminModelController::authenticate(auth_token.token(), &app_state.db_pool)
+        .await?
+        .ok_or_else(|| {
+            tracing::error!("Unauthorized Admin Request Attempt");
+            Error::Unauthorized("Invalid admin token".to_string())
+        })
+}
+
+fn is_bootstrap_token(config: &Config, auth_token: &Authorization<Bearer>) -> bool {
+    config.ADMIN_TOKEN.as_ref().is_some_and(|admin_token| {
+        bool::from(admin_token.as_bytes().ct_eq(auth_token.token().as_bytes()))
+    })
 }
@@ -1,6 +1,7 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::{Request, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Request, State},
     middleware::Next,
     response::Response,
 };
@@ -8,20 +9,36 @@ use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
 use sqlx::SqlitePool;
 
-use crate::{config::Config, model::user::UserModelController, AppState};
-use crate::{
-    ctx::ctx_client::{ClientCtx, Identifier},
-    database_utils::hash_password,
-};
+use crate::ctx::ctx_client::{ClientCtx, Identifier};
+use crate::error::{Error, Result};
+use crate::middleware::auth_guard::{ensure_address_allowed, failure_keys, LockoutKey};
+use crate::model::token::{AuthenticatedToken, TokenModelController, TokenScope};
+use crate::{config::Config, AppState};
 
+/// Authenticates a client and checks that its token has the `scope` the routes require.
+///
+/// Use it with the state tuple `(AppState, TokenScope)`.
 pub async fn mw_client_auth(
     client_ctx: ClientCtx,
-    State(app_state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    State((app_state, scope)): State<(AppState, TokenScope)>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
     mut req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-    if let Err(e) = authorize_client(
+) -> Result<Response> {
+    let Some(TypedHeader(auth)) = auth else {
+        return Err(Error::Unauthorized(
+            "Missing Authorization header".to_string(),
+        ));
+    };
+
+    let lockout_keys = [
+        LockoutKey::Address(address.ip()),
+        LockoutKey::Server(client_ctx.identifier.server_id.clone()),
+    ];
+    app_state.auth_guard.ensure_unlocked(&lockout_keys)?;
+
+    let token = match authorize_client(
         client_ctx.identifier(),
         &app_state.db_pool,
         &auth,
@@ -29,14 +46,52 @@ pub async fn mw_client_auth(
     )
     .await
     {
-        if e.to_string() == "Unauthorized Admin Request" {
-            return Err(StatusCode::UNAUTHORIZED);
+        Ok(token) => token,
+        Err(e @ Error::Unauthorized(_)) => {
+            let failure_keys = failure_keys(
+                address.ip(),
+                client_ctx.identifier.server_id(),
+                &app_state.db_pool,
+            )
+            .await;
+            app_state.auth_guard.record_failure(&failure_keys);
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if let Err(e) = ensure_address_allowed(
+        client_ctx.identifier.server_id(),
+        address.ip(),
+        &app_state.db_pool,
+    )
+    .await
+    {
+        // A host outside the allowlist only counts against itself, not against the server.
+        if matches!(e, Error::Forbidden(_)) {
+            app_state
+                .auth_guard
+                .record_failure(&[LockoutKey::Address(address.ip())]);
         }
+        return Err(e);
+    }
 
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    app_state.auth_guard.record_success(&lockout_keys);
+
+    if !token.has_scope(scope) {
+        tracing::error!(
+            "Token of {} is missing the {:?} scope",
+            client_ctx.identifier,
+            scope
+        );
+        return Err(Error::Forbidden(format!(
+            "Token is missing the {:?} scope",
+            scope
+        )));
     }
 
     req.extensions_mut().insert(client_ctx);
+    req.extensions_mut().insert(token);
 
     Ok(next.run(req).await)
 }
@@ -46,16 +101,100 @@ async fn authorize_client(
     db_pool: &SqlitePool,
     auth_token: &Authorization<Bearer>,
     salt: &str,
-) -> anyhow::Result<()> {
-    let user = match UserModelController::get_user_by_id(identifier.server_id(), db_pool).await {
-        Ok(user) => user,
-        Err(e) => return Err(e),
-    };
+) -> Result<AuthenticatedToken> {
+    let token =
+        TokenModelController::authenticate(identifier, auth_token.token(), salt, db_pool).await?;
+
+    token.ok_or_else(|| {
+        tracing::error!("Unauthorized Client Request Attempt");
+        Error::Unauthorized("Invalid token".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::model::user::{AdminPostBody, UserModelController};
+
+    async fn app() -> Router {
+        let app_state = AppState::for_tests().await;
+
+        UserModelController::add_user(
+            AdminPostBody {
+                server_id: "kiwitech".to_string(),
+                server_list: vec![],
+                auth_token: "secret".to_string(),
+                allowed_cidrs: None,
+            },
+            &app_state.db_pool,
+        )
+        .await
+        .unwrap();
+
+        Router::new()
+            .route("/", get(|| async {}))
+            .route_layer(middleware::from_fn_with_state(
+                (app_state, TokenScope::ConfigRead),
+                mw_client_auth,
+            ))
+            .layer(MockConnectInfo(SocketAddr::from(([203, 0, 113, 7], 4000))))
+    }
+
+    async fn status_for(app: &Router, client_id: &str, token: &str) -> StatusCode {
+        let request = Request::builder()
+            .uri("/")
+            .header("X-Client-ID", client_id)
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn accepts_the_primary_token() {
+        let app = app().await;
+
+        assert_eq!(
+            status_for(&app, "kiwitech:smp", "secret").await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_servers_look_like_wrong_tokens() {
+        let app = app().await;
 
-    if user.hashed_auth_token != hash_password(auth_token.token().to_string(), salt) {
-        tracing::error!("Unauthorized Admin Request Attempt");
-        return Err(anyhow::anyhow!("Unauthorized Admin Request"));
+        assert_eq!(
+            status_for(&app, "kiwitech:smp", "guess").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_for(&app, "nowhere:smp", "guess").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_for(&app, "nowhere:smp", "cbt_1_guess").await,
+            StatusCode::UNAUTHORIZED
+        );
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn failures_on_unknown_servers_count_towards_the_lockout() {
+        let app = app().await;
+
+        let mut status = StatusCode::OK;
+        for _ in 0..10 {
+            status = status_for(&app, "nowhere:smp", "guess").await;
+        }
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
 }
@@ -1,38 +1,131 @@
-use axum::extract::{Request, State};
-use axum::http::StatusCode;
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Query, Request, State};
 use axum::middleware::Next;
 use axum::response::Response;
 use axum_extra::TypedHeader;
 use headers::authorization::Bearer;
 use headers::Authorization;
+use serde::Deserialize;
 use sqlx::SqlitePool;
 
 use crate::config::Config;
 use crate::ctx::ctx_client::{ClientCtx, Identifier};
-use crate::database_utils::hash_password;
-use crate::model::config::ConfigModelController;
-use crate::model::user::UserModelController;
+use crate::error::{Error, Result};
+use crate::middleware::auth_guard::{ensure_address_allowed, failure_keys, AuthGuard, LockoutKey};
+use crate::model::config::{ClientConfig, ConfigModelController};
+use crate::model::token::{AuthenticatedToken, TokenModelController, TokenScope};
+use crate::websocket::ticket::TicketIssuer;
 use crate::AppState;
 
+/// Authenticates a websocket handshake.
+///
+/// Clients either send the usual `X-Client-ID` and `Authorization` headers, or a ticket from
+/// `POST /auth/ticket` as `?ticket=...`, which browsers can do.
+///
+/// Tokens with only the [TokenScope::Spectate] scope connect read-only and can narrow their
+/// subscriptions with `?sources=...`.
 pub async fn mw_websocket_auth(
     app_state: State<AppState>,
-    client_ctx: ClientCtx,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Query(query): Query<TicketQuery>,
+    client_ctx: Option<ClientCtx>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
     mut req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-    check_auth_token(
-        client_ctx.identifier(),
-        &auth,
-        &app_state.db_pool,
-        &app_state.config.SALT,
-    )
-    .await?;
-    let subscriptions =
-        get_subscriptions(client_ctx.identifier.clone(), &app_state.db_pool).await?;
+) -> Result<Response> {
+    let (client_ctx, token) = match (query.ticket.as_deref(), client_ctx, auth) {
+        (Some(ticket), _, _) => {
+            let lockout_keys = [LockoutKey::Address(address.ip())];
+            app_state.auth_guard.ensure_unlocked(&lockout_keys)?;
+
+            let redeemed = redeem_ticket(&app_state.tickets, ticket);
+            record_outcome(&app_state.auth_guard, &lockout_keys, &redeemed);
+            redeemed?
+        }
+        (None, Some(client_ctx), Some(TypedHeader(auth))) => {
+            let lockout_keys = [
+                LockoutKey::Address(address.ip()),
+                LockoutKey::Server(client_ctx.identifier.server_id.clone()),
+            ];
+            app_state.auth_guard.ensure_unlocked(&lockout_keys)?;
+
+            let token = match check_auth_token(
+                client_ctx.identifier(),
+                &auth,
+                &app_state.db_pool,
+                &app_state.config.SALT,
+            )
+            .await
+            {
+                Ok(token) => token,
+                Err(e @ Error::Unauthorized(_)) => {
+                    let failure_keys = failure_keys(
+                        address.ip(),
+                        client_ctx.identifier.server_id(),
+                        &app_state.db_pool,
+                    )
+                    .await;
+                    app_state.auth_guard.record_failure(&failure_keys);
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+
+            // A host outside the allowlist only counts against itself, not against the server.
+            let allowed = ensure_address_allowed(
+                client_ctx.identifier.server_id(),
+                address.ip(),
+                &app_state.db_pool,
+            )
+            .await;
+            match &allowed {
+                Ok(()) => app_state.auth_guard.record_success(&lockout_keys),
+                Err(_) => record_outcome(
+                    &app_state.auth_guard,
+                    &[LockoutKey::Address(address.ip())],
+                    &allowed,
+                ),
+            }
+            allowed?;
+
+            (client_ctx, token)
+        }
+        _ => {
+            tracing::error!("Websocket Request without credentials");
+            return Err(Error::Unauthorized(
+                "Send X-Client-ID and Authorization headers or a ticket".to_string(),
+            ));
+        }
+    };
+
+    if !token.has_scope(TokenScope::Spectate) {
+        tracing::error!(
+            "Token of {} is missing the {:?} or {:?} scope",
+            client_ctx.identifier,
+            TokenScope::WsConnect,
+            TokenScope::Spectate
+        );
+        return Err(Error::Forbidden(format!(
+            "Token is missing the {:?} or {:?} scope",
+            TokenScope::WsConnect,
+            TokenScope::Spectate
+        )));
+    }
+
+    let mut config =
+        ConfigModelController::get_config_by_identifier(&client_ctx.identifier, &app_state.db_pool)
+            .await?;
+
+    if token.is_spectator() {
+        if let Some(sources) = query.sources {
+            config.subscriptions = choose_sources(&config.subscriptions, &sources)?;
+        }
+    }
 
     req.extensions_mut().insert(client_ctx.clone());
-    req.extensions_mut().insert(subscriptions);
+    req.extensions_mut().insert(config);
+    req.extensions_mut().insert(token);
 
     tracing::info!("Wesocket Middleware Authenticated Client: {:?}", client_ctx);
 
@@ -44,32 +137,245 @@ async fn check_auth_token(
     auth_token: &Authorization<Bearer>,
     db_pool: &SqlitePool,
     config_salt: &str,
-) -> Result<(), StatusCode> {
-    let Ok(user) = UserModelController::get_user_by_id(identifier.server_id(), db_pool).await
-    else {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    };
+) -> Result<AuthenticatedToken> {
+    let token =
+        TokenModelController::authenticate(identifier, auth_token.token(), config_salt, db_pool)
+            .await?;
 
-    if user.hashed_auth_token != hash_password(auth_token.token().to_string(), config_salt) {
+    token.ok_or_else(|| {
         tracing::error!("Unauthorized Websocket Request Attempt");
-        return Err(StatusCode::UNAUTHORIZED);
+        Error::Unauthorized("Invalid token".to_string())
+    })
+}
+
+/// Counts rejected credentials towards a lockout. Server errors do not count.
+fn record_outcome<T>(auth_guard: &AuthGuard, lockout_keys: &[LockoutKey], outcome: &Result<T>) {
+    match outcome {
+        Ok(_) => auth_guard.record_success(lockout_keys),
+        Err(Error::Unauthorized(_) | Error::Forbidden(_)) => {
+            auth_guard.record_failure(lockout_keys)
+        }
+        Err(_) => {}
     }
+}
+
+fn redeem_ticket(tickets: &TicketIssuer, ticket: &str) -> Result<(ClientCtx, AuthenticatedToken)> {
+    let Some(claims) = tickets.redeem(ticket) else {
+        tracing::error!("Invalid, expired or reused Websocket Ticket");
+        return Err(Error::Unauthorized(
+            "Invalid, expired or reused ticket".to_string(),
+        ));
+    };
+
+    // A ticket is only ever issued with the websocket scopes of a token, and grants nothing else.
+    let token = AuthenticatedToken {
+        token_id: claims.token_id,
+        scopes: claims.scopes,
+    };
 
-    Ok(())
+    Ok((ClientCtx::new(claims.identifier), token))
 }
 
-async fn get_subscriptions(
-    identifier: Identifier,
-    db_pool: &SqlitePool,
-) -> Result<Vec<String>, StatusCode> {
-    match ConfigModelController::get_config_by_identifier(&identifier, db_pool).await {
-        Ok(config) => Ok(config.subscriptions),
-        Err(e) => {
-            if e.to_string() == "No Config Found" {
-                Err(StatusCode::NOT_FOUND)
-            } else {
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
+/// Narrows the subscriptions of a spectator to the comma separated `sources` it asked for.
+///
+/// Every source has to be covered by a subscription of the config, so a spectator can never see
+/// more than its config allows.
+fn choose_sources(subscriptions: &[String], sources: &str) -> Result<Vec<String>> {
+    let sources = sources
+        .split(',')
+        .map(str::trim)
+        .filter(|source| !source.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    if sources.is_empty() {
+        return Err(Error::BadRequest("No sources given".to_string()));
+    }
+
+    for source in &sources {
+        let is_covered = subscriptions.contains(source)
+            || source.parse::<Identifier>().is_ok_and(|identifier| {
+                subscriptions
+                    .iter()
+                    .any(|sub| identifier.matches_subscription(sub))
+            });
+
+        if !is_covered {
+            return Err(Error::Forbidden(format!(
+                "{source} is not one of the subscriptions of this client"
+            )));
         }
     }
+
+    Ok(sources)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TicketQuery {
+    pub ticket: Option<String>,
+    /// Comma separated sources a spectator wants events from, e.g. `kiwitech:smp,discord`.
+    /// Defaults to all subscriptions of its config.
+    pub sources: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Extension, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::model::config::SubscriptionTemplates;
+    use crate::model::token::TokenCreateBody;
+    use crate::model::user::{AdminPostBody, UserModelController};
+
+    fn subscriptions() -> Vec<String> {
+        vec!["kiwitech".to_string(), "discord:bot".to_string()]
+    }
+
+    #[test]
+    fn chooses_covered_sources() {
+        assert_eq!(
+            choose_sources(&subscriptions(), "kiwitech:smp, discord:bot").unwrap(),
+            vec!["kiwitech:smp", "discord:bot"]
+        );
+        assert_eq!(
+            choose_sources(&subscriptions(), "kiwitech").unwrap(),
+            vec!["kiwitech"]
+        );
+    }
+
+    #[test]
+    fn rejects_sources_outside_the_subscriptions() {
+        assert!(matches!(
+            choose_sources(&subscriptions(), "kiwitech:smp,other:smp"),
+            Err(Error::Forbidden(_))
+        ));
+        assert!(matches!(
+            choose_sources(&subscriptions(), "discord"),
+            Err(Error::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_sources() {
+        assert!(matches!(
+            choose_sources(&subscriptions(), " , "),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    /// Sets up `kiwitech:smp` subscribed to `kiwitech` and `discord`, and returns the websocket
+    /// route behind the middleware together with a token of `kiwitech` with `scopes`.
+    async fn app(scopes: &[TokenScope]) -> (Router, String) {
+        let app_state = AppState::for_tests().await;
+
+        UserModelController::add_user(
+            AdminPostBody {
+                server_id: "kiwitech".to_string(),
+                server_list: vec!["kiwitech".to_string()],
+                auth_token: "secret".to_string(),
+                allowed_cidrs: None,
+            },
+            &app_state.db_pool,
+        )
+        .await
+        .unwrap();
+        ConfigModelController::add_or_update_config(
+            &"kiwitech:smp".parse().unwrap(),
+            &vec!["kiwitech".to_string(), "discord".to_string()],
+            &SubscriptionTemplates::new(),
+            &app_state.db_pool,
+        )
+        .await
+        .unwrap();
+        let (_, token) = TokenModelController::create_token(
+            TokenCreateBody {
+                server_id: "kiwitech".to_string(),
+                label: "viewer".to_string(),
+                scopes: scopes.to_vec(),
+                expires_at: None,
+                client_ids: None,
+            },
+            &app_state.db_pool,
+        )
+        .await
+        .unwrap();
+
+        // Answers with whether the connection is read-only and what it is subscribed to.
+        let handler = |Extension(token): Extension<AuthenticatedToken>,
+                       Extension(config): Extension<ClientConfig>| async move {
+            format!(
+                "{} {}",
+                token.is_spectator(),
+                config.subscriptions.join(",")
+            )
+        };
+
+        let app = Router::new()
+            .route("/ws", get(handler))
+            .route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                mw_websocket_auth,
+            ))
+            .with_state(app_state)
+            .layer(MockConnectInfo(SocketAddr::from(([203, 0, 113, 7], 4000))));
+
+        (app, token)
+    }
+
+    async fn connect(app: &Router, uri: &str, token: &str) -> (StatusCode, String) {
+        let request = axum::http::Request::builder()
+            .uri(uri)
+            .header("X-Client-ID", "kiwitech:smp")
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn spectators_connect_read_only_with_the_sources_they_chose() {
+        let (app, token) = app(&[TokenScope::Spectate]).await;
+
+        assert_eq!(
+            connect(&app, "/ws", &token).await,
+            (StatusCode::OK, "true kiwitech,discord".to_string())
+        );
+        assert_eq!(
+            connect(&app, "/ws?sources=discord", &token).await,
+            (StatusCode::OK, "true discord".to_string())
+        );
+        assert_eq!(
+            connect(&app, "/ws?sources=other", &token).await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn full_clients_ignore_sources() {
+        let (app, token) = app(&[TokenScope::WsConnect]).await;
+
+        assert_eq!(
+            connect(&app, "/ws?sources=discord", &token).await,
+            (StatusCode::OK, "false kiwitech,discord".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn tokens_without_a_websocket_scope_are_forbidden() {
+        let (app, token) = app(&[TokenScope::ConfigRead]).await;
+
+        assert_eq!(connect(&app, "/ws", &token).await.0, StatusCode::FORBIDDEN);
+    }
 }
@@ -0,0 +1,40 @@
+use axum::http::HeaderValue;
+use axum::response::Response;
+
+/// Marks responses of the unversioned routes as deprecated in favour of `/v1`.
+pub async fn mw_deprecated(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    headers.insert(
+        "Link",
+        HeaderValue::from_static("</v1/openapi.json>; rel=\"successor-version\""),
+    );
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::StatusCode;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn points_to_the_successor_version() {
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap();
+
+        let response = mw_deprecated(response).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["Deprecation"], "true");
+        assert_eq!(
+            response.headers()["Link"],
+            "</v1/openapi.json>; rel=\"successor-version\""
+        );
+    }
+}
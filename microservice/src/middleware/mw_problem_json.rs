@@ -0,0 +1,130 @@
+use axum::body::to_bytes;
+use axum::http::header;
+use axum::response::Response;
+use serde_json::Map;
+
+use crate::error::{problem_response, PROBLEM_JSON};
+
+/// Largest error body that is carried over into the problem's `detail`.
+const MAX_DETAIL_SIZE: usize = 16 * 1024;
+
+/// Turns error responses that are not problems yet, like the rejections of axum's extractors,
+/// into `application/problem+json` with the original body as `detail`.
+pub async fn mw_problem_json(response: Response) -> Response {
+    let status_code = response.status();
+
+    let is_problem = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == PROBLEM_JSON);
+
+    if is_problem || !(status_code.is_client_error() || status_code.is_server_error()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+
+    let detail = to_bytes(body, MAX_DETAIL_SIZE)
+        .await
+        .ok()
+        .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+        .filter(|detail| !detail.is_empty());
+
+    let mut problem = problem_response(status_code, detail, Map::new());
+
+    // The iterator only names a header at its first value, the further values of a repeated
+    // header like `Set-Cookie` follow without a name.
+    let mut last_name = None;
+    for (name, value) in parts.headers {
+        let is_first = name.is_some();
+        last_name = name.or(last_name);
+
+        let Some(name) = last_name
+            .as_ref()
+            .filter(|name| *name != header::CONTENT_TYPE && *name != header::CONTENT_LENGTH)
+        else {
+            continue;
+        };
+
+        if is_first {
+            problem.headers_mut().insert(name, value);
+        } else {
+            problem.headers_mut().append(name, value);
+        }
+    }
+
+    problem
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{HeaderValue, StatusCode};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_every_value_of_repeated_headers() {
+        let response = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::SET_COOKIE, "a=1")
+            .header(header::SET_COOKIE, "b=2")
+            .header(header::VARY, "Origin")
+            .header(header::VARY, "Accept")
+            .body(Body::from("Missing token"))
+            .unwrap();
+
+        let problem = mw_problem_json(response).await;
+        let headers = problem.headers();
+
+        assert_eq!(headers[header::CONTENT_TYPE], PROBLEM_JSON);
+        assert_eq!(
+            headers
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .collect::<Vec<_>>(),
+            [
+                HeaderValue::from_static("a=1"),
+                HeaderValue::from_static("b=2")
+            ]
+        );
+        assert_eq!(
+            headers.get_all(header::VARY).iter().collect::<Vec<_>>(),
+            [
+                HeaderValue::from_static("Origin"),
+                HeaderValue::from_static("Accept")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn carries_the_body_over_as_detail() {
+        let response = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Failed to parse the request body\n"))
+            .unwrap();
+
+        let problem = mw_problem_json(response).await;
+        assert_eq!(problem.status(), StatusCode::BAD_REQUEST);
+
+        let body = to_bytes(problem.into_body(), MAX_DETAIL_SIZE)
+            .await
+            .unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["detail"], "Failed to parse the request body");
+    }
+
+    #[tokio::test]
+    async fn leaves_successes_alone() {
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header(header::SET_COOKIE, "a=1")
+            .body(Body::from("ok"))
+            .unwrap();
+
+        let response = mw_problem_json(response).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_TYPE).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminRole};
+use crate::database_utils::{
+    acquire_connection, generate_secret, hash_token, verify_token, TokenVerification,
+};
+use crate::error::{DbContext, Error, Result};
+
+/// Prefix of admin tokens. The full format is `cba_<id>_<secret>`.
+const ADMIN_TOKEN_PREFIX: &str = "cba_";
+
+pub struct AdminModelController;
+
+impl AdminModelController {
+    pub async fn list_admins(db_pool: &SqlitePool) -> Result<Vec<Admin>> {
+        sqlx::query_as::<_, AdminInDB>("SELECT * FROM admins ORDER BY id;")
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to list admins")?
+            .into_iter()
+            .map(|admin| admin.try_into())
+            .collect()
+    }
+
+    pub async fn add_admin(data: AdminAccountPostBody, db_pool: &SqlitePool) -> Result<Admin> {
+        match (data.role, &data.server_id) {
+            (AdminRole::ServerAdmin, None) => {
+                return Err(Error::BadRequest(
+                    "A server admin needs a server_id".to_string(),
+                ))
+            }
+            (AdminRole::Superadmin | AdminRole::Operator, Some(_)) => {
+                return Err(Error::BadRequest(
+                    "Only server admins can have a server_id".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        sqlx::query_as::<_, AdminInDB>(
+            "INSERT INTO admins (name, role, server_id, created_at) VALUES (?, ?, ?, ?) RETURNING *;",
+        )
+        .bind(data.name)
+        .bind(data.role.as_str())
+        .bind(data.server_id)
+        .bind(Utc::now())
+        .fetch_one(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to add admin")?
+        .try_into()
+    }
+
+    pub async fn delete_admin(id: i64, db_pool: &SqlitePool) -> Result<Admin> {
+        let mut tx = db_pool
+            .begin()
+            .await
+            .db_context("Failed to start transaction")?;
+
+        sqlx::query("DELETE FROM admin_tokens WHERE admin_id = ?;")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .db_context("Failed to delete admin tokens")?;
+
+        let admin = sqlx::query_as::<_, AdminInDB>("DELETE FROM admins WHERE id = ? RETURNING *;")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .db_context("Failed to delete admin")?
+            .map(|admin| admin.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound("Admin not found".to_string()))?;
+
+        tx.commit().await.db_context("Failed to delete admin")?;
+
+        Ok(admin)
+    }
+
+    pub async fn list_admin_tokens(admin_id: i64, db_pool: &SqlitePool) -> Result<Vec<AdminToken>> {
+        sqlx::query_as::<_, AdminTokenInDB>(
+            "SELECT * FROM admin_tokens WHERE admin_id = ? ORDER BY id;",
+        )
+        .bind(admin_id)
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to list admin tokens")
+        .map(|tokens| tokens.into_iter().map(AdminToken::from).collect())
+    }
+
+    /// Creates a new token for an admin and returns it together with its plaintext value.
+    pub async fn create_admin_token(
+        admin_id: i64,
+        data: AdminTokenPostBody,
+        db_pool: &SqlitePool,
+    ) -> Result<(AdminToken, String)> {
+        let secret = generate_secret();
+
+        let token = sqlx::query_as::<_, AdminTokenInDB>(
+            "INSERT INTO admin_tokens (admin_id, label, token_hash, created_at, expires_at) VALUES (?, ?, ?, ?, ?) RETURNING *;",
+        )
+        .bind(admin_id)
+        .bind(data.label)
+        .bind(hash_token(&secret).await?)
+        .bind(Utc::now())
+        .bind(data.expires_at)
+        .fetch_one(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to add admin token")?;
+
+        let plaintext = format!("{ADMIN_TOKEN_PREFIX}{}_{secret}", token.id);
+
+        Ok((token.into(), plaintext))
+    }
+
+    pub async fn revoke_admin_token(
+        admin_id: i64,
+        token_id: i64,
+        db_pool: &SqlitePool,
+    ) -> Result<AdminToken> {
+        sqlx::query_as::<_, AdminTokenInDB>(
+            "UPDATE admin_tokens SET revoked_at = COALESCE(revoked_at, ?) WHERE id = ? AND admin_id = ? RETURNING *;",
+        )
+        .bind(Utc::now())
+        .bind(token_id)
+        .bind(admin_id)
+        .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to revoke admin token")?
+        .map(AdminToken::from)
+        .ok_or_else(|| Error::NotFound("Token not found".to_string()))
+    }
+
+    /// Authenticates an admin token and returns the admin it belongs to.
+    ///
+    /// Returns `None` if the token is malformed, unknown, revoked or expired.
+    pub async fn authenticate(bearer: &str, db_pool: &SqlitePool) -> Result<Option<AdminCtx>> {
+        let Some((token_id, secret)) = parse_admin_token(bearer) else {
+            return Ok(None);
+        };
+
+        let Some(token) =
+            sqlx::query_as::<_, AdminTokenInDB>("SELECT * FROM admin_tokens WHERE id = ?;")
+                .bind(token_id)
+                .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+                .await
+                .db_context("Failed to get admin token")?
+        else {
+            return Ok(None);
+        };
+
+        if token.revoked_at.is_some()
+            || token
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= Utc::now())
+            || verify_token(secret, &token.token_hash, "").await != TokenVerification::Valid
+        {
+            return Ok(None);
+        }
+
+        let admin: Admin = sqlx::query_as::<_, AdminInDB>("SELECT * FROM admins WHERE id = ?;")
+            .bind(token.admin_id)
+            .fetch_one(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to get admin")?
+            .try_into()?;
+
+        sqlx::query("UPDATE admin_tokens SET last_used_at = ? WHERE id = ?;")
+            .bind(Utc::now())
+            .bind(token_id)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to update admin token usage")?;
+
+        Ok(Some(AdminCtx::new(
+            Some(admin.id),
+            admin.name,
+            admin.role,
+            admin.server_id,
+        )))
+    }
+}
+
+fn parse_admin_token(bearer: &str) -> Option<(i64, &str)> {
+    let (id, secret) = bearer.strip_prefix(ADMIN_TOKEN_PREFIX)?.split_once('_')?;
+
+    Some((id.parse().ok()?, secret))
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct AdminAccountPostBody {
+    pub name: String,
+    pub role: AdminRole,
+    pub server_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct AdminTokenPostBody {
+    pub label: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct AdminInDB {
+    pub id: i64,
+    pub name: String,
+    pub role: String,
+    pub server_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Admin {
+    pub id: i64,
+    pub name: String,
+    pub role: AdminRole,
+    pub server_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<AdminInDB> for Admin {
+    type Error = Error;
+
+    fn try_from(admin: AdminInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            id: admin.id,
+            name: admin.name,
+            role: admin.role.parse()?,
+            server_id: admin.server_id,
+            created_at: admin.created_at,
+        })
+    }
+}
+
+/// A newly created admin token together with its secret, which is only ever shown once.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedAdminToken {
+    pub token: AdminToken,
+    pub secret: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct AdminTokenInDB {
+    pub id: i64,
+    pub admin_id: i64,
+    pub label: String,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AdminToken {
+    pub id: i64,
+    pub admin_id: i64,
+    pub label: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<AdminTokenInDB> for AdminToken {
+    fn from(token: AdminTokenInDB) -> Self {
+        Self {
+            id: token.id,
+            admin_id: token.admin_id,
+            label: token.label,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+            expires_at: token.expires_at,
+            revoked_at: token.revoked_at,
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::net::IpAddr;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::database_utils::acquire_connection;
+use crate::error::{DbContext, Error, Result};
+
+/// Default page size of [AuditModelController::list_entries].
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Largest page size of [AuditModelController::list_entries].
+const MAX_LIMIT: u32 = 500;
+
+pub struct AuditModelController;
+
+impl AuditModelController {
+    /// Appends an entry to the audit log.
+    ///
+    /// Failing to write the audit log never fails the change itself, it is only logged.
+    pub async fn record(entry: NewAuditEntry, db_pool: &SqlitePool) {
+        if let Err(e) = Self::insert(&entry, db_pool).await {
+            tracing::error!("Failed to write audit log entry {:?}: {e}", entry);
+        }
+    }
+
+    async fn insert(entry: &NewAuditEntry, db_pool: &SqlitePool) -> Result<()> {
+        sqlx::query("INSERT INTO audit_log (created_at, actor, action, target, server_id, before, after, source_ip) VALUES (?, ?, ?, ?, ?, ?, ?, ?);")
+            .bind(Utc::now())
+            .bind(&entry.actor)
+            .bind(entry.action)
+            .bind(&entry.target)
+            .bind(&entry.server_id)
+            .bind(entry.before.as_ref().map(Value::to_string))
+            .bind(entry.after.as_ref().map(Value::to_string))
+            .bind(entry.source_ip.map(|ip| ip.to_string()))
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to add audit log entry")
+            .map(|_| ())
+    }
+
+    /// Lists audit log entries, newest first.
+    ///
+    /// Pass the `next_cursor` of a page as `cursor` to get the next one.
+    pub async fn list_entries(query: &AuditQuery, db_pool: &SqlitePool) -> Result<AuditPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let sql_query = r#"
+            SELECT * FROM audit_log
+            WHERE ($1 IS NULL OR actor = $1)
+              AND ($2 IS NULL OR action = $2)
+              AND ($3 IS NULL OR target = $3)
+              AND ($4 IS NULL OR server_id = $4)
+              AND ($5 IS NULL OR created_at >= $5)
+              AND ($6 IS NULL OR created_at < $6)
+              AND ($7 IS NULL OR id < $7)
+            ORDER BY id DESC
+            LIMIT $8;
+        "#;
+
+        let entries = sqlx::query_as::<_, AuditEntryInDB>(sql_query)
+            .bind(&query.actor)
+            .bind(&query.action)
+            .bind(&query.target)
+            .bind(&query.server_id)
+            .bind(query.since)
+            .bind(query.until)
+            .bind(query.cursor)
+            .bind(limit)
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to list audit log")?
+            .into_iter()
+            .map(|entry| entry.try_into())
+            .collect::<Result<Vec<AuditEntry>>>()?;
+
+        let next_cursor = if entries.len() == limit as usize {
+            entries.last().map(|entry| entry.id)
+        } else {
+            None
+        };
+
+        Ok(AuditPage {
+            entries,
+            next_cursor,
+        })
+    }
+}
+
+/// [NewAuditEntry] describes a change that is about to be written to the audit log.
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    /// Who made the change, e.g. `admin:alice` or `client:kiwitech:smp`.
+    pub actor: String,
+    /// What was changed, e.g. `user.update`.
+    pub action: &'static str,
+    /// The changed object, e.g. a server ID or an identifier.
+    pub target: String,
+    /// The server the change belongs to, if any. Server admins only see those entries.
+    pub server_id: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub source_ip: Option<IpAddr>,
+}
+
+impl NewAuditEntry {
+    pub fn new(
+        actor: impl Into<String>,
+        action: &'static str,
+        target: impl Into<String>,
+        source_ip: IpAddr,
+    ) -> Self {
+        Self {
+            actor: actor.into(),
+            action,
+            target: target.into(),
+            server_id: None,
+            before: None,
+            after: None,
+            source_ip: Some(source_ip),
+        }
+    }
+
+    pub fn server_id(mut self, server_id: impl Into<String>) -> Self {
+        self.server_id = Some(server_id.into());
+        self
+    }
+
+    /// Sets the state before the change. `None` and `null` leave it unset.
+    pub fn before(mut self, before: impl Serialize) -> Self {
+        self.before = serde_json::to_value(before).ok().filter(|v| !v.is_null());
+        self
+    }
+
+    /// Sets the state after the change. `None` and `null` leave it unset.
+    pub fn after(mut self, after: impl Serialize) -> Self {
+        self.after = serde_json::to_value(after).ok().filter(|v| !v.is_null());
+        self
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub server_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub cursor: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct AuditEntryInDB {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub server_id: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub source_ip: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub server_id: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<Value>,
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<Value>,
+    pub source_ip: Option<String>,
+}
+
+impl TryFrom<AuditEntryInDB> for AuditEntry {
+    type Error = Error;
+
+    fn try_from(entry: AuditEntryInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            id: entry.id,
+            created_at: entry.created_at,
+            actor: entry.actor,
+            action: entry.action,
+            target: entry.target,
+            server_id: entry.server_id,
+            before: entry.before.map(|v| serde_json::from_str(&v)).transpose()?,
+            after: entry.after.map(|v| serde_json::from_str(&v)).transpose()?,
+            source_ip: entry.source_ip,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_cursor: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use serde_json::json;
+
+    use super::*;
+    use crate::database_utils::test_pool;
+
+    async fn record(actor: &str, action: &'static str, server_id: &str, db_pool: &SqlitePool) {
+        AuditModelController::record(
+            NewAuditEntry::new(actor, action, server_id, "127.0.0.1".parse().unwrap())
+                .server_id(server_id),
+            db_pool,
+        )
+        .await;
+    }
+
+    async fn targets(query: AuditQuery, db_pool: &SqlitePool) -> Vec<String> {
+        AuditModelController::list_entries(&query, db_pool)
+            .await
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| format!("{} {} {}", entry.actor, entry.action, entry.target))
+            .collect()
+    }
+
+    #[test]
+    fn null_states_are_left_unset() {
+        let entry =
+            NewAuditEntry::new("admin:alice", "user.add", "kiwitech", [127, 0, 0, 1].into())
+                .before(None::<String>)
+                .after(json!({ "server_id": "kiwitech" }));
+
+        assert!(entry.before.is_none());
+        assert_eq!(entry.after, Some(json!({ "server_id": "kiwitech" })));
+    }
+
+    #[tokio::test]
+    async fn filters_entries_newest_first() {
+        let db_pool = test_pool().await;
+        record("admin:alice", "user.add", "kiwitech", &db_pool).await;
+        record("admin:bob", "user.add", "other", &db_pool).await;
+        record("admin:alice", "user.delete", "other", &db_pool).await;
+
+        assert_eq!(
+            targets(AuditQuery::default(), &db_pool).await,
+            [
+                "admin:alice user.delete other",
+                "admin:bob user.add other",
+                "admin:alice user.add kiwitech"
+            ]
+        );
+        assert_eq!(
+            targets(
+                AuditQuery {
+                    actor: Some("admin:alice".to_string()),
+                    action: Some("user.add".to_string()),
+                    ..Default::default()
+                },
+                &db_pool
+            )
+            .await,
+            ["admin:alice user.add kiwitech"]
+        );
+        assert_eq!(
+            targets(
+                AuditQuery {
+                    server_id: Some("other".to_string()),
+                    ..Default::default()
+                },
+                &db_pool
+            )
+            .await
+            .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_entries_by_time() {
+        let db_pool = test_pool().await;
+        record("admin:alice", "user.add", "kiwitech", &db_pool).await;
+
+        let hour_ago = Utc::now() - Duration::hours(1);
+        let in_an_hour = Utc::now() + Duration::hours(1);
+
+        let since = |since| AuditQuery {
+            since: Some(since),
+            ..Default::default()
+        };
+        let until = |until| AuditQuery {
+            until: Some(until),
+            ..Default::default()
+        };
+
+        assert_eq!(targets(since(hour_ago), &db_pool).await.len(), 1);
+        assert!(targets(since(in_an_hour), &db_pool).await.is_empty());
+        assert_eq!(targets(until(in_an_hour), &db_pool).await.len(), 1);
+        assert!(targets(until(hour_ago), &db_pool).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pages_through_entries_with_the_cursor() {
+        let db_pool = test_pool().await;
+        for server_id in ["a", "b", "c"] {
+            record("admin:alice", "user.add", server_id, &db_pool).await;
+        }
+
+        let first = AuditModelController::list_entries(
+            &AuditQuery {
+                limit: Some(2),
+                ..Default::default()
+            },
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(first.entries.len(), 2);
+        assert_eq!(first.entries[1].target, "b");
+
+        let second = AuditModelController::list_entries(
+            &AuditQuery {
+                limit: Some(2),
+                cursor: first.next_cursor,
+                ..Default::default()
+            },
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.entries[0].target, "a");
+        assert_eq!(second.next_cursor, None);
+    }
+}
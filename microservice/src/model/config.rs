@@ -1,9 +1,13 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use utoipa::ToSchema;
 
 use crate::ctx::ctx_client::Identifier;
 use crate::database_utils::acquire_connection;
+use crate::error::{DbContext, Error, Result};
 
 pub struct ConfigModelController;
 
@@ -11,28 +15,36 @@ impl ConfigModelController {
     pub async fn get_config_by_identifier(
         identifier: &Identifier,
         db_pool: &SqlitePool,
-    ) -> anyhow::Result<ClientConfig> {
+    ) -> Result<ClientConfig> {
         sqlx::query_as::<_, ConfigInDatabase>("SELECT * FROM configs WHERE identifier = ?;")
             .bind(identifier.to_string())
             .fetch_optional(acquire_connection(db_pool).await?.as_mut())
             .await
-            .context("Failed to get config")?
+            .db_context("Failed to get config")?
             .map(|config| config.try_into())
             .transpose()?
-            .ok_or_else(|| {
-                anyhow::anyhow!("No config found for identifier: {}", identifier.to_string())
-            })
+            .ok_or_else(|| Error::NotFound(format!("No config found for identifier: {identifier}")))
     }
 
     pub async fn get_config_by_server_id(
         server_id: &str,
         db_pool: &SqlitePool,
-    ) -> anyhow::Result<Vec<ClientConfig>> {
+    ) -> Result<Vec<ClientConfig>> {
         sqlx::query_as::<_, ConfigInDatabase>("SELECT * FROM configs WHERE server_id = ?;")
             .bind(server_id)
             .fetch_all(acquire_connection(db_pool).await?.as_mut())
             .await
-            .context("Failed to get config")?
+            .db_context("Failed to get config")?
+            .into_iter()
+            .map(|config| config.try_into())
+            .collect()
+    }
+
+    pub async fn list_configs(db_pool: &SqlitePool) -> Result<Vec<ClientConfig>> {
+        sqlx::query_as::<_, ConfigInDatabase>("SELECT * FROM configs ORDER BY identifier;")
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to list configs")?
             .into_iter()
             .map(|config| config.try_into())
             .collect()
@@ -40,54 +52,179 @@ impl ConfigModelController {
 
     pub async fn delete_config_by_server_id(
         server_id: &str,
-        db_pool: &SqlitePool,
-    ) -> anyhow::Result<u64> {
+        conn: &mut SqliteConnection,
+    ) -> Result<u64> {
         sqlx::query("DELETE FROM configs WHERE server_id = ?;")
             .bind(server_id)
-            .execute(acquire_connection(db_pool).await?.as_mut())
+            .execute(conn)
             .await
-            .context("Failed to delete config")
+            .db_context("Failed to delete config")
             .map(|result| result.rows_affected())
     }
 
+    pub async fn delete_config_by_identifier(
+        identifier: &Identifier,
+        db_pool: &SqlitePool,
+    ) -> Result<ClientConfig> {
+        sqlx::query_as::<_, ConfigInDatabase>(
+            "DELETE FROM configs WHERE identifier = ? RETURNING *;",
+        )
+        .bind(identifier.to_string())
+        .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to delete config")?
+        .map(|config| config.try_into())
+        .transpose()?
+        .ok_or_else(|| Error::NotFound(format!("No config found for identifier: {identifier}")))
+    }
+
     pub async fn add_or_update_config(
         identifier: &Identifier,
         subscriptions: &Vec<String>,
+        templates: &SubscriptionTemplates,
         db_pool: &SqlitePool,
-    ) -> anyhow::Result<ClientConfig> {
-        sqlx::query_as::<_, ConfigInDatabase>("INSERT INTO configs (identifier, server_id, client_id, subscriptions) VALUES ($1, $2, $3, $4) ON CONFLICT(identifier) DO UPDATE SET subscriptions = $4 RETURNING *;")
+    ) -> Result<ClientConfig> {
+        sqlx::query_as::<_, ConfigInDatabase>("INSERT INTO configs (identifier, server_id, client_id, subscriptions, templates) VALUES ($1, $2, $3, $4, $5) ON CONFLICT(identifier) DO UPDATE SET subscriptions = $4, templates = $5 RETURNING *;")
             .bind(identifier.to_string())
             .bind(identifier.server_id())
             .bind(identifier.client_id())
             .bind(serde_json::to_string(&subscriptions)?)
+            .bind(serde_json::to_string(&templates)?)
             .fetch_one(acquire_connection(db_pool).await?.as_mut())
             .await
-            .context("Failed to add or update config")?
+            .db_context("Failed to add or update config")?
             .try_into()
     }
 }
 
+/// Checks that a client only subscribes to servers in the `server_list` of its user, and only
+/// defines templates for subscriptions it has.
+pub fn check_subscriptions(
+    identifier: &Identifier,
+    server_list: &[String],
+    subscriptions: &[String],
+    templates: &SubscriptionTemplates,
+) -> Result<()> {
+    if let Some(sub) = subscriptions.iter().find(|sub| !server_list.contains(sub)) {
+        return Err(Error::Forbidden(format!(
+            "Client {identifier} is not allowed to subscribe to {sub}"
+        )));
+    }
+
+    if let Some(sub) = templates.keys().find(|sub| !subscriptions.contains(sub)) {
+        return Err(Error::BadRequest(format!(
+            "Client {identifier} defines templates for {sub} without subscribing to it"
+        )));
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, FromRow, Deserialize)]
 pub struct ConfigInDatabase {
     pub identifier: String,
     pub server_id: String,
     pub client_id: String,
     pub subscriptions: String,
+    pub templates: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Formatting templates per subscription entry and event kind, for example
+/// `{"kiwitech": {"chat": "[{client}] <{player}> {message}"}}`.
+///
+/// See [crate::websocket::template] for the available placeholders.
+pub type SubscriptionTemplates = HashMap<String, HashMap<String, String>>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
 pub struct ClientConfig {
     pub identifier: Identifier,
     pub subscriptions: Vec<String>,
+    /// See [SubscriptionTemplates].
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub templates: SubscriptionTemplates,
 }
 
 impl TryFrom<ConfigInDatabase> for ClientConfig {
-    type Error = anyhow::Error;
+    type Error = Error;
 
-    fn try_from(config: ConfigInDatabase) -> Result<Self, Self::Error> {
+    fn try_from(config: ConfigInDatabase) -> std::result::Result<Self, Self::Error> {
         Ok(ClientConfig {
             identifier: config.identifier.parse()?,
             subscriptions: serde_json::from_str(&config.subscriptions)?,
+            templates: serde_json::from_str(&config.templates)?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_utils::test_pool;
+
+    fn identifier() -> Identifier {
+        "kiwitech:smp".parse().unwrap()
+    }
+
+    fn templates(sub: &str) -> SubscriptionTemplates {
+        HashMap::from([(
+            sub.to_string(),
+            HashMap::from([("chat".to_string(), "<{player}> {message}".to_string())]),
+        )])
+    }
+
+    #[test]
+    fn templates_need_a_matching_subscription() {
+        let server_list = ["kiwitech".to_string(), "discord".to_string()];
+        let subscriptions = ["kiwitech".to_string()];
+
+        assert!(check_subscriptions(
+            &identifier(),
+            &server_list,
+            &subscriptions,
+            &templates("kiwitech")
+        )
+        .is_ok());
+        assert!(matches!(
+            check_subscriptions(
+                &identifier(),
+                &server_list,
+                &subscriptions,
+                &templates("discord")
+            ),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn subscriptions_need_to_be_in_the_server_list() {
+        assert!(matches!(
+            check_subscriptions(
+                &identifier(),
+                &["kiwitech".to_string()],
+                &["other".to_string()],
+                &SubscriptionTemplates::new()
+            ),
+            Err(Error::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn stores_templates_with_the_config() {
+        let db_pool = test_pool().await;
+
+        ConfigModelController::add_or_update_config(
+            &identifier(),
+            &vec!["kiwitech".to_string()],
+            &templates("kiwitech"),
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        let config = ConfigModelController::get_config_by_identifier(&identifier(), &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(config.templates, templates("kiwitech"));
+    }
+}
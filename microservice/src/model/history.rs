@@ -0,0 +1,304 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::query::QueryAs;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{FromRow, Sqlite, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ctx::ctx_client::Identifier;
+use crate::database_utils::acquire_connection;
+use crate::error::{DbContext, Error, Result};
+use crate::message::Event;
+
+/// Default page size of [HistoryModelController::search].
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Largest page size of [HistoryModelController::search].
+const MAX_LIMIT: u32 = 500;
+
+/// The filters of [HistoryQuery], shared by [HistoryModelController::search] and
+/// [HistoryModelController::export_page]. `$1` is the server, `$8` the sequence bound.
+const SEARCH_FILTERS: &str = r#"
+    WHERE server_id = $1
+      AND ($2 IS NULL OR seq IN (SELECT rowid FROM event_history_fts WHERE event_history_fts MATCH $2))
+      AND ($3 IS NULL OR source = $3)
+      AND ($4 IS NULL OR json_extract(event, '$.player') = $4 COLLATE NOCASE)
+      AND ($5 IS NULL OR kind = $5)
+      AND ($6 IS NULL OR created_at >= $6)
+      AND ($7 IS NULL OR created_at < $7)
+"#;
+
+pub struct HistoryModelController;
+
+impl HistoryModelController {
+    /// Stores an event `source` published and returns it with its sequence number.
+    pub async fn record(
+        source: &Identifier,
+        event: &Event,
+        db_pool: &SqlitePool,
+    ) -> Result<HistoryEntry> {
+        sqlx::query_as::<_, HistoryEntryInDB>("INSERT INTO event_history (created_at, source, server_id, kind, event) VALUES (?, ?, ?, ?, ?) RETURNING *;")
+            .bind(Utc::now())
+            .bind(source.to_string())
+            .bind(source.server_id())
+            .bind(event.kind())
+            .bind(serde_json::to_string(event)?)
+            .fetch_one(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to add event to history")?
+            .try_into()
+    }
+
+    /// Lists up to `limit` events with a sequence number above `after`, oldest first.
+    pub async fn list_after(
+        after: i64,
+        limit: u32,
+        db_pool: &SqlitePool,
+    ) -> Result<Vec<HistoryEntry>> {
+        sqlx::query_as::<_, HistoryEntryInDB>(
+            "SELECT * FROM event_history WHERE seq > ? ORDER BY seq LIMIT ?;",
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to list history")?
+        .into_iter()
+        .map(|entry| entry.try_into())
+        .collect()
+    }
+
+    /// Returns the sequence number of the newest event, or 0 if there is none.
+    pub async fn latest_seq(db_pool: &SqlitePool) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>("SELECT COALESCE(MAX(seq), 0) FROM event_history;")
+            .fetch_one(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to get latest history sequence")
+    }
+
+    /// Searches the events of `server_id`, newest first.
+    ///
+    /// Pass the `next_cursor` of a page as `cursor` to get the next one.
+    pub async fn search(
+        server_id: &str,
+        query: &HistoryQuery,
+        db_pool: &SqlitePool,
+    ) -> Result<HistoryPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let sql_query = format!(
+            "SELECT * FROM event_history {SEARCH_FILTERS} AND ($8 IS NULL OR seq < $8) ORDER BY seq DESC LIMIT $9;"
+        );
+
+        let entries = Self::bind_filters(&sql_query, server_id, query)?
+            .bind(query.cursor)
+            .bind(limit)
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to search history")?
+            .into_iter()
+            .map(|entry| entry.try_into())
+            .collect::<Result<Vec<HistoryEntry>>>()?;
+
+        let next_cursor = if entries.len() == limit as usize {
+            entries.last().map(|entry| entry.seq)
+        } else {
+            None
+        };
+
+        Ok(HistoryPage {
+            entries,
+            next_cursor,
+        })
+    }
+
+    /// Lists up to `limit` events of `server_id` matching `query` with a sequence number above
+    /// `after`, oldest first. `cursor` and `limit` of `query` are ignored.
+    pub async fn export_page(
+        server_id: &str,
+        query: &HistoryQuery,
+        after: i64,
+        limit: u32,
+        db_pool: &SqlitePool,
+    ) -> Result<Vec<HistoryEntry>> {
+        let sql_query = format!(
+            "SELECT * FROM event_history {SEARCH_FILTERS} AND seq > $8 ORDER BY seq LIMIT $9;"
+        );
+
+        Self::bind_filters(&sql_query, server_id, query)?
+            .bind(after)
+            .bind(limit)
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to export history")?
+            .into_iter()
+            .map(|entry| entry.try_into())
+            .collect()
+    }
+
+    fn bind_filters<'q>(
+        sql_query: &'q str,
+        server_id: &'q str,
+        query: &'q HistoryQuery,
+    ) -> Result<QueryAs<'q, Sqlite, HistoryEntryInDB, SqliteArguments<'q>>> {
+        Ok(sqlx::query_as::<_, HistoryEntryInDB>(sql_query)
+            .bind(server_id)
+            .bind(query.text_query()?)
+            .bind(&query.source)
+            .bind(&query.player)
+            .bind(&query.kind)
+            .bind(query.since)
+            .bind(query.until))
+    }
+
+    /// Deletes events older than `before`. Returns how many were deleted.
+    pub async fn prune(before: DateTime<Utc>, db_pool: &SqlitePool) -> Result<u64> {
+        sqlx::query("DELETE FROM event_history WHERE created_at < ?;")
+            .bind(before)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to prune history")
+            .map(|result| result.rows_affected())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct HistoryQuery {
+    /// Words that all have to appear in the message, in any order. Wrap words in double quotes
+    /// to search for them as a phrase.
+    pub q: Option<String>,
+    /// `server_id:client_id` the event was published by.
+    pub source: Option<String>,
+    /// The player the event is about, ignoring case.
+    pub player: Option<String>,
+    /// The `kind` of the event, e.g. `chat`.
+    pub kind: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub cursor: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+impl HistoryQuery {
+    /// Turns `q` into an FTS5 query that matches its words or quoted phrases literally, so
+    /// that no FTS5 syntax can be injected.
+    fn text_query(&self) -> Result<Option<String>> {
+        let Some(q) = self.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) else {
+            return Ok(None);
+        };
+
+        if q.matches('"').count() % 2 != 0 {
+            return Err(Error::BadRequest("Unbalanced quotes in q".to_string()));
+        }
+
+        let terms = q
+            .split('"')
+            .enumerate()
+            .flat_map(|(i, part)| {
+                // Every odd part was inside quotes and is kept together as a phrase.
+                if i % 2 == 1 {
+                    vec![part]
+                } else {
+                    part.split_whitespace().collect()
+                }
+            })
+            .filter(|term| !term.trim().is_empty())
+            .map(|term| format!("\"{term}\""))
+            .collect::<Vec<_>>();
+
+        Ok((!terms.is_empty()).then(|| terms.join(" ")))
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct HistoryEntryInDB {
+    pub seq: i64,
+    pub created_at: DateTime<Utc>,
+    pub source: String,
+    pub server_id: String,
+    pub kind: String,
+    pub event: String,
+}
+
+/// [HistoryEntry] is an event as it was published, numbered in the order it was stored.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub seq: i64,
+    pub created_at: DateTime<Utc>,
+    pub source: Identifier,
+    pub event: Event,
+}
+
+impl TryFrom<HistoryEntryInDB> for HistoryEntry {
+    type Error = Error;
+
+    fn try_from(entry: HistoryEntryInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            seq: entry.seq,
+            created_at: entry.created_at,
+            source: entry.source.parse()?,
+            event: serde_json::from_str(&entry.event)?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryPage {
+    pub entries: Vec<HistoryEntry>,
+    pub next_cursor: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_query(q: &str) -> Result<Option<String>> {
+        HistoryQuery {
+            q: Some(q.to_string()),
+            ..Default::default()
+        }
+        .text_query()
+    }
+
+    #[test]
+    fn text_query_quotes_every_word() {
+        assert_eq!(
+            text_query("hello  world").unwrap().as_deref(),
+            Some("\"hello\" \"world\"")
+        );
+    }
+
+    #[test]
+    fn text_query_keeps_phrases_together() {
+        assert_eq!(
+            text_query("find \"the diamonds\" now").unwrap().as_deref(),
+            Some("\"find\" \"the diamonds\" \"now\"")
+        );
+    }
+
+    #[test]
+    fn text_query_neutralises_fts_syntax() {
+        assert_eq!(
+            text_query("a OR b* NEAR(c) -d message:e")
+                .unwrap()
+                .as_deref(),
+            Some("\"a\" \"OR\" \"b*\" \"NEAR(c)\" \"-d\" \"message:e\"")
+        );
+    }
+
+    #[test]
+    fn text_query_without_terms_is_none() {
+        assert_eq!(text_query("").unwrap(), None);
+        assert_eq!(text_query("   ").unwrap(), None);
+        assert_eq!(text_query("\" \"").unwrap(), None);
+        assert_eq!(HistoryQuery::default().text_query().unwrap(), None);
+    }
+
+    #[test]
+    fn text_query_rejects_unbalanced_quotes() {
+        assert!(matches!(
+            text_query("\"open phrase"),
+            Err(Error::BadRequest(_))
+        ));
+    }
+}
@@ -1,2 +1,9 @@
+pub mod admin;
+pub mod audit;
 pub mod config;
+pub mod history;
+pub mod status;
+pub mod token;
+pub mod transfer;
 pub mod user;
+pub mod webhook;
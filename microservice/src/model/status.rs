@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_client::Identifier;
+use crate::database_utils::acquire_connection;
+use crate::error::{DbContext, Error, Result};
+
+pub struct StatusModelController;
+
+impl StatusModelController {
+    pub async fn record_connected(identifier: &Identifier, db_pool: &SqlitePool) -> Result<()> {
+        sqlx::query("INSERT INTO client_status (identifier, last_connected_at) VALUES ($1, $2) ON CONFLICT(identifier) DO UPDATE SET last_connected_at = $2;")
+            .bind(identifier.to_string())
+            .bind(Utc::now())
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to record connect")
+            .map(|_| ())
+    }
+
+    pub async fn record_disconnected(
+        identifier: &Identifier,
+        reason: &str,
+        db_pool: &SqlitePool,
+    ) -> Result<()> {
+        sqlx::query("INSERT INTO client_status (identifier, last_disconnected_at, disconnect_reason) VALUES ($1, $2, $3) ON CONFLICT(identifier) DO UPDATE SET last_disconnected_at = $2, disconnect_reason = $3;")
+            .bind(identifier.to_string())
+            .bind(Utc::now())
+            .bind(reason)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to record disconnect")
+            .map(|_| ())
+    }
+
+    /// Returns the recorded status of every client that ever connected, keyed by identifier.
+    pub async fn list_statuses(db_pool: &SqlitePool) -> Result<HashMap<String, ClientStatusInDB>> {
+        sqlx::query_as::<_, ClientStatusInDB>("SELECT * FROM client_status;")
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to list client status")
+            .map(|statuses| {
+                statuses
+                    .into_iter()
+                    .map(|status| (status.identifier.clone(), status))
+                    .collect()
+            })
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ClientStatusInDB {
+    pub identifier: String,
+    pub last_connected_at: Option<DateTime<Utc>>,
+    pub last_disconnected_at: Option<DateTime<Utc>>,
+    pub disconnect_reason: Option<String>,
+}
+
+/// [ClientStatus] combines a configured client with its live and recorded connection state.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClientStatus {
+    pub identifier: Identifier,
+    pub online: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected_since: Option<DateTime<Utc>>,
+    pub last_connected_at: Option<DateTime<Utc>>,
+    pub last_disconnected_at: Option<DateTime<Utc>>,
+    pub disconnect_reason: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_utils::test_pool;
+
+    #[tokio::test]
+    async fn keeps_the_last_connect_and_disconnect_of_each_client() {
+        let db_pool = test_pool().await;
+        let smp = "kiwitech:smp".parse::<Identifier>().unwrap();
+        let cmp = "kiwitech:cmp".parse::<Identifier>().unwrap();
+
+        StatusModelController::record_connected(&smp, &db_pool)
+            .await
+            .unwrap();
+        StatusModelController::record_disconnected(&smp, "connection lost", &db_pool)
+            .await
+            .unwrap();
+        StatusModelController::record_connected(&smp, &db_pool)
+            .await
+            .unwrap();
+        StatusModelController::record_disconnected(&cmp, "closed by client", &db_pool)
+            .await
+            .unwrap();
+
+        let statuses = StatusModelController::list_statuses(&db_pool)
+            .await
+            .unwrap();
+        assert_eq!(statuses.len(), 2);
+
+        let smp = &statuses["kiwitech:smp"];
+        assert!(smp.last_connected_at.unwrap() >= smp.last_disconnected_at.unwrap());
+        assert_eq!(smp.disconnect_reason.as_deref(), Some("connection lost"));
+
+        let cmp = &statuses["kiwitech:cmp"];
+        assert!(cmp.last_connected_at.is_none());
+        assert_eq!(cmp.disconnect_reason.as_deref(), Some("closed by client"));
+    }
+}
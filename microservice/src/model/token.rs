@@ -0,0 +1,600 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_client::Identifier;
+use crate::database_utils::{
+    acquire_connection, generate_secret, hash_token, verify_token, TokenVerification,
+};
+use crate::error::{DbContext, Error, Result};
+use crate::model::user::UserModelController;
+
+/// Prefix of named API tokens. The full format is `cbt_<id>_<secret>`.
+const TOKEN_PREFIX: &str = "cbt_";
+
+pub struct TokenModelController;
+
+impl TokenModelController {
+    /// Creates a new token and returns it together with its plaintext value.
+    ///
+    /// The plaintext is never stored and can't be retrieved again.
+    pub async fn create_token(
+        data: TokenCreateBody,
+        db_pool: &SqlitePool,
+    ) -> Result<(ApiToken, String)> {
+        if data.scopes.is_empty() {
+            return Err(Error::BadRequest(
+                "A token needs at least one scope".to_string(),
+            ));
+        }
+
+        let secret = generate_secret();
+
+        let token: ApiToken = sqlx::query_as::<_, ApiTokenInDB>(
+            "INSERT INTO tokens (server_id, label, token_hash, scopes, created_at, expires_at, client_ids) VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING *;",
+        )
+        .bind(&data.server_id)
+        .bind(&data.label)
+        .bind(hash_token(&secret).await?)
+        .bind(serde_json::to_string(&data.scopes)?)
+        .bind(Utc::now())
+        .bind(data.expires_at)
+        .bind(
+            data.client_ids
+                .map(|client_ids| serde_json::to_string(&client_ids))
+                .transpose()?,
+        )
+        .fetch_one(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to add token")?
+        .try_into()?;
+
+        let plaintext = format!("{TOKEN_PREFIX}{}_{secret}", token.id);
+
+        Ok((token, plaintext))
+    }
+
+    pub async fn get_token(id: i64, db_pool: &SqlitePool) -> Result<ApiToken> {
+        sqlx::query_as::<_, ApiTokenInDB>("SELECT * FROM tokens WHERE id = ?;")
+            .bind(id)
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to get token")?
+            .map(|token| token.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound("Token not found".to_string()))
+    }
+
+    pub async fn list_tokens(
+        server_id: Option<&str>,
+        db_pool: &SqlitePool,
+    ) -> Result<Vec<ApiToken>> {
+        sqlx::query_as::<_, ApiTokenInDB>(
+            "SELECT * FROM tokens WHERE $1 IS NULL OR server_id = $1 ORDER BY id;",
+        )
+        .bind(server_id)
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to list tokens")?
+        .into_iter()
+        .map(|token| token.try_into())
+        .collect()
+    }
+
+    pub async fn update_token(
+        id: i64,
+        data: TokenUpdateBody,
+        db_pool: &SqlitePool,
+    ) -> Result<ApiToken> {
+        if data.scopes.as_ref().is_some_and(|scopes| scopes.is_empty()) {
+            return Err(Error::BadRequest(
+                "A token needs at least one scope".to_string(),
+            ));
+        }
+
+        let scopes = data
+            .scopes
+            .map(|scopes| serde_json::to_string(&scopes))
+            .transpose()?;
+
+        let client_ids = data
+            .client_ids
+            .as_ref()
+            .map(|client_ids| client_ids.as_ref().map(serde_json::to_string).transpose())
+            .transpose()?;
+
+        let sql_query = r#"
+            UPDATE tokens SET
+                label = CASE WHEN $1 IS NOT NULL THEN $1 ELSE label END,
+                scopes = CASE WHEN $2 IS NOT NULL THEN $2 ELSE scopes END,
+                expires_at = CASE WHEN $3 THEN $4 ELSE expires_at END,
+                client_ids = CASE WHEN $5 THEN $6 ELSE client_ids END
+            WHERE id = $7
+            RETURNING *;
+        "#;
+
+        sqlx::query_as::<_, ApiTokenInDB>(sql_query)
+            .bind(data.label)
+            .bind(scopes)
+            .bind(data.expires_at.is_some())
+            .bind(data.expires_at.flatten())
+            .bind(client_ids.is_some())
+            .bind(client_ids.flatten())
+            .bind(id)
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to update token")?
+            .map(|token| token.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound("Token not found".to_string()))
+    }
+
+    /// Revokes a token. Revoked tokens are kept for reference but never authenticate again.
+    pub async fn revoke_token(id: i64, db_pool: &SqlitePool) -> Result<ApiToken> {
+        sqlx::query_as::<_, ApiTokenInDB>(
+            "UPDATE tokens SET revoked_at = COALESCE(revoked_at, ?) WHERE id = ? RETURNING *;",
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to revoke token")?
+        .map(|token| token.try_into())
+        .transpose()?
+        .ok_or_else(|| Error::NotFound("Token not found".to_string()))
+    }
+
+    pub async fn delete_tokens_by_server_id(
+        server_id: &str,
+        conn: &mut SqliteConnection,
+    ) -> Result<u64> {
+        sqlx::query("DELETE FROM tokens WHERE server_id = ?;")
+            .bind(server_id)
+            .execute(conn)
+            .await
+            .db_context("Failed to delete tokens")
+            .map(|result| result.rows_affected())
+    }
+
+    /// Authenticates a bearer token for the client `identifier`.
+    ///
+    /// Named tokens (`cbt_<id>_<secret>`) are checked against the `tokens` table. Anything else is
+    /// checked against the primary token of the user, which has every scope and is valid for
+    /// every client of the server.
+    ///
+    /// Returns `None` if the token is invalid, revoked, expired or bound to other clients.
+    pub async fn authenticate(
+        identifier: &Identifier,
+        bearer: &str,
+        legacy_salt: &str,
+        db_pool: &SqlitePool,
+    ) -> Result<Option<AuthenticatedToken>> {
+        let server_id = identifier.server_id();
+
+        let Some((id, secret)) = parse_token(bearer) else {
+            let is_valid =
+                UserModelController::verify_auth_token(server_id, bearer, legacy_salt, db_pool)
+                    .await?;
+
+            return Ok(is_valid.then(AuthenticatedToken::primary));
+        };
+
+        let Some(token) = sqlx::query_as::<_, ApiTokenInDB>("SELECT * FROM tokens WHERE id = ?;")
+            .bind(id)
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to get token")?
+        else {
+            return Ok(None);
+        };
+
+        if token.server_id != server_id
+            || token.revoked_at.is_some()
+            || token
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= Utc::now())
+            || verify_token(secret, &token.token_hash, "").await != TokenVerification::Valid
+        {
+            return Ok(None);
+        }
+
+        let token = ApiToken::try_from(token)?;
+
+        if !token.is_valid_for(identifier) {
+            tracing::error!(
+                "Token {} of {} is not valid for {}",
+                token.id,
+                server_id,
+                identifier
+            );
+            return Ok(None);
+        }
+
+        sqlx::query("UPDATE tokens SET last_used_at = ? WHERE id = ?;")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to update token usage")?;
+
+        Ok(Some(AuthenticatedToken {
+            token_id: Some(token.id),
+            scopes: token.scopes,
+        }))
+    }
+}
+
+fn parse_token(bearer: &str) -> Option<(i64, &str)> {
+    let (id, secret) = bearer.strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+
+    Some((id.parse().ok()?, secret))
+}
+
+/// What a token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Connect to the websocket and talk to other clients through the bridge.
+    WsConnect,
+    /// Read configs and presence.
+    ConfigRead,
+    /// Change configs.
+    ConfigWrite,
+    /// Connect to the websocket read-only and receive events without publishing any.
+    /// [TokenScope::WsConnect] includes it.
+    Spectate,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 4] = [
+        TokenScope::WsConnect,
+        TokenScope::ConfigRead,
+        TokenScope::ConfigWrite,
+        TokenScope::Spectate,
+    ];
+}
+
+/// [AuthenticatedToken] is the token a request was authenticated with.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedToken {
+    /// The id of the named token, or `None` for the primary token of the user.
+    pub token_id: Option<i64>,
+    pub scopes: Vec<TokenScope>,
+}
+
+impl AuthenticatedToken {
+    fn primary() -> Self {
+        Self {
+            token_id: None,
+            scopes: TokenScope::ALL.to_vec(),
+        }
+    }
+
+    pub fn has_scope(&self, scope: TokenScope) -> bool {
+        self.scopes.contains(&scope)
+            || (scope == TokenScope::Spectate && self.scopes.contains(&TokenScope::WsConnect))
+    }
+
+    /// Returns true if the token may only connect to the websocket as a spectator.
+    pub fn is_spectator(&self) -> bool {
+        !self.has_scope(TokenScope::WsConnect) && self.has_scope(TokenScope::Spectate)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct TokenCreateBody {
+    pub server_id: String,
+    pub label: String,
+    pub scopes: Vec<TokenScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The clients the token may act as. Omit it for a server-wide token.
+    pub client_ids: Option<Vec<String>>,
+}
+
+/// Changes to a token. Missing fields are kept.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct TokenUpdateBody {
+    pub label: Option<String>,
+    pub scopes: Option<Vec<TokenScope>>,
+    /// `null` removes the expiry.
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    #[schema(value_type = Option<DateTime<Utc>>, nullable)]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    /// `null` makes the token valid for the whole server again.
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    #[schema(value_type = Option<Vec<String>>, nullable)]
+    pub client_ids: Option<Option<Vec<String>>>,
+}
+
+/// A newly created token together with its secret, which is only ever shown once.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedToken {
+    pub token: ApiToken,
+    pub secret: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct ApiTokenInDB {
+    pub id: i64,
+    pub server_id: String,
+    pub label: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub client_ids: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiToken {
+    pub id: i64,
+    pub server_id: String,
+    pub label: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// The clients the token may act as, or `None` if it is valid for the whole server.
+    pub client_ids: Option<Vec<String>>,
+}
+
+impl ApiToken {
+    pub fn is_valid_for(&self, identifier: &Identifier) -> bool {
+        self.server_id == identifier.server_id()
+            && self
+                .client_ids
+                .as_ref()
+                .is_none_or(|client_ids| client_ids.iter().any(|id| id == identifier.client_id()))
+    }
+}
+
+impl TryFrom<ApiTokenInDB> for ApiToken {
+    type Error = Error;
+
+    fn try_from(token: ApiTokenInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            id: token.id,
+            server_id: token.server_id,
+            label: token.label,
+            scopes: serde_json::from_str(&token.scopes)?,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+            expires_at: token.expires_at,
+            revoked_at: token.revoked_at,
+            client_ids: token
+                .client_ids
+                .map(|client_ids| serde_json::from_str(&client_ids))
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::database_utils::test_pool;
+
+    fn identifier(identifier: &str) -> Identifier {
+        identifier.parse().unwrap()
+    }
+
+    fn create_body(scopes: &[TokenScope]) -> TokenCreateBody {
+        TokenCreateBody {
+            server_id: "kiwitech".to_string(),
+            label: "bot".to_string(),
+            scopes: scopes.to_vec(),
+            expires_at: None,
+            client_ids: None,
+        }
+    }
+
+    async fn authenticate(bearer: &str, db_pool: &SqlitePool) -> Option<AuthenticatedToken> {
+        TokenModelController::authenticate(&identifier("kiwitech:smp"), bearer, "salt", db_pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_named_tokens() {
+        assert_eq!(parse_token("cbt_12_abc_def"), Some((12, "abc_def")));
+        assert_eq!(parse_token("cbt_x_abc"), None);
+        assert_eq!(parse_token("cbt_12"), None);
+        assert_eq!(parse_token("12_abc"), None);
+        assert_eq!(parse_token("legacy-primary-token"), None);
+    }
+
+    #[test]
+    fn ws_connect_includes_spectate() {
+        let connect = AuthenticatedToken {
+            token_id: Some(1),
+            scopes: vec![TokenScope::WsConnect],
+        };
+        assert!(connect.has_scope(TokenScope::Spectate));
+        assert!(!connect.has_scope(TokenScope::ConfigRead));
+        assert!(!connect.is_spectator());
+
+        let spectate = AuthenticatedToken {
+            token_id: Some(1),
+            scopes: vec![TokenScope::Spectate],
+        };
+        assert!(!spectate.has_scope(TokenScope::WsConnect));
+        assert!(spectate.is_spectator());
+
+        assert!(TokenScope::ALL
+            .iter()
+            .all(|scope| AuthenticatedToken::primary().has_scope(*scope)));
+        assert!(!AuthenticatedToken::primary().is_spectator());
+    }
+
+    #[tokio::test]
+    async fn authenticates_named_tokens_with_their_scopes() {
+        let db_pool = test_pool().await;
+        let (token, plaintext) =
+            TokenModelController::create_token(create_body(&[TokenScope::ConfigRead]), &db_pool)
+                .await
+                .unwrap();
+
+        let authenticated = authenticate(&plaintext, &db_pool).await.unwrap();
+        assert_eq!(authenticated.token_id, Some(token.id));
+        assert_eq!(authenticated.scopes, [TokenScope::ConfigRead]);
+
+        let used = TokenModelController::get_token(token.id, &db_pool)
+            .await
+            .unwrap();
+        assert!(used.last_used_at.is_some());
+
+        let wrong_secret = format!("{TOKEN_PREFIX}{}_guess", token.id);
+        assert!(authenticate(&wrong_secret, &db_pool).await.is_none());
+        assert!(authenticate("cbt_999_guess", &db_pool).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_of_other_servers() {
+        let db_pool = test_pool().await;
+        let (_, plaintext) =
+            TokenModelController::create_token(create_body(&[TokenScope::WsConnect]), &db_pool)
+                .await
+                .unwrap();
+
+        let authenticated = TokenModelController::authenticate(
+            &identifier("other:smp"),
+            &plaintext,
+            "salt",
+            &db_pool,
+        )
+        .await
+        .unwrap();
+
+        assert!(authenticated.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_revoked_and_expired_tokens() {
+        let db_pool = test_pool().await;
+        let (token, plaintext) =
+            TokenModelController::create_token(create_body(&[TokenScope::WsConnect]), &db_pool)
+                .await
+                .unwrap();
+
+        TokenModelController::revoke_token(token.id, &db_pool)
+            .await
+            .unwrap();
+        assert!(authenticate(&plaintext, &db_pool).await.is_none());
+
+        let expired = TokenCreateBody {
+            label: "expired".to_string(),
+            expires_at: Some(Utc::now() - Duration::minutes(1)),
+            ..create_body(&[TokenScope::WsConnect])
+        };
+        let (_, plaintext) = TokenModelController::create_token(expired, &db_pool)
+            .await
+            .unwrap();
+        assert!(authenticate(&plaintext, &db_pool).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn updates_can_set_and_remove_the_expiry() {
+        let db_pool = test_pool().await;
+        let (token, _) =
+            TokenModelController::create_token(create_body(&[TokenScope::WsConnect]), &db_pool)
+                .await
+                .unwrap();
+        let update: TokenUpdateBody = serde_json::from_value(serde_json::json!({
+            "expires_at": "2030-01-01T00:00:00Z",
+        }))
+        .unwrap();
+
+        let token = TokenModelController::update_token(token.id, update, &db_pool)
+            .await
+            .unwrap();
+        assert!(token.expires_at.is_some());
+
+        let keep: TokenUpdateBody =
+            serde_json::from_value(serde_json::json!({ "label": "renamed" })).unwrap();
+        let token = TokenModelController::update_token(token.id, keep, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(token.label, "renamed");
+        assert!(token.expires_at.is_some());
+
+        let remove: TokenUpdateBody =
+            serde_json::from_value(serde_json::json!({ "expires_at": null })).unwrap();
+        let token = TokenModelController::update_token(token.id, remove, &db_pool)
+            .await
+            .unwrap();
+        assert!(token.expires_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn tokens_need_a_scope() {
+        let db_pool = test_pool().await;
+
+        assert!(matches!(
+            TokenModelController::create_token(create_body(&[]), &db_pool).await,
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn client_ids_narrow_a_token_to_some_clients() {
+        let token = |client_ids: Option<&[&str]>| ApiToken {
+            id: 1,
+            server_id: "kiwitech".to_string(),
+            label: "bot".to_string(),
+            scopes: vec![TokenScope::WsConnect],
+            created_at: Utc::now(),
+            last_used_at: None,
+            expires_at: None,
+            revoked_at: None,
+            client_ids: client_ids.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+        };
+
+        let server_wide = token(None);
+        assert!(server_wide.is_valid_for(&identifier("kiwitech:smp")));
+        assert!(server_wide.is_valid_for(&identifier("kiwitech:cmp")));
+        assert!(!server_wide.is_valid_for(&identifier("other:smp")));
+
+        let bound = token(Some(&["smp"]));
+        assert!(bound.is_valid_for(&identifier("kiwitech:smp")));
+        assert!(!bound.is_valid_for(&identifier("kiwitech:cmp")));
+        assert!(!bound.is_valid_for(&identifier("other:smp")));
+    }
+
+    #[tokio::test]
+    async fn bound_tokens_only_authenticate_their_clients_until_made_server_wide() {
+        let db_pool = test_pool().await;
+        let bound = TokenCreateBody {
+            client_ids: Some(vec!["cmp".to_string()]),
+            ..create_body(&[TokenScope::WsConnect])
+        };
+        let (token, plaintext) = TokenModelController::create_token(bound, &db_pool)
+            .await
+            .unwrap();
+
+        assert!(authenticate(&plaintext, &db_pool).await.is_none());
+        assert!(TokenModelController::authenticate(
+            &identifier("kiwitech:cmp"),
+            &plaintext,
+            "salt",
+            &db_pool
+        )
+        .await
+        .unwrap()
+        .is_some());
+
+        let server_wide: TokenUpdateBody =
+            serde_json::from_value(serde_json::json!({ "client_ids": null })).unwrap();
+        let token = TokenModelController::update_token(token.id, server_wide, &db_pool)
+            .await
+            .unwrap();
+        assert!(token.client_ids.is_none());
+        assert!(authenticate(&plaintext, &db_pool).await.is_some());
+    }
+}
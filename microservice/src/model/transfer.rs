@@ -0,0 +1,1157 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ctx::ctx_client::Identifier;
+use crate::database_utils::acquire_connection;
+use crate::error::{DbContext, Error, Result};
+use crate::model::config::{check_subscriptions, ClientConfig, ConfigInDatabase};
+use crate::model::token::{ApiTokenInDB, TokenScope};
+use crate::model::user::UserInDB;
+use crate::model::webhook::WebhookInDB;
+
+/// Version of the [ExportDocument] format. Imports of other versions are rejected.
+pub const EXPORT_VERSION: u32 = 1;
+
+pub struct TransferModelController;
+
+impl TransferModelController {
+    /// Exports every user and config.
+    ///
+    /// Links between clients are the `server_list` of the users and the `subscriptions` of the
+    /// configs, so they are part of the export. Token hashes are left out unless
+    /// `include_token_hashes` is set, and users without one can only be imported over an
+    /// existing user. Named tokens are only exported with their hashes. Webhooks are always
+    /// exported, but their secrets only with the token hashes.
+    pub async fn export(
+        include_token_hashes: bool,
+        db_pool: &SqlitePool,
+    ) -> Result<ExportDocument> {
+        let mut conn = acquire_connection(db_pool).await?;
+
+        let users = Self::list_users_with_hashes(conn.as_mut())
+            .await?
+            .into_iter()
+            .map(|user| {
+                let mut user = ExportedUser::try_from(user)?;
+
+                if !include_token_hashes {
+                    user.auth_token_hash = None;
+                }
+
+                Ok(user)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let webhooks = Self::list_webhooks_with_secrets(conn.as_mut())
+            .await?
+            .into_iter()
+            .map(|mut webhook| {
+                if !include_token_hashes {
+                    webhook.secret = None;
+                }
+
+                webhook
+            })
+            .collect();
+
+        let tokens = match include_token_hashes {
+            true => Some(Self::list_tokens_with_hashes(conn.as_mut()).await?),
+            false => None,
+        };
+
+        Ok(ExportDocument {
+            version: EXPORT_VERSION,
+            exported_at: Utc::now(),
+            users,
+            configs: Self::list_configs(conn.as_mut()).await?,
+            webhooks,
+            tokens,
+        })
+    }
+
+    /// Imports `document` according to `options`.
+    ///
+    /// Conflicts are collected before anything is written. The import is only applied if there
+    /// are none and it is not a dry run. The current state is read in the same transaction that
+    /// applies the import, so nothing can change in between.
+    pub async fn import(
+        document: ExportDocument,
+        options: &ImportOptions,
+        db_pool: &SqlitePool,
+    ) -> Result<ImportReport> {
+        if document.version != EXPORT_VERSION {
+            return Err(Error::BadRequest(format!(
+                "Unsupported export version {}, expected {EXPORT_VERSION}",
+                document.version
+            )));
+        }
+
+        let mut tx = db_pool
+            .begin()
+            .await
+            .db_context("Failed to start transaction")?;
+
+        // sqlx only starts deferred transactions. A write that changes nothing takes the write
+        // lock right away, like `BEGIN IMMEDIATE`, so no other write can slip in after the reads.
+        sqlx::query("DELETE FROM users WHERE 0;")
+            .execute(&mut *tx)
+            .await
+            .db_context("Failed to lock the database")?;
+
+        let existing_users = Self::list_users_with_hashes(&mut tx)
+            .await?
+            .into_iter()
+            .map(|user| ExportedUser::try_from(user).map(|user| (user.server_id.clone(), user)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let existing_configs = Self::list_configs(&mut tx)
+            .await?
+            .into_iter()
+            .map(|config| (config.identifier.clone(), config))
+            .collect::<HashMap<_, _>>();
+
+        let existing_tokens = Self::list_tokens_with_hashes(&mut tx)
+            .await?
+            .into_iter()
+            .map(|token| (token.id, token))
+            .collect::<HashMap<_, _>>();
+
+        let existing_webhooks = Self::list_webhooks_with_secrets(&mut tx)
+            .await?
+            .into_iter()
+            .map(|webhook| (webhook.identifier.clone(), webhook))
+            .collect::<HashMap<_, _>>();
+
+        let existing = ExistingState {
+            users: existing_users,
+            configs: existing_configs,
+            webhooks: existing_webhooks,
+            tokens: existing_tokens,
+        };
+        let plan = ImportPlan::new(&document, &existing, options);
+
+        // Dropping the transaction rolls it back.
+        if !plan.report.conflicts.is_empty() || options.dry_run {
+            return Ok(plan.report);
+        }
+
+        let report = plan.apply(&document, &existing, &mut tx).await?;
+        tx.commit().await.db_context("Failed to import")?;
+
+        Ok(report)
+    }
+
+    async fn list_users_with_hashes(conn: &mut SqliteConnection) -> Result<Vec<UserInDB>> {
+        sqlx::query_as::<_, UserInDB>("SELECT * FROM users ORDER BY server_id;")
+            .fetch_all(conn)
+            .await
+            .db_context("Failed to list users")
+    }
+
+    async fn list_configs(conn: &mut SqliteConnection) -> Result<Vec<ClientConfig>> {
+        sqlx::query_as::<_, ConfigInDatabase>("SELECT * FROM configs ORDER BY identifier;")
+            .fetch_all(conn)
+            .await
+            .db_context("Failed to list configs")?
+            .into_iter()
+            .map(|config| config.try_into())
+            .collect()
+    }
+
+    async fn list_webhooks_with_secrets(
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<ExportedWebhook>> {
+        sqlx::query_as::<_, WebhookInDB>("SELECT * FROM webhooks ORDER BY identifier;")
+            .fetch_all(conn)
+            .await
+            .db_context("Failed to list webhooks")?
+            .into_iter()
+            .map(|webhook| webhook.try_into())
+            .collect()
+    }
+
+    async fn list_tokens_with_hashes(conn: &mut SqliteConnection) -> Result<Vec<ExportedToken>> {
+        sqlx::query_as::<_, ApiTokenInDB>("SELECT * FROM tokens ORDER BY id;")
+            .fetch_all(conn)
+            .await
+            .db_context("Failed to list tokens")?
+            .into_iter()
+            .map(|token| token.try_into())
+            .collect()
+    }
+}
+
+/// [ExistingState] is what is in the database before an import.
+struct ExistingState {
+    users: HashMap<String, ExportedUser>,
+    configs: HashMap<Identifier, ClientConfig>,
+    webhooks: HashMap<Identifier, ExportedWebhook>,
+    tokens: HashMap<i64, ExportedToken>,
+}
+
+/// [ImportPlan] is what an import would change, worked out without touching the database.
+struct ImportPlan {
+    report: ImportReport,
+    /// Users to create or overwrite.
+    upsert_users: Vec<String>,
+    /// Configs to create or overwrite.
+    upsert_configs: Vec<Identifier>,
+    /// Webhooks to create or overwrite.
+    upsert_webhooks: Vec<Identifier>,
+    /// Tokens to create or overwrite.
+    upsert_tokens: Vec<i64>,
+    delete_users: Vec<String>,
+    delete_configs: Vec<Identifier>,
+    delete_webhooks: Vec<Identifier>,
+    delete_tokens: Vec<i64>,
+}
+
+impl ImportPlan {
+    fn new(document: &ExportDocument, existing: &ExistingState, options: &ImportOptions) -> Self {
+        let replace = options.mode == ImportMode::Replace;
+        let mut plan = Self {
+            report: ImportReport {
+                mode: options.mode,
+                dry_run: options.dry_run,
+                ..Default::default()
+            },
+            upsert_users: Vec::new(),
+            upsert_configs: Vec::new(),
+            upsert_webhooks: Vec::new(),
+            upsert_tokens: Vec::new(),
+            delete_users: Vec::new(),
+            delete_configs: Vec::new(),
+            delete_webhooks: Vec::new(),
+            delete_tokens: Vec::new(),
+        };
+
+        let mut seen_users = HashSet::new();
+        for user in &document.users {
+            if !seen_users.insert(user.server_id.as_str()) {
+                plan.conflict("user", &user.server_id, "appears more than once");
+                continue;
+            }
+
+            match existing.users.get(&user.server_id) {
+                None if user.auth_token_hash.is_none() => plan.conflict(
+                    "user",
+                    &user.server_id,
+                    "has no token hash and does not exist yet",
+                ),
+                None => {
+                    plan.report.users.created += 1;
+                    plan.upsert_users.push(user.server_id.clone());
+                }
+                Some(existing) if user.matches(existing) => plan.report.users.unchanged += 1,
+                Some(_) if !replace => {
+                    plan.conflict("user", &user.server_id, "exists with different data")
+                }
+                Some(_) => {
+                    plan.report.users.updated += 1;
+                    plan.upsert_users.push(user.server_id.clone());
+                }
+            }
+        }
+
+        if replace {
+            plan.delete_users = existing
+                .users
+                .keys()
+                .filter(|server_id| !seen_users.contains(server_id.as_str()))
+                .cloned()
+                .collect();
+            plan.delete_users.sort();
+            plan.report.users.deleted = plan.delete_users.len();
+        }
+
+        // The users and configs as they would be after the import.
+        let mut server_lists = HashMap::new();
+        let mut client_ids = HashMap::new();
+
+        if !replace {
+            for user in existing.users.values() {
+                server_lists.insert(user.server_id.as_str(), &user.server_list);
+            }
+
+            for identifier in existing.configs.keys() {
+                client_ids.insert(identifier.client_id(), identifier);
+            }
+        }
+
+        for user in &document.users {
+            server_lists.insert(user.server_id.as_str(), &user.server_list);
+        }
+
+        let mut seen_configs = HashSet::new();
+        for config in &document.configs {
+            let identifier = &config.identifier;
+            let key = identifier.to_string();
+
+            if !seen_configs.insert(identifier) {
+                plan.conflict("config", &key, "appears more than once");
+                continue;
+            }
+
+            let Some(server_list) = server_lists.get(identifier.server_id()) else {
+                plan.conflict("config", &key, "belongs to a user that does not exist");
+                continue;
+            };
+
+            if let Err(e) = check_subscriptions(
+                identifier,
+                server_list,
+                &config.subscriptions,
+                &config.templates,
+            ) {
+                plan.conflict("config", &key, &e.to_string());
+                continue;
+            }
+
+            // Client IDs are unique across all servers.
+            if let Some(other) = client_ids.insert(identifier.client_id(), identifier) {
+                if other != identifier {
+                    plan.conflict(
+                        "config",
+                        &key,
+                        &format!("uses the same client ID as {other}"),
+                    );
+                    continue;
+                }
+            }
+
+            match existing.configs.get(identifier) {
+                None => {
+                    plan.report.configs.created += 1;
+                    plan.upsert_configs.push(identifier.clone());
+                }
+                Some(existing) if existing == config => plan.report.configs.unchanged += 1,
+                Some(_) if !replace => plan.conflict("config", &key, "exists with different data"),
+                Some(_) => {
+                    plan.report.configs.updated += 1;
+                    plan.upsert_configs.push(identifier.clone());
+                }
+            }
+        }
+
+        if replace {
+            plan.delete_configs = existing
+                .configs
+                .keys()
+                .filter(|identifier| !seen_configs.contains(identifier))
+                .cloned()
+                .collect();
+            plan.delete_configs
+                .sort_by_key(|identifier| identifier.to_string());
+            plan.report.configs.deleted = plan.delete_configs.len();
+        }
+
+        // The configs as they would be after the import.
+        let mut configs = seen_configs;
+        if !replace {
+            configs.extend(existing.configs.keys());
+        }
+
+        let mut seen_webhooks = HashSet::new();
+        for webhook in &document.webhooks {
+            let identifier = &webhook.identifier;
+            let key = identifier.to_string();
+
+            if !seen_webhooks.insert(identifier) {
+                plan.conflict("webhook", &key, "appears more than once");
+                continue;
+            }
+
+            if !configs.contains(identifier) {
+                plan.conflict("webhook", &key, "belongs to a config that does not exist");
+                continue;
+            }
+
+            match existing.webhooks.get(identifier) {
+                None if webhook.secret.is_none() => {
+                    plan.conflict("webhook", &key, "has no secret and does not exist yet")
+                }
+                None => {
+                    plan.report.webhooks.created += 1;
+                    plan.upsert_webhooks.push(identifier.clone());
+                }
+                Some(existing) if webhook.matches(existing) => plan.report.webhooks.unchanged += 1,
+                Some(_) if !replace => plan.conflict("webhook", &key, "exists with different data"),
+                Some(_) => {
+                    plan.report.webhooks.updated += 1;
+                    plan.upsert_webhooks.push(identifier.clone());
+                }
+            }
+        }
+
+        if replace {
+            plan.delete_webhooks = existing
+                .webhooks
+                .keys()
+                .filter(|identifier| !seen_webhooks.contains(identifier))
+                .cloned()
+                .collect();
+            plan.delete_webhooks
+                .sort_by_key(|identifier| identifier.to_string());
+            plan.report.webhooks.deleted = plan.delete_webhooks.len();
+        }
+
+        // A document without tokens was exported without token hashes, and leaves them alone.
+        let Some(tokens) = &document.tokens else {
+            return plan;
+        };
+
+        // Labels are unique per server.
+        let mut labels = HashMap::new();
+        if !replace {
+            for token in existing.tokens.values() {
+                labels.insert((token.server_id.as_str(), token.label.as_str()), token.id);
+            }
+        }
+
+        let mut seen_tokens = HashSet::new();
+        for token in tokens {
+            let key = token.id.to_string();
+
+            if !seen_tokens.insert(token.id) {
+                plan.conflict("token", &key, "appears more than once");
+                continue;
+            }
+
+            if !server_lists.contains_key(token.server_id.as_str()) {
+                plan.conflict("token", &key, "belongs to a user that does not exist");
+                continue;
+            }
+
+            if let Some(other) =
+                labels.insert((token.server_id.as_str(), token.label.as_str()), token.id)
+            {
+                if other != token.id {
+                    plan.conflict(
+                        "token",
+                        &key,
+                        &format!("uses the same label as token {other}"),
+                    );
+                    continue;
+                }
+            }
+
+            match existing.tokens.get(&token.id) {
+                None => {
+                    plan.report.tokens.created += 1;
+                    plan.upsert_tokens.push(token.id);
+                }
+                Some(existing) if token.matches(existing) => plan.report.tokens.unchanged += 1,
+                Some(_) if !replace => plan.conflict("token", &key, "exists with different data"),
+                Some(_) => {
+                    plan.report.tokens.updated += 1;
+                    plan.upsert_tokens.push(token.id);
+                }
+            }
+        }
+
+        if replace {
+            plan.delete_tokens = existing
+                .tokens
+                .keys()
+                .filter(|id| !seen_tokens.contains(id))
+                .copied()
+                .collect();
+            plan.delete_tokens.sort();
+            plan.report.tokens.deleted = plan.delete_tokens.len();
+        }
+
+        plan
+    }
+
+    fn conflict(&mut self, kind: &'static str, key: &str, reason: &str) {
+        self.report.conflicts.push(ImportConflict {
+            kind,
+            key: key.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Writes the plan in the transaction `tx` it was worked out in.
+    async fn apply(
+        mut self,
+        document: &ExportDocument,
+        existing: &ExistingState,
+        tx: &mut SqliteConnection,
+    ) -> Result<ImportReport> {
+        for id in &self.delete_tokens {
+            sqlx::query("DELETE FROM tokens WHERE id = ?;")
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .db_context("Failed to delete token")?;
+        }
+
+        for identifier in &self.delete_webhooks {
+            sqlx::query("DELETE FROM webhooks WHERE identifier = ?;")
+                .bind(identifier.to_string())
+                .execute(&mut *tx)
+                .await
+                .db_context("Failed to delete webhook")?;
+        }
+
+        // Configs go first, so the client IDs they free up can be reused.
+        for identifier in &self.delete_configs {
+            sqlx::query("DELETE FROM configs WHERE identifier = ?;")
+                .bind(identifier.to_string())
+                .execute(&mut *tx)
+                .await
+                .db_context("Failed to delete config")?;
+        }
+
+        for server_id in &self.delete_users {
+            sqlx::query("DELETE FROM tokens WHERE server_id = ?;")
+                .bind(server_id)
+                .execute(&mut *tx)
+                .await
+                .db_context("Failed to delete tokens")?;
+
+            sqlx::query("DELETE FROM users WHERE server_id = ?;")
+                .bind(server_id)
+                .execute(&mut *tx)
+                .await
+                .db_context("Failed to delete user")?;
+        }
+
+        let users = document
+            .users
+            .iter()
+            .filter(|user| self.upsert_users.contains(&user.server_id));
+
+        for user in users {
+            // Users exported without their hash keep the one they have.
+            let auth_token_hash = user
+                .auth_token_hash
+                .as_ref()
+                .or_else(|| {
+                    existing
+                        .users
+                        .get(&user.server_id)?
+                        .auth_token_hash
+                        .as_ref()
+                })
+                .ok_or_else(|| anyhow::anyhow!("No token hash for {}", user.server_id))?;
+
+            let allowed_cidrs = user
+                .allowed_cidrs
+                .as_ref()
+                .filter(|cidrs| !cidrs.is_empty())
+                .map(serde_json::to_string)
+                .transpose()
+                .context("Failed to serialize allowed CIDRs")?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO users (server_id, server_list, auth_token, allowed_cidrs) VALUES ($1, $2, $3, $4)
+                ON CONFLICT(server_id) DO UPDATE SET server_list = $2, auth_token = $3, allowed_cidrs = $4;
+                "#,
+            )
+            .bind(&user.server_id)
+            .bind(serde_json::to_string(&user.server_list).context("Failed to serialize serverlist")?)
+            .bind(auth_token_hash)
+            .bind(allowed_cidrs)
+            .execute(&mut *tx)
+            .await
+            .db_context("Failed to import user")?;
+        }
+
+        let configs = document
+            .configs
+            .iter()
+            .filter(|config| self.upsert_configs.contains(&config.identifier));
+
+        for config in configs {
+            sqlx::query("INSERT INTO configs (identifier, server_id, client_id, subscriptions, templates) VALUES ($1, $2, $3, $4, $5) ON CONFLICT(identifier) DO UPDATE SET subscriptions = $4, templates = $5;")
+                .bind(config.identifier.to_string())
+                .bind(config.identifier.server_id())
+                .bind(config.identifier.client_id())
+                .bind(serde_json::to_string(&config.subscriptions)?)
+                .bind(serde_json::to_string(&config.templates)?)
+                .execute(&mut *tx)
+                .await
+                .db_context("Failed to import config")?;
+        }
+
+        let webhooks = document
+            .webhooks
+            .iter()
+            .filter(|webhook| self.upsert_webhooks.contains(&webhook.identifier));
+
+        let now = Utc::now();
+        for webhook in webhooks {
+            // Webhooks exported without their secret keep the one they have.
+            let secret = webhook
+                .secret
+                .as_ref()
+                .or_else(|| existing.webhooks.get(&webhook.identifier)?.secret.as_ref())
+                .ok_or_else(|| anyhow::anyhow!("No secret for webhook {}", webhook.identifier))?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO webhooks (identifier, server_id, url, secret, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $5)
+                ON CONFLICT(identifier) DO UPDATE SET url = $3, secret = $4, updated_at = $5;
+                "#,
+            )
+            .bind(webhook.identifier.to_string())
+            .bind(webhook.identifier.server_id())
+            .bind(&webhook.url)
+            .bind(secret)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .db_context("Failed to import webhook")?;
+        }
+
+        let tokens = document
+            .tokens
+            .iter()
+            .flatten()
+            .filter(|token| self.upsert_tokens.contains(&token.id));
+
+        // The id is part of the plaintext token, so it is kept. When the token was last used
+        // is kept as well.
+        for token in tokens {
+            sqlx::query(
+                r#"
+                INSERT INTO tokens (id, server_id, label, token_hash, scopes, created_at, last_used_at, expires_at, revoked_at, client_ids)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT(id) DO UPDATE SET server_id = $2, label = $3, token_hash = $4, scopes = $5, created_at = $6, expires_at = $8, revoked_at = $9, client_ids = $10;
+                "#,
+            )
+            .bind(token.id)
+            .bind(&token.server_id)
+            .bind(&token.label)
+            .bind(&token.token_hash)
+            .bind(serde_json::to_string(&token.scopes)?)
+            .bind(token.created_at)
+            .bind(token.last_used_at)
+            .bind(token.expires_at)
+            .bind(token.revoked_at)
+            .bind(
+                token
+                    .client_ids
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+            )
+            .execute(&mut *tx)
+            .await
+            .db_context("Failed to import token")?;
+        }
+
+        self.report.applied = true;
+        Ok(self.report)
+    }
+}
+
+/// [ExportDocument] is everything needed to move the bridge to another host.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExportDocument {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub users: Vec<ExportedUser>,
+    pub configs: Vec<ClientConfig>,
+    #[serde(default)]
+    pub webhooks: Vec<ExportedWebhook>,
+    /// The named tokens with their hashes. Missing if the export left out token hashes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<ExportedToken>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExportedUser {
+    pub server_id: String,
+    pub server_list: Vec<String>,
+    #[serde(default)]
+    #[schema(value_type = Option<Vec<String>>)]
+    pub allowed_cidrs: Option<Vec<IpNet>>,
+    /// The stored hash of the primary token. Missing if the export left out token hashes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token_hash: Option<String>,
+}
+
+impl ExportedUser {
+    /// Returns true if importing this user would not change `existing`.
+    fn matches(&self, existing: &ExportedUser) -> bool {
+        self.server_list == existing.server_list
+            && self.allowed_cidrs.as_deref().unwrap_or_default()
+                == existing.allowed_cidrs.as_deref().unwrap_or_default()
+            && (self.auth_token_hash.is_none() || self.auth_token_hash == existing.auth_token_hash)
+    }
+}
+
+impl TryFrom<UserInDB> for ExportedUser {
+    type Error = Error;
+
+    fn try_from(user: UserInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            server_id: user.server_id,
+            server_list: serde_json::from_str(&user.server_list)?,
+            allowed_cidrs: user
+                .allowed_cidrs
+                .map(|cidrs| serde_json::from_str(&cidrs))
+                .transpose()?,
+            auth_token_hash: Some(user.auth_token),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExportedWebhook {
+    pub identifier: Identifier,
+    pub url: String,
+    /// The secret deliveries are signed with. Missing if the export left out token hashes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl ExportedWebhook {
+    /// Returns true if importing this webhook would not change `existing`.
+    fn matches(&self, existing: &ExportedWebhook) -> bool {
+        self.url == existing.url && (self.secret.is_none() || self.secret == existing.secret)
+    }
+}
+
+impl TryFrom<WebhookInDB> for ExportedWebhook {
+    type Error = Error;
+
+    fn try_from(webhook: WebhookInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            identifier: webhook.identifier.parse()?,
+            url: webhook.url,
+            secret: Some(webhook.secret),
+        })
+    }
+}
+
+/// [ExportedToken] is a named token with its hash. It keeps its id, which is part of the
+/// plaintext token.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExportedToken {
+    pub id: i64,
+    pub server_id: String,
+    pub label: String,
+    pub token_hash: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub client_ids: Option<Vec<String>>,
+}
+
+impl ExportedToken {
+    /// Returns true if importing this token would not change `existing`. When it was last used
+    /// does not count.
+    fn matches(&self, existing: &ExportedToken) -> bool {
+        self.server_id == existing.server_id
+            && self.label == existing.label
+            && self.token_hash == existing.token_hash
+            && self.scopes == existing.scopes
+            && self.created_at == existing.created_at
+            && self.expires_at == existing.expires_at
+            && self.revoked_at == existing.revoked_at
+            && self.client_ids == existing.client_ids
+    }
+}
+
+impl TryFrom<ApiTokenInDB> for ExportedToken {
+    type Error = Error;
+
+    fn try_from(token: ApiTokenInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            id: token.id,
+            server_id: token.server_id,
+            label: token.label,
+            token_hash: token.token_hash,
+            scopes: serde_json::from_str(&token.scopes)?,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+            expires_at: token.expires_at,
+            revoked_at: token.revoked_at,
+            client_ids: token
+                .client_ids
+                .map(|client_ids| serde_json::from_str(&client_ids))
+                .transpose()?,
+        })
+    }
+}
+
+/// How an import treats what is already in the database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Adds what is new. Existing users, configs, webhooks and tokens that differ are conflicts.
+    #[default]
+    Merge,
+    /// Makes the database match the document. Users and configs that are not in it are deleted,
+    /// together with their webhooks and the named tokens of those users. If the document has
+    /// tokens, the tokens that are not in it are deleted as well.
+    Replace,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub mode: ImportMode,
+    /// Only report what would change.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    pub dry_run: bool,
+    /// Whether anything was written. Never true with conflicts.
+    pub applied: bool,
+    pub users: ChangeCounts,
+    pub configs: ChangeCounts,
+    pub webhooks: ChangeCounts,
+    pub tokens: ChangeCounts,
+    pub conflicts: Vec<ImportConflict>,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ChangeCounts {
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportConflict {
+    /// `user`, `config`, `webhook` or `token`.
+    #[schema(value_type = String)]
+    pub kind: &'static str,
+    /// The server ID of a user, the identifier of a config or webhook, or the id of a token.
+    pub key: String,
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(server_id: &str, server_list: &[&str]) -> ExportedUser {
+        ExportedUser {
+            server_id: server_id.to_string(),
+            server_list: server_list.iter().map(|entry| entry.to_string()).collect(),
+            allowed_cidrs: None,
+            auth_token_hash: Some(format!("hash-{server_id}")),
+        }
+    }
+
+    fn config(identifier: &str, subscriptions: &[&str]) -> ClientConfig {
+        ClientConfig {
+            identifier: identifier.parse().unwrap(),
+            subscriptions: subscriptions.iter().map(|sub| sub.to_string()).collect(),
+            templates: Default::default(),
+        }
+    }
+
+    fn token(id: i64, server_id: &str, label: &str) -> ExportedToken {
+        ExportedToken {
+            id,
+            server_id: server_id.to_string(),
+            label: label.to_string(),
+            token_hash: format!("hash-{id}"),
+            scopes: vec![TokenScope::ConfigRead],
+            created_at: DateTime::UNIX_EPOCH,
+            last_used_at: None,
+            expires_at: None,
+            revoked_at: None,
+            client_ids: None,
+        }
+    }
+
+    fn webhook(identifier: &str, url: &str, secret: Option<&str>) -> ExportedWebhook {
+        ExportedWebhook {
+            identifier: identifier.parse().unwrap(),
+            url: url.to_string(),
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    fn document(
+        users: Vec<ExportedUser>,
+        configs: Vec<ClientConfig>,
+        tokens: Option<Vec<ExportedToken>>,
+    ) -> ExportDocument {
+        ExportDocument {
+            version: EXPORT_VERSION,
+            exported_at: Utc::now(),
+            users,
+            configs,
+            webhooks: Vec::new(),
+            tokens,
+        }
+    }
+
+    fn existing(document: &ExportDocument) -> ExistingState {
+        ExistingState {
+            users: document
+                .users
+                .iter()
+                .map(|user| (user.server_id.clone(), user.clone()))
+                .collect(),
+            configs: document
+                .configs
+                .iter()
+                .map(|config| (config.identifier.clone(), config.clone()))
+                .collect(),
+            webhooks: document
+                .webhooks
+                .iter()
+                .map(|webhook| (webhook.identifier.clone(), webhook.clone()))
+                .collect(),
+            tokens: document
+                .tokens
+                .iter()
+                .flatten()
+                .map(|token| (token.id, token.clone()))
+                .collect(),
+        }
+    }
+
+    fn options(mode: ImportMode) -> ImportOptions {
+        ImportOptions {
+            mode,
+            dry_run: false,
+        }
+    }
+
+    fn conflicts(plan: &ImportPlan) -> Vec<(&str, &str, &str)> {
+        plan.report
+            .conflicts
+            .iter()
+            .map(|conflict| {
+                (
+                    conflict.kind,
+                    conflict.key.as_str(),
+                    conflict.reason.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn import_into_empty_database_creates_everything() {
+        let document = document(
+            vec![user("a", &["a"]), user("b", &["a", "b"])],
+            vec![config("a:smp", &["a"]), config("b:web", &["a", "b"])],
+            Some(vec![token(1, "a", "bot")]),
+        );
+        let empty = existing(&self::document(vec![], vec![], None));
+
+        let plan = ImportPlan::new(&document, &empty, &options(ImportMode::Merge));
+
+        assert!(plan.report.conflicts.is_empty());
+        assert_eq!(plan.report.users.created, 2);
+        assert_eq!(plan.report.configs.created, 2);
+        assert_eq!(plan.report.tokens.created, 1);
+    }
+
+    #[test]
+    fn import_of_the_same_state_changes_nothing() {
+        let document = document(
+            vec![user("a", &["a"])],
+            vec![config("a:smp", &["a"])],
+            Some(vec![token(1, "a", "bot")]),
+        );
+        let mut state = existing(&document);
+        state.tokens.get_mut(&1).unwrap().last_used_at = Some(Utc::now());
+
+        for mode in [ImportMode::Merge, ImportMode::Replace] {
+            let plan = ImportPlan::new(&document, &state, &options(mode));
+
+            assert!(plan.report.conflicts.is_empty());
+            assert_eq!(plan.report.users.unchanged, 1);
+            assert_eq!(plan.report.configs.unchanged, 1);
+            assert_eq!(plan.report.tokens.unchanged, 1);
+            assert!(plan.upsert_users.is_empty() && plan.upsert_tokens.is_empty());
+        }
+    }
+
+    #[test]
+    fn duplicates_and_users_without_hash_conflict() {
+        let mut hashless = user("b", &["b"]);
+        hashless.auth_token_hash = None;
+        let document = document(
+            vec![user("a", &["a"]), user("a", &["a"]), hashless],
+            vec![config("a:smp", &["a"]), config("a:smp", &["a"])],
+            Some(vec![token(1, "a", "bot"), token(1, "a", "bot")]),
+        );
+        let empty = existing(&self::document(vec![], vec![], None));
+
+        let plan = ImportPlan::new(&document, &empty, &options(ImportMode::Merge));
+
+        assert_eq!(
+            conflicts(&plan),
+            vec![
+                ("user", "a", "appears more than once"),
+                ("user", "b", "has no token hash and does not exist yet"),
+                ("config", "a:smp", "appears more than once"),
+                ("token", "1", "appears more than once"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_conflicts_with_changed_data_and_replace_overwrites_it() {
+        let before = document(
+            vec![user("a", &["a"]), user("old", &["old"])],
+            vec![config("a:smp", &["a"]), config("old:proxy", &["old"])],
+            Some(vec![token(1, "a", "bot"), token(2, "old", "bot")]),
+        );
+        let mut changed_token = token(1, "a", "bot");
+        changed_token.scopes = vec![TokenScope::WsConnect];
+        let document = document(
+            vec![user("a", &["a", "b"]), user("b", &["b"])],
+            vec![config("a:smp", &["a", "b"])],
+            Some(vec![changed_token]),
+        );
+
+        let merge = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Merge));
+        assert_eq!(
+            conflicts(&merge),
+            vec![
+                ("user", "a", "exists with different data"),
+                ("config", "a:smp", "exists with different data"),
+                ("token", "1", "exists with different data"),
+            ]
+        );
+
+        let replace = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Replace));
+        assert!(replace.report.conflicts.is_empty());
+        assert_eq!(replace.upsert_users, vec!["a", "b"]);
+        assert_eq!(replace.delete_users, vec!["old"]);
+        assert_eq!(replace.delete_configs, vec!["old:proxy".parse().unwrap()]);
+        assert_eq!(replace.upsert_tokens, vec![1]);
+        assert_eq!(replace.delete_tokens, vec![2]);
+    }
+
+    #[test]
+    fn replace_without_tokens_leaves_them_alone() {
+        let before = document(
+            vec![user("a", &["a"])],
+            vec![],
+            Some(vec![token(1, "a", "bot")]),
+        );
+        let document = document(vec![user("a", &["a"])], vec![], None);
+
+        let plan = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Replace));
+
+        assert!(plan.report.conflicts.is_empty());
+        assert!(plan.delete_tokens.is_empty());
+    }
+
+    #[test]
+    fn configs_have_to_fit_their_user() {
+        let before = document(vec![user("a", &["a"])], vec![config("a:smp", &["a"])], None);
+        let document = document(
+            vec![user("b", &["b"])],
+            vec![
+                config("c:smp", &["c"]),
+                config("b:web", &["a"]),
+                config("b:smp", &["b"]),
+            ],
+            None,
+        );
+
+        let plan = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Merge));
+
+        assert_eq!(
+            conflicts(&plan),
+            vec![
+                ("config", "c:smp", "belongs to a user that does not exist"),
+                (
+                    "config",
+                    "b:web",
+                    "Client b:web is not allowed to subscribe to a"
+                ),
+                ("config", "b:smp", "uses the same client ID as a:smp"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_have_to_fit_their_user() {
+        let before = document(
+            vec![user("a", &["a"])],
+            vec![],
+            Some(vec![token(1, "a", "bot")]),
+        );
+        let document = document(
+            vec![],
+            vec![],
+            Some(vec![token(2, "a", "bot"), token(3, "c", "bot")]),
+        );
+
+        let plan = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Merge));
+
+        assert_eq!(
+            conflicts(&plan),
+            vec![
+                ("token", "2", "uses the same label as token 1"),
+                ("token", "3", "belongs to a user that does not exist"),
+            ]
+        );
+    }
+
+    #[test]
+    fn webhooks_need_a_config_and_a_secret() {
+        let mut before = document(vec![user("a", &["a"])], vec![config("a:smp", &["a"])], None);
+        before.webhooks = vec![webhook("a:smp", "https://a.example/hook", Some("secret"))];
+
+        let mut document = document(
+            vec![user("a", &["a"])],
+            vec![config("a:smp", &["a"]), config("a:web", &["a"])],
+            None,
+        );
+        document.webhooks = vec![
+            // Without its secret, it is the same webhook.
+            webhook("a:smp", "https://a.example/hook", None),
+            webhook("a:web", "https://a.example/web", None),
+            webhook("a:proxy", "https://a.example/proxy", Some("secret")),
+        ];
+
+        let plan = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Merge));
+
+        assert_eq!(
+            conflicts(&plan),
+            vec![
+                ("webhook", "a:web", "has no secret and does not exist yet"),
+                (
+                    "webhook",
+                    "a:proxy",
+                    "belongs to a config that does not exist"
+                ),
+            ]
+        );
+        assert_eq!(plan.report.webhooks.unchanged, 1);
+
+        document.webhooks = vec![webhook("a:smp", "https://a.example/moved", None)];
+        let plan = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Merge));
+        assert_eq!(
+            conflicts(&plan),
+            vec![("webhook", "a:smp", "exists with different data")]
+        );
+
+        document.webhooks = Vec::new();
+        let plan = ImportPlan::new(&document, &existing(&before), &options(ImportMode::Replace));
+        assert!(plan.report.conflicts.is_empty());
+        assert_eq!(plan.delete_webhooks, vec!["a:smp".parse().unwrap()]);
+    }
+}
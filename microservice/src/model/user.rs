@@ -1,109 +1,261 @@
+use std::net::IpAddr;
+
 use anyhow::Context;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::database_utils::{acquire_connection, hash_token, verify_token, TokenVerification};
+use crate::error::{DbContext, Error, Result};
+use crate::model::config::ConfigModelController;
+use crate::model::token::TokenModelController;
+
+/// Default page size of [UserModelController::list_users_page].
+const DEFAULT_LIMIT: u32 = 50;
 
-use crate::database_utils::{acquire_connection, hash_password};
+/// Largest page size of [UserModelController::list_users_page].
+const MAX_LIMIT: u32 = 500;
+
+/// The Discord bot, which every user is linked to.
+pub const DISCORD_SERVER_ID: &str = "discord";
 
 pub struct UserModelController;
 
 impl UserModelController {
-    pub async fn get_user_by_id(server_id: &str, db_pool: &SqlitePool) -> anyhow::Result<User> {
+    pub async fn get_user_by_id(server_id: &str, db_pool: &SqlitePool) -> Result<User> {
         sqlx::query_as::<_, UserInDB>("SELECT * FROM users WHERE server_id = ?;")
             .bind(server_id)
             .fetch_optional(acquire_connection(db_pool).await?.as_mut())
             .await
-            .context("Failed to get user: {e}")?
+            .db_context("Failed to get user: {e}")?
             .map(|user| user.try_into())
             .transpose()?
-            .ok_or_else(|| anyhow::anyhow!("User not found"))
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))
     }
 
-    pub async fn list_users(db_pool: &SqlitePool) -> anyhow::Result<Vec<UserNoToken>> {
-        sqlx::query_as::<_, UserNoTokenInDB>("SELECT server_id, server_list FROM users;")
+    pub async fn list_users(db_pool: &SqlitePool) -> Result<Vec<UserNoToken>> {
+        sqlx::query_as::<_, UserNoTokenInDB>(
+            "SELECT server_id, server_list, allowed_cidrs FROM users;",
+        )
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to get users from database")?
+        .into_iter()
+        .map(|user| user.try_into())
+        .collect()
+    }
+
+    /// Lists users ordered by `server_id`, with the number of configured clients of each.
+    ///
+    /// `scope` restricts the listing to a single server. Pass the `next_cursor` of a page as
+    /// `cursor` to get the next one. The `connections` of the returned users are left at zero.
+    pub async fn list_users_page(
+        query: &UserQuery,
+        scope: Option<&str>,
+        db_pool: &SqlitePool,
+    ) -> Result<UserPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let sql_query = r#"
+            SELECT users.server_id, users.server_list, users.allowed_cidrs,
+                   (SELECT COUNT(*) FROM configs WHERE configs.server_id = users.server_id) AS clients
+            FROM users
+            WHERE ($1 IS NULL OR users.server_id = $1)
+              AND ($2 IS NULL OR substr(users.server_id, 1, length($2)) = $2)
+              AND ($3 IS NULL OR EXISTS (SELECT 1 FROM json_each(users.server_list) WHERE value = $3))
+              AND ($4 IS NULL OR users.server_id > $4)
+            ORDER BY users.server_id
+            LIMIT $5;
+        "#;
+
+        let users = sqlx::query_as::<_, UserSummaryInDB>(sql_query)
+            .bind(scope)
+            .bind(&query.server_id_prefix)
+            .bind(&query.subscribed_to)
+            .bind(&query.cursor)
+            .bind(limit)
             .fetch_all(acquire_connection(db_pool).await?.as_mut())
             .await
-            .context("Failed to get users from database")?
+            .db_context("Failed to list users")?
             .into_iter()
             .map(|user| user.try_into())
-            .collect()
+            .collect::<Result<Vec<UserSummary>>>()?;
+
+        let next_cursor = if users.len() == limit as usize {
+            users.last().map(|user| user.user.server_id.clone())
+        } else {
+            None
+        };
+
+        Ok(UserPage { users, next_cursor })
     }
 
-    pub async fn add_user(
-        mut data: AdminPostBody,
-        db_pool: &SqlitePool,
-    ) -> anyhow::Result<UserNoToken> {
-        add_if_not_contains(&mut data.server_list, "discord".to_string());
+    pub async fn add_user(mut data: AdminPostBody, db_pool: &SqlitePool) -> Result<UserNoToken> {
+        add_if_not_contains(&mut data.server_list, DISCORD_SERVER_ID.to_string());
 
         sqlx::query_as::<_, UserNoTokenInDB>(
-            "INSERT INTO users (server_id, server_list, auth_token) VALUES (?, ?, ?) RETURNING *;",
+            "INSERT INTO users (server_id, server_list, auth_token, allowed_cidrs) VALUES (?, ?, ?, ?) RETURNING *;",
         )
         .bind(data.server_id)
         .bind(serde_json::to_string(&data.server_list).context("Failed to serialize serverlist")?)
-        .bind(data.auth_token)
+        .bind(hash_token(&data.auth_token).await?)
+        .bind(stringify_cidrs(data.allowed_cidrs.as_deref())?)
         .fetch_one(acquire_connection(db_pool).await?.as_mut())
         .await
-        .context("Failed to add user")?
+        .db_context("Failed to add user")?
         .try_into()
     }
 
-    pub async fn delete_user(
-        data: AdminDeleteBody,
-        db_pool: &SqlitePool,
-    ) -> anyhow::Result<UserNoToken> {
-        sqlx::query_as::<_, UserNoTokenInDB>("DELETE FROM users WHERE server_id = ? RETURNING *;")
-            .bind(data.server_id)
-            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+    /// Deletes a user together with its tokens and configs, which takes their webhooks with them.
+    /// Either all of it is deleted or nothing.
+    pub async fn delete_user(data: AdminDeleteBody, db_pool: &SqlitePool) -> Result<UserNoToken> {
+        let mut tx = db_pool
+            .begin()
             .await
-            .context("Failed to delete user")?
-            .map(|user| user.try_into())
-            .transpose()?
-            .ok_or_else(|| anyhow::anyhow!("User not found"))
+            .db_context("Failed to start transaction")?;
+
+        let user: UserNoToken = sqlx::query_as::<_, UserNoTokenInDB>(
+            "DELETE FROM users WHERE server_id = ? RETURNING *;",
+        )
+        .bind(&data.server_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .db_context("Failed to delete user")?
+        .map(|user| user.try_into())
+        .transpose()?
+        .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        TokenModelController::delete_tokens_by_server_id(&user.server_id, &mut tx).await?;
+        ConfigModelController::delete_config_by_server_id(&user.server_id, &mut tx).await?;
+
+        tx.commit().await.db_context("Failed to delete user")?;
+
+        Ok(user)
     }
 
-    pub async fn update_user(
-        data: AdminUpdateBody,
-        db_pool: &SqlitePool,
-        salt: &str,
-    ) -> anyhow::Result<UserNoToken> {
-        if let Some(mut server_list) = data.server_list.clone() {
+    pub async fn update_user(data: AdminUpdateBody, db_pool: &SqlitePool) -> Result<UserNoToken> {
+        let mut server_list = data.server_list.clone();
+
+        if let Some(server_list) = server_list.as_mut() {
             if server_list.is_empty() {
-                return Err(anyhow::anyhow!("Server list cannot be empty"));
+                return Err(Error::BadRequest("Server list cannot be empty".to_string()));
             }
 
-            add_if_not_contains(server_list.as_mut(), "discord".to_string());
+            add_if_not_contains(server_list, DISCORD_SERVER_ID.to_string());
         }
 
-        let stringified_server_list =
-            if let Ok(server_list) = serde_json::to_string(&data.server_list) {
-                Some(server_list)
-            } else {
-                None
-            };
+        let stringified_server_list = server_list
+            .map(|server_list| serde_json::to_string(&server_list))
+            .transpose()
+            .context("Failed to serialize serverlist")?;
 
-        let hashed_token = if let Some(token) = data.auth_token {
-            Some(hash_password(token, salt))
-        } else {
-            None
+        let hashed_token = match &data.auth_token {
+            Some(token) => Some(hash_token(token).await?),
+            None => None,
         };
 
+        let stringified_cidrs = stringify_cidrs(data.allowed_cidrs.as_deref())?;
+
         let sql_query = r#"
             UPDATE users SET
                 server_list = CASE WHEN $1 IS NOT NULL THEN $1 ELSE server_list END,
-                auth_token = CASE WHEN $2 IS NOT NULL THEN $2 ELSE auth_token END
-            WHERE server_id = $3
-            RETURNING server_id, server_list;
+                auth_token = CASE WHEN $2 IS NOT NULL THEN $2 ELSE auth_token END,
+                allowed_cidrs = CASE WHEN $3 THEN $4 ELSE allowed_cidrs END
+            WHERE server_id = $5
+            RETURNING server_id, server_list, allowed_cidrs;
         "#;
 
-        return sqlx::query_as::<_, UserNoTokenInDB>(sql_query)
+        sqlx::query_as::<_, UserNoTokenInDB>(sql_query)
             .bind(stringified_server_list)
             .bind(hashed_token)
+            .bind(data.allowed_cidrs.is_some())
+            .bind(stringified_cidrs)
             .bind(data.server_id)
-            .fetch_optional(acquire_connection(&db_pool).await?.as_mut())
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
             .await
-            .context("Failed to update user")?
+            .db_context("Failed to update user")?
             .map(|user| user.try_into())
-            .context("Failed to deserialize user")?;
+            .transpose()?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))
     }
+
+    /// Checks `auth_token` against the stored hash of `server_id`.
+    ///
+    /// Tokens that still match a legacy SHA-256 hash are rehashed with Argon2id on the spot. A
+    /// `server_id` without a user is an invalid token like any other, so that a failed login does
+    /// not tell which servers exist.
+    pub async fn verify_auth_token(
+        server_id: &str,
+        auth_token: &str,
+        legacy_salt: &str,
+        db_pool: &SqlitePool,
+    ) -> Result<bool> {
+        let user = match Self::get_user_by_id(server_id, db_pool).await {
+            Ok(user) => user,
+            Err(Error::NotFound(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        match verify_token(auth_token, &user.hashed_auth_token, legacy_salt).await {
+            TokenVerification::Valid => Ok(true),
+            TokenVerification::ValidLegacy => {
+                if let Err(e) =
+                    Self::set_token_hash(server_id, &hash_token(auth_token).await?, db_pool).await
+                {
+                    tracing::error!("Failed to upgrade token hash of {server_id}: {e}");
+                } else {
+                    tracing::info!("Upgraded token hash of {server_id} to Argon2id");
+                }
+
+                Ok(true)
+            }
+            TokenVerification::Invalid => Ok(false),
+        }
+    }
+
+    /// Returns true if the tokens of `server_id` may be used from `address`.
+    ///
+    /// Users without an allowlist accept every address.
+    pub async fn is_address_allowed(
+        server_id: &str,
+        address: IpAddr,
+        db_pool: &SqlitePool,
+    ) -> Result<bool> {
+        let user = Self::get_user_by_id(server_id, db_pool).await?;
+
+        Ok(user.allows_address(address))
+    }
+
+    async fn set_token_hash(
+        server_id: &str,
+        hashed_token: &str,
+        db_pool: &SqlitePool,
+    ) -> Result<()> {
+        sqlx::query("UPDATE users SET auth_token = ? WHERE server_id = ?;")
+            .bind(hashed_token)
+            .bind(server_id)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to update token hash")
+            .map(|_| ())
+    }
+}
+
+/// Stores an empty allowlist as `NULL`, which allows every address.
+fn stringify_cidrs(cidrs: Option<&[IpNet]>) -> Result<Option<String>> {
+    cidrs
+        .filter(|cidrs| !cidrs.is_empty())
+        .map(|cidrs| serde_json::to_string(cidrs).context("Failed to serialize allowed CIDRs"))
+        .transpose()
+        .map_err(Error::from)
+}
+
+fn parse_cidrs(cidrs: Option<String>) -> Result<Option<Vec<IpNet>>> {
+    cidrs
+        .map(|cidrs| serde_json::from_str(&cidrs).context("Failed to deserialize allowed CIDRs"))
+        .transpose()
+        .map_err(Error::from)
 }
 
 fn add_if_not_contains<T: PartialEq>(list: &mut Vec<T>, item: T) {
@@ -112,11 +264,15 @@ fn add_if_not_contains<T: PartialEq>(list: &mut Vec<T>, item: T) {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct AdminPostBody {
     pub server_id: String,
     pub server_list: Vec<String>,
     pub auth_token: String,
+    /// Addresses the tokens of this user may be used from. Missing or empty allows every address.
+    #[serde(default)]
+    #[schema(value_type = Option<Vec<String>>)]
+    pub allowed_cidrs: Option<Vec<IpNet>>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -129,6 +285,29 @@ pub struct AdminUpdateBody {
     pub server_id: String,
     pub server_list: Option<Vec<String>>,
     pub auth_token: Option<String>,
+    /// Replaces the allowlist. An empty list removes it.
+    pub allowed_cidrs: Option<Vec<IpNet>>,
+}
+
+/// Changes to a user in `PATCH /v1/admin/users/{server_id}`. Missing fields are kept.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct UserPatchBody {
+    pub server_list: Option<Vec<String>>,
+    pub auth_token: Option<String>,
+    /// Replaces the allowlist. An empty list removes it.
+    #[schema(value_type = Option<Vec<String>>)]
+    pub allowed_cidrs: Option<Vec<IpNet>>,
+}
+
+impl UserPatchBody {
+    pub fn for_user(self, server_id: impl Into<String>) -> AdminUpdateBody {
+        AdminUpdateBody {
+            server_id: server_id.into(),
+            server_list: self.server_list,
+            auth_token: self.auth_token,
+            allowed_cidrs: self.allowed_cidrs,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -136,16 +315,27 @@ pub struct User {
     pub server_id: String,
     pub server_list: Vec<String>,
     pub hashed_auth_token: String,
+    pub allowed_cidrs: Option<Vec<IpNet>>,
+}
+
+impl User {
+    /// Returns true if the tokens of this user may be used from `address`.
+    pub fn allows_address(&self, address: IpAddr) -> bool {
+        self.allowed_cidrs
+            .as_ref()
+            .is_none_or(|cidrs| cidrs.iter().any(|cidr| cidr.contains(&address)))
+    }
 }
 
 impl TryFrom<UserInDB> for User {
-    type Error = anyhow::Error;
+    type Error = Error;
 
-    fn try_from(user: UserInDB) -> Result<Self, Self::Error> {
+    fn try_from(user: UserInDB) -> std::result::Result<Self, Self::Error> {
         Ok(Self {
             server_id: user.server_id,
             server_list: serde_json::from_str(&user.server_list)?,
             hashed_auth_token: user.auth_token,
+            allowed_cidrs: parse_cidrs(user.allowed_cidrs)?,
         })
     }
 }
@@ -155,19 +345,20 @@ pub struct UserInDB {
     pub server_id: String,
     pub server_list: String,
     pub auth_token: String,
+    pub allowed_cidrs: Option<String>,
 }
 
 impl UserInDB {
-    pub fn new(
+    pub async fn new(
         server_id: String,
         server_list: Vec<String>,
         unhashed_auth_token: String,
-        salt: &str,
-    ) -> anyhow::Result<Self> {
+    ) -> Result<Self> {
         Ok(Self {
             server_id,
             server_list: serde_json::to_string(&server_list)?,
-            auth_token: hash_password(unhashed_auth_token, salt),
+            auth_token: hash_token(&unhashed_auth_token).await?,
+            allowed_cidrs: None,
         })
     }
 }
@@ -176,21 +367,362 @@ impl UserInDB {
 pub struct UserNoTokenInDB {
     pub server_id: String,
     pub server_list: String,
+    pub allowed_cidrs: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserNoToken {
     pub server_id: String,
     pub server_list: Vec<String>,
+    #[schema(value_type = Option<Vec<String>>)]
+    pub allowed_cidrs: Option<Vec<IpNet>>,
+}
+
+impl From<User> for UserNoToken {
+    fn from(user: User) -> Self {
+        Self {
+            server_id: user.server_id,
+            server_list: user.server_list,
+            allowed_cidrs: user.allowed_cidrs,
+        }
+    }
 }
 
 impl TryFrom<UserNoTokenInDB> for UserNoToken {
-    type Error = anyhow::Error;
+    type Error = Error;
 
-    fn try_from(user: UserNoTokenInDB) -> Result<Self, Self::Error> {
+    fn try_from(user: UserNoTokenInDB) -> std::result::Result<Self, Self::Error> {
         Ok(Self {
             server_id: user.server_id,
             server_list: serde_json::from_str(&user.server_list)?,
+            allowed_cidrs: parse_cidrs(user.allowed_cidrs)?,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct UserQuery {
+    /// Only users whose `server_id` starts with this.
+    pub server_id_prefix: Option<String>,
+    /// Only users whose `server_list` contains this server.
+    pub subscribed_to: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserSummaryInDB {
+    pub server_id: String,
+    pub server_list: String,
+    pub allowed_cidrs: Option<String>,
+    pub clients: i64,
+}
+
+/// [UserSummary] is a user as listed for admins, with how many clients it has.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserSummary {
+    #[serde(flatten)]
+    pub user: UserNoToken,
+    /// Number of clients with a stored config.
+    pub clients: i64,
+    /// Number of live websocket connections, spectators included.
+    pub connections: usize,
+}
+
+impl TryFrom<UserSummaryInDB> for UserSummary {
+    type Error = Error;
+
+    fn try_from(user: UserSummaryInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            user: UserNoTokenInDB {
+                server_id: user.server_id,
+                server_list: user.server_list,
+                allowed_cidrs: user.allowed_cidrs,
+            }
+            .try_into()?,
+            clients: user.clients,
+            connections: 0,
         })
     }
 }
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserPage {
+    pub users: Vec<UserSummary>,
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_utils::test_pool;
+    use crate::model::config::SubscriptionTemplates;
+    use crate::model::token::{TokenCreateBody, TokenScope};
+
+    fn user_with_cidrs(cidrs: Option<&str>) -> User {
+        User {
+            server_id: "kiwitech".to_string(),
+            server_list: vec!["kiwitech".to_string()],
+            hashed_auth_token: String::new(),
+            allowed_cidrs: parse_cidrs(cidrs.map(str::to_string)).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_stored_cidrs() {
+        let cidrs = parse_cidrs(Some(r#"["10.0.0.0/8","2001:db8::/32"]"#.to_string()))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(cidrs.len(), 2);
+        assert_eq!(cidrs[0], "10.0.0.0/8".parse::<IpNet>().unwrap());
+        assert!(parse_cidrs(None).unwrap().is_none());
+        assert!(parse_cidrs(Some(r#"["not a cidr"]"#.to_string())).is_err());
+    }
+
+    #[test]
+    fn stores_an_empty_allowlist_as_null() {
+        assert!(stringify_cidrs(Some(&[])).unwrap().is_none());
+        assert!(stringify_cidrs(None).unwrap().is_none());
+
+        let cidrs = ["192.168.1.0/24".parse::<IpNet>().unwrap()];
+        let stored = stringify_cidrs(Some(&cidrs)).unwrap();
+        assert_eq!(parse_cidrs(stored).unwrap().unwrap(), cidrs);
+    }
+
+    #[test]
+    fn users_without_allowlist_allow_every_address() {
+        let user = user_with_cidrs(None);
+
+        assert!(user.allows_address("203.0.113.7".parse().unwrap()));
+        assert!(user.allows_address("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn allowlist_only_allows_contained_addresses() {
+        let user = user_with_cidrs(Some(r#"["10.0.0.0/8","2001:db8::/32"]"#));
+
+        assert!(user.allows_address("10.1.2.3".parse().unwrap()));
+        assert!(user.allows_address("2001:db8::1".parse().unwrap()));
+        assert!(!user.allows_address("11.0.0.1".parse().unwrap()));
+        assert!(!user.allows_address("2001:db9::1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn delete_user_takes_tokens_and_configs_with_it() {
+        let db_pool = test_pool().await;
+
+        for server_id in ["kiwitech", "other"] {
+            UserModelController::add_user(
+                AdminPostBody {
+                    server_id: server_id.to_string(),
+                    server_list: vec![server_id.to_string()],
+                    auth_token: "secret".to_string(),
+                    allowed_cidrs: None,
+                },
+                &db_pool,
+            )
+            .await
+            .unwrap();
+            TokenModelController::create_token(
+                TokenCreateBody {
+                    server_id: server_id.to_string(),
+                    label: "bot".to_string(),
+                    scopes: vec![TokenScope::WsConnect],
+                    expires_at: None,
+                    client_ids: None,
+                },
+                &db_pool,
+            )
+            .await
+            .unwrap();
+            // Client IDs are unique across servers.
+            ConfigModelController::add_or_update_config(
+                &format!("{server_id}:{server_id}smp").parse().unwrap(),
+                &vec![server_id.to_string()],
+                &SubscriptionTemplates::new(),
+                &db_pool,
+            )
+            .await
+            .unwrap();
+        }
+
+        let user = UserModelController::delete_user(
+            AdminDeleteBody {
+                server_id: "kiwitech".to_string(),
+            },
+            &db_pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.server_id, "kiwitech");
+
+        let tokens = TokenModelController::list_tokens(None, &db_pool)
+            .await
+            .unwrap();
+        let configs = ConfigModelController::list_configs(&db_pool).await.unwrap();
+        assert!(tokens.iter().all(|token| token.server_id == "other"));
+        assert!(configs
+            .iter()
+            .all(|config| config.identifier.server_id() == "other"));
+        assert_eq!((tokens.len(), configs.len()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn delete_user_fails_for_unknown_users() {
+        let db_pool = test_pool().await;
+
+        let deleted = UserModelController::delete_user(
+            AdminDeleteBody {
+                server_id: "nowhere".to_string(),
+            },
+            &db_pool,
+        )
+        .await;
+
+        assert!(matches!(deleted, Err(Error::NotFound(_))));
+    }
+
+    async fn pool_with_users(users: &[(&str, &[&str])]) -> SqlitePool {
+        let db_pool = test_pool().await;
+
+        for (server_id, server_list) in users {
+            UserModelController::add_user(
+                AdminPostBody {
+                    server_id: server_id.to_string(),
+                    server_list: server_list.iter().map(|sub| sub.to_string()).collect(),
+                    auth_token: "secret".to_string(),
+                    allowed_cidrs: None,
+                },
+                &db_pool,
+            )
+            .await
+            .unwrap();
+        }
+
+        db_pool
+    }
+
+    fn server_ids(page: &UserPage) -> Vec<&str> {
+        page.users
+            .iter()
+            .map(|summary| summary.user.server_id.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn list_users_page_walks_the_cursor() {
+        let db_pool = pool_with_users(&[
+            ("charlie", &[]),
+            ("alpha", &[]),
+            ("bravo", &[]),
+            ("delta", &[]),
+        ])
+        .await;
+        let mut query = UserQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let page = UserModelController::list_users_page(&query, None, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(server_ids(&page), ["alpha", "bravo"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("bravo"));
+
+        query.cursor = page.next_cursor;
+        let page = UserModelController::list_users_page(&query, None, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(server_ids(&page), ["charlie", "delta"]);
+
+        // A full page cannot know that nothing follows, the next one is empty.
+        query.cursor = page.next_cursor;
+        let page = UserModelController::list_users_page(&query, None, &db_pool)
+            .await
+            .unwrap();
+        assert!(page.users.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn list_users_page_clamps_the_limit() {
+        let db_pool = pool_with_users(&[("alpha", &[]), ("bravo", &[])]).await;
+        let query = UserQuery {
+            limit: Some(0),
+            ..Default::default()
+        };
+
+        let page = UserModelController::list_users_page(&query, None, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(server_ids(&page), ["alpha"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("alpha"));
+    }
+
+    #[tokio::test]
+    async fn list_users_page_filters() {
+        let db_pool = pool_with_users(&[
+            ("kiwi_smp", &["survival"]),
+            ("kiwi_creative", &[]),
+            ("kiwitech", &["survival"]),
+            ("survival", &[]),
+        ])
+        .await;
+
+        let query = UserQuery {
+            server_id_prefix: Some("kiwi_".to_string()),
+            ..Default::default()
+        };
+        let page = UserModelController::list_users_page(&query, None, &db_pool)
+            .await
+            .unwrap();
+        // `_` is not a wildcard, so kiwitech does not match.
+        assert_eq!(server_ids(&page), ["kiwi_creative", "kiwi_smp"]);
+
+        let query = UserQuery {
+            subscribed_to: Some("survival".to_string()),
+            ..Default::default()
+        };
+        let page = UserModelController::list_users_page(&query, None, &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(server_ids(&page), ["kiwi_smp", "kiwitech"]);
+
+        let page = UserModelController::list_users_page(&query, Some("kiwitech"), &db_pool)
+            .await
+            .unwrap();
+        assert_eq!(server_ids(&page), ["kiwitech"]);
+    }
+
+    #[tokio::test]
+    async fn list_users_page_counts_clients() {
+        let db_pool = pool_with_users(&[("kiwitech", &[]), ("other", &[])]).await;
+
+        for identifier in ["kiwitech:smp", "kiwitech:creative"] {
+            ConfigModelController::add_or_update_config(
+                &identifier.parse().unwrap(),
+                &vec![],
+                &SubscriptionTemplates::new(),
+                &db_pool,
+            )
+            .await
+            .unwrap();
+        }
+
+        let page = UserModelController::list_users_page(&UserQuery::default(), None, &db_pool)
+            .await
+            .unwrap();
+        let clients: Vec<_> = page
+            .users
+            .iter()
+            .map(|summary| (summary.user.server_id.as_str(), summary.clients))
+            .collect();
+        assert_eq!(clients, [("kiwitech", 2), ("other", 0)]);
+        // The discord bot is linked to every user.
+        assert!(page.users[0]
+            .user
+            .server_list
+            .contains(&DISCORD_SERVER_ID.to_string()));
+    }
+}
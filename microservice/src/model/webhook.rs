@@ -0,0 +1,571 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ctx::ctx_client::Identifier;
+use crate::database_utils::acquire_connection;
+use crate::error::{DbContext, Error, Result};
+use crate::model::config::SubscriptionTemplates;
+
+/// Default page size of [WebhookModelController::list_dead_letters].
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Largest page size of [WebhookModelController::list_dead_letters].
+const MAX_LIMIT: u32 = 500;
+
+/// Shortest secret a webhook may be signed with.
+const MIN_SECRET_LENGTH: usize = 16;
+
+pub struct WebhookModelController;
+
+impl WebhookModelController {
+    pub async fn get_webhook(identifier: &Identifier, db_pool: &SqlitePool) -> Result<Webhook> {
+        sqlx::query_as::<_, WebhookInDB>("SELECT * FROM webhooks WHERE identifier = ?;")
+            .bind(identifier.to_string())
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to get webhook")?
+            .map(|webhook| webhook.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound(format!("No webhook found for {identifier}")))
+    }
+
+    /// Lists webhooks without their secrets, optionally only those of `server_id`.
+    pub async fn list_webhooks(
+        server_id: Option<&str>,
+        db_pool: &SqlitePool,
+    ) -> Result<Vec<Webhook>> {
+        sqlx::query_as::<_, WebhookInDB>(
+            "SELECT * FROM webhooks WHERE ($1 IS NULL OR server_id = $1) ORDER BY identifier;",
+        )
+        .bind(server_id)
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to list webhooks")?
+        .into_iter()
+        .map(|webhook| webhook.try_into())
+        .collect()
+    }
+
+    /// Lists every webhook together with the subscriptions and templates of its config.
+    pub async fn list_subscribers(db_pool: &SqlitePool) -> Result<Vec<WebhookSubscriber>> {
+        sqlx::query_as::<_, WebhookSubscriberInDB>(
+            r#"
+            SELECT webhooks.identifier, webhooks.url, webhooks.secret,
+                   configs.subscriptions, configs.templates
+            FROM webhooks
+            JOIN configs ON configs.identifier = webhooks.identifier;
+            "#,
+        )
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to list webhook subscribers")?
+        .into_iter()
+        .map(|subscriber| subscriber.try_into())
+        .collect()
+    }
+
+    /// Points the config of `identifier` at `body.url`. The config has to exist.
+    pub async fn set_webhook(
+        identifier: &Identifier,
+        body: &WebhookBody,
+        db_pool: &SqlitePool,
+    ) -> Result<Webhook> {
+        body.validate()?;
+
+        let now = Utc::now();
+        sqlx::query_as::<_, WebhookInDB>(
+            r#"
+            INSERT INTO webhooks (identifier, server_id, url, secret, created_at, updated_at)
+            SELECT $1, $2, $3, $4, $5, $5 WHERE EXISTS (SELECT 1 FROM configs WHERE identifier = $1)
+            ON CONFLICT(identifier) DO UPDATE SET url = $3, secret = $4, updated_at = $5
+            RETURNING *;
+            "#,
+        )
+        .bind(identifier.to_string())
+        .bind(identifier.server_id())
+        .bind(&body.url)
+        .bind(&body.secret)
+        .bind(now)
+        .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to set webhook")?
+        .map(|webhook| webhook.try_into())
+        .transpose()?
+        .ok_or_else(|| Error::NotFound(format!("No config found for identifier: {identifier}")))
+    }
+
+    pub async fn delete_webhook(identifier: &Identifier, db_pool: &SqlitePool) -> Result<Webhook> {
+        sqlx::query_as::<_, WebhookInDB>("DELETE FROM webhooks WHERE identifier = ? RETURNING *;")
+            .bind(identifier.to_string())
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to delete webhook")?
+            .map(|webhook| webhook.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound(format!("No webhook found for {identifier}")))
+    }
+
+    pub async fn add_dead_letter(
+        dead_letter: NewDeadLetter<'_>,
+        db_pool: &SqlitePool,
+    ) -> Result<DeadLetter> {
+        sqlx::query_as::<_, DeadLetterInDB>("INSERT INTO webhook_dead_letters (identifier, server_id, url, payload, attempts, last_error, created_at, failed_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING *;")
+            .bind(dead_letter.identifier.to_string())
+            .bind(dead_letter.identifier.server_id())
+            .bind(dead_letter.url)
+            .bind(dead_letter.payload)
+            .bind(dead_letter.attempts)
+            .bind(dead_letter.last_error)
+            .bind(dead_letter.created_at)
+            .bind(Utc::now())
+            .fetch_one(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to add dead letter")?
+            .try_into()
+    }
+
+    /// Lists failed deliveries, newest first.
+    ///
+    /// Pass the `next_cursor` of a page as `cursor` to get the next one.
+    pub async fn list_dead_letters(
+        query: &DeadLetterQuery,
+        db_pool: &SqlitePool,
+    ) -> Result<DeadLetterPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let sql_query = r#"
+            SELECT * FROM webhook_dead_letters
+            WHERE ($1 IS NULL OR server_id = $1)
+              AND ($2 IS NULL OR identifier = $2)
+              AND ($3 IS NULL OR id < $3)
+            ORDER BY id DESC
+            LIMIT $4;
+        "#;
+
+        let dead_letters = sqlx::query_as::<_, DeadLetterInDB>(sql_query)
+            .bind(&query.server_id)
+            .bind(&query.identifier)
+            .bind(query.cursor)
+            .bind(limit)
+            .fetch_all(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to list dead letters")?
+            .into_iter()
+            .map(|dead_letter| dead_letter.try_into())
+            .collect::<Result<Vec<DeadLetter>>>()?;
+
+        let next_cursor = if dead_letters.len() == limit as usize {
+            dead_letters.last().map(|dead_letter| dead_letter.id)
+        } else {
+            None
+        };
+
+        Ok(DeadLetterPage {
+            dead_letters,
+            next_cursor,
+        })
+    }
+
+    pub async fn get_dead_letter(id: i64, db_pool: &SqlitePool) -> Result<DeadLetter> {
+        sqlx::query_as::<_, DeadLetterInDB>("SELECT * FROM webhook_dead_letters WHERE id = ?;")
+            .bind(id)
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to get dead letter")?
+            .map(|dead_letter| dead_letter.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound(format!("No dead letter with id {id}")))
+    }
+
+    pub async fn delete_dead_letter(id: i64, db_pool: &SqlitePool) -> Result<DeadLetter> {
+        sqlx::query_as::<_, DeadLetterInDB>(
+            "DELETE FROM webhook_dead_letters WHERE id = ? RETURNING *;",
+        )
+        .bind(id)
+        .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to delete dead letter")?
+        .map(|dead_letter| dead_letter.try_into())
+        .transpose()?
+        .ok_or_else(|| Error::NotFound(format!("No dead letter with id {id}")))
+    }
+
+    /// Queues `payload` for the webhook of `identifier`, unless `max_pending` deliveries are
+    /// queued for it already. Returns false in that case.
+    pub async fn queue_delivery(
+        identifier: &Identifier,
+        kind: &str,
+        payload: &str,
+        max_pending: u32,
+        db_pool: &SqlitePool,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let queued = sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (identifier, kind, payload, created_at, next_attempt_at)
+            SELECT $1, $2, $3, $4, $4
+            WHERE (SELECT COUNT(*) FROM webhook_deliveries WHERE identifier = $1) < $5;
+            "#,
+        )
+        .bind(identifier.to_string())
+        .bind(kind)
+        .bind(payload)
+        .bind(now)
+        .bind(max_pending)
+        .execute(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to queue webhook delivery")?
+        .rows_affected();
+
+        Ok(queued > 0)
+    }
+
+    /// Lists up to `limit` deliveries that are due at `now` with the current URL and secret of
+    /// their webhook, at most one per webhook and oldest first.
+    pub async fn due_deliveries(
+        now: DateTime<Utc>,
+        limit: u32,
+        db_pool: &SqlitePool,
+    ) -> Result<Vec<PendingDelivery>> {
+        sqlx::query_as::<_, PendingDeliveryInDB>(
+            r#"
+            SELECT webhook_deliveries.*, webhooks.url, webhooks.secret
+            FROM webhook_deliveries
+            JOIN webhooks ON webhooks.identifier = webhook_deliveries.identifier
+            WHERE webhook_deliveries.id IN (
+                SELECT MIN(id) FROM webhook_deliveries WHERE next_attempt_at <= $1 GROUP BY identifier
+            )
+            ORDER BY webhook_deliveries.next_attempt_at
+            LIMIT $2;
+            "#,
+        )
+        .bind(now)
+        .bind(limit)
+        .fetch_all(acquire_connection(db_pool).await?.as_mut())
+        .await
+        .db_context("Failed to list due webhook deliveries")?
+        .into_iter()
+        .map(|delivery| delivery.try_into())
+        .collect()
+    }
+
+    /// Forgets a delivery that was made.
+    pub async fn delete_delivery(id: i64, db_pool: &SqlitePool) -> Result<()> {
+        sqlx::query("DELETE FROM webhook_deliveries WHERE id = ?;")
+            .bind(id)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to delete webhook delivery")?;
+
+        Ok(())
+    }
+
+    /// Counts a failed attempt of a delivery and schedules the next one.
+    pub async fn retry_delivery(
+        id: i64,
+        last_error: &str,
+        next_attempt_at: DateTime<Utc>,
+        db_pool: &SqlitePool,
+    ) -> Result<()> {
+        sqlx::query("UPDATE webhook_deliveries SET attempts = attempts + 1, last_error = ?, next_attempt_at = ? WHERE id = ?;")
+            .bind(last_error)
+            .bind(next_attempt_at)
+            .bind(id)
+            .execute(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to reschedule webhook delivery")?;
+
+        Ok(())
+    }
+
+    /// Turns a delivery whose last attempt failed with `last_error` into a dead letter.
+    pub async fn give_up_delivery(
+        delivery: &PendingDelivery,
+        last_error: &str,
+        db_pool: &SqlitePool,
+    ) -> Result<DeadLetter> {
+        let mut tx = db_pool
+            .begin()
+            .await
+            .db_context("Failed to start transaction")?;
+
+        sqlx::query("DELETE FROM webhook_deliveries WHERE id = ?;")
+            .bind(delivery.id)
+            .execute(&mut *tx)
+            .await
+            .db_context("Failed to delete webhook delivery")?;
+
+        let dead_letter = sqlx::query_as::<_, DeadLetterInDB>("INSERT INTO webhook_dead_letters (identifier, server_id, url, payload, attempts, last_error, created_at, failed_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING *;")
+            .bind(delivery.identifier.to_string())
+            .bind(delivery.identifier.server_id())
+            .bind(&delivery.url)
+            .bind(&delivery.payload)
+            .bind(delivery.attempts + 1)
+            .bind(last_error)
+            .bind(delivery.created_at)
+            .bind(Utc::now())
+            .fetch_one(&mut *tx)
+            .await
+            .db_context("Failed to add dead letter")?
+            .try_into()?;
+
+        tx.commit()
+            .await
+            .db_context("Failed to move delivery to the dead letters")?;
+
+        Ok(dead_letter)
+    }
+
+    /// Counts another failed attempt of a dead letter, for example a failed replay.
+    pub async fn record_attempt(
+        id: i64,
+        last_error: &str,
+        db_pool: &SqlitePool,
+    ) -> Result<DeadLetter> {
+        sqlx::query_as::<_, DeadLetterInDB>("UPDATE webhook_dead_letters SET attempts = attempts + 1, last_error = ?, failed_at = ? WHERE id = ? RETURNING *;")
+            .bind(last_error)
+            .bind(Utc::now())
+            .bind(id)
+            .fetch_optional(acquire_connection(db_pool).await?.as_mut())
+            .await
+            .db_context("Failed to update dead letter")?
+            .map(|dead_letter| dead_letter.try_into())
+            .transpose()?
+            .ok_or_else(|| Error::NotFound(format!("No dead letter with id {id}")))
+    }
+}
+
+/// The endpoint a config wants its events POSTed to, and the secret they are signed with.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct WebhookBody {
+    /// An `http` or `https` URL.
+    pub url: String,
+    /// At least 16 characters. It is never returned.
+    pub secret: String,
+}
+
+impl WebhookBody {
+    fn validate(&self) -> Result<()> {
+        let url = reqwest::Url::parse(&self.url)
+            .map_err(|e| Error::BadRequest(format!("Invalid webhook URL: {e}")))?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::BadRequest(
+                "Webhook URLs have to use http or https".to_string(),
+            ));
+        }
+
+        if self.secret.chars().count() < MIN_SECRET_LENGTH {
+            return Err(Error::BadRequest(format!(
+                "Webhook secrets need at least {MIN_SECRET_LENGTH} characters"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct WebhookInDB {
+    pub identifier: String,
+    pub server_id: String,
+    pub url: String,
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// [Webhook] is the HTTP endpoint a config receives its events at. The secret is never sent.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Webhook {
+    pub identifier: Identifier,
+    pub url: String,
+    #[serde(skip)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<WebhookInDB> for Webhook {
+    type Error = Error;
+
+    fn try_from(webhook: WebhookInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            identifier: webhook.identifier.parse()?,
+            url: webhook.url,
+            secret: webhook.secret,
+            created_at: webhook.created_at,
+            updated_at: webhook.updated_at,
+        })
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct WebhookSubscriberInDB {
+    pub identifier: String,
+    pub url: String,
+    pub secret: String,
+    pub subscriptions: String,
+    pub templates: String,
+}
+
+/// [WebhookSubscriber] is a webhook with what it wants to receive.
+#[derive(Debug, Clone)]
+pub struct WebhookSubscriber {
+    pub identifier: Identifier,
+    pub url: String,
+    pub secret: String,
+    pub subscriptions: Vec<String>,
+    pub templates: SubscriptionTemplates,
+}
+
+impl WebhookSubscriber {
+    /// Returns true if this webhook wants to receive events from `source`.
+    pub fn is_subscribed_to(&self, source: &Identifier) -> bool {
+        self.subscriptions
+            .iter()
+            .any(|sub| source.matches_subscription(sub))
+    }
+}
+
+impl TryFrom<WebhookSubscriberInDB> for WebhookSubscriber {
+    type Error = Error;
+
+    fn try_from(subscriber: WebhookSubscriberInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            identifier: subscriber.identifier.parse()?,
+            url: subscriber.url,
+            secret: subscriber.secret,
+            subscriptions: serde_json::from_str(&subscriber.subscriptions)?,
+            templates: serde_json::from_str(&subscriber.templates)?,
+        })
+    }
+}
+
+/// [NewDeadLetter] is a delivery that is about to be given up on.
+#[derive(Debug)]
+pub struct NewDeadLetter<'a> {
+    pub identifier: &'a Identifier,
+    pub url: &'a str,
+    pub payload: &'a str,
+    pub attempts: u32,
+    pub last_error: &'a str,
+    /// When the first attempt was made.
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct PendingDeliveryInDB {
+    pub id: i64,
+    pub identifier: String,
+    pub kind: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub url: String,
+    pub secret: String,
+}
+
+/// [PendingDelivery] is a queued webhook delivery, with where it has to go.
+#[derive(Debug, Clone)]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub identifier: Identifier,
+    pub kind: String,
+    pub payload: String,
+    /// Attempts that failed so far.
+    pub attempts: u32,
+    pub created_at: DateTime<Utc>,
+    pub url: String,
+    pub secret: String,
+}
+
+impl TryFrom<PendingDeliveryInDB> for PendingDelivery {
+    type Error = Error;
+
+    fn try_from(delivery: PendingDeliveryInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            id: delivery.id,
+            identifier: delivery.identifier.parse()?,
+            kind: delivery.kind,
+            payload: delivery.payload,
+            attempts: delivery.attempts,
+            created_at: delivery.created_at,
+            url: delivery.url,
+            secret: delivery.secret,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct DeadLetterQuery {
+    pub server_id: Option<String>,
+    /// `server_id:client_id` of the webhook's config.
+    pub identifier: Option<String>,
+    pub cursor: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct DeadLetterInDB {
+    pub id: i64,
+    pub identifier: String,
+    pub server_id: String,
+    pub url: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub created_at: DateTime<Utc>,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// [DeadLetter] is a webhook delivery that failed on every attempt.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub identifier: Identifier,
+    /// The URL at the time of the last attempt. Replays use the current URL of the webhook.
+    pub url: String,
+    /// The frame that was POSTed.
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    pub attempts: u32,
+    pub last_error: String,
+    pub created_at: DateTime<Utc>,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    /// The `kind` of the event in the payload.
+    pub fn kind(&self) -> &str {
+        self.payload["event"]["kind"].as_str().unwrap_or_default()
+    }
+}
+
+impl TryFrom<DeadLetterInDB> for DeadLetter {
+    type Error = Error;
+
+    fn try_from(dead_letter: DeadLetterInDB) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            id: dead_letter.id,
+            identifier: dead_letter.identifier.parse()?,
+            url: dead_letter.url,
+            payload: serde_json::from_str(&dead_letter.payload)?,
+            attempts: dead_letter.attempts,
+            last_error: dead_letter.last_error,
+            created_at: dead_letter.created_at,
+            failed_at: dead_letter.failed_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeadLetterPage {
+    pub dead_letters: Vec<DeadLetter>,
+    pub next_cursor: Option<i64>,
+}
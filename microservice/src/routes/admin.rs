@@ -1,20 +1,38 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Extension, Path, Query, State},
     http::StatusCode,
     middleware,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{delete, get, patch, post},
     Json, Router,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use utoipa::IntoParams;
 
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::error::{Error, Result};
+use crate::model::audit::{AuditModelController, AuditPage, AuditQuery, NewAuditEntry};
 use crate::model::config::ConfigModelController;
+use crate::model::status::{ClientStatus, StatusModelController};
+use crate::model::token::{
+    ApiToken, CreatedToken, TokenCreateBody, TokenModelController, TokenUpdateBody,
+};
+use crate::routes::admin_accounts::admin_account_routes;
+use crate::routes::admin_configs::admin_config_routes;
+use crate::routes::admin_connections::admin_connection_routes;
+use crate::routes::admin_transfer::admin_transfer_routes;
+use crate::routes::admin_users::{add_user, admin_user_routes, delete_user, update_user};
+use crate::routes::admin_webhooks::admin_webhook_routes;
+use crate::routes::openapi::ProblemResponses;
 use crate::{
     model::user::{AdminDeleteBody, AdminPostBody, AdminUpdateBody, UserModelController},
     AppState,
 };
 
+/// The `/admin` routes. Deprecated aliases of [admin_v1_routes].
 pub fn admin_routes(app_state: AppState) -> Router {
     Router::new()
         .route("/list", get(handle_admin_list))
@@ -22,127 +40,344 @@ pub fn admin_routes(app_state: AppState) -> Router {
         .route("/delete", delete(handle_admin_delete))
         .route("/update", patch(handle_admin_update))
         .with_state(app_state.clone())
+        .merge(admin_common_routes(app_state.clone()))
         .route_layer(middleware::from_fn_with_state(
             app_state,
             crate::middleware::mw_auth_admin::mw_admin_auth,
         ))
 }
 
-pub async fn handle_admin_list(State(app_state): State<AppState>) -> impl IntoResponse {
-    if let Ok(users) = UserModelController::list_users(&app_state.db_pool).await {
-        tracing::info!("User List Requested: {:#?}", users);
-        return Json(users).into_response();
+/// The `/v1/admin` routes.
+pub fn admin_v1_routes(app_state: AppState) -> Router {
+    admin_user_routes(app_state.clone())
+        .merge(admin_common_routes(app_state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            app_state,
+            crate::middleware::mw_auth_admin::mw_admin_auth,
+        ))
+}
+
+/// Routes that `/admin` and `/v1/admin` have in common.
+fn admin_common_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/status", get(handle_admin_status))
+        .route("/audit", get(handle_admin_audit))
+        .route(
+            "/tokens",
+            get(handle_admin_token_list).post(handle_admin_token_add),
+        )
+        .route(
+            "/tokens/:id",
+            patch(handle_admin_token_update).delete(handle_admin_token_revoke),
+        )
+        .with_state(app_state.clone())
+        .merge(admin_account_routes(app_state.clone()))
+        .merge(admin_config_routes(app_state.clone()))
+        .merge(admin_connection_routes(app_state.clone()))
+        .merge(admin_transfer_routes(app_state.clone()))
+        .merge(admin_webhook_routes(app_state))
+}
+
+pub async fn handle_admin_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+) -> Result<Response> {
+    let mut users = UserModelController::list_users(&app_state.db_pool).await?;
+
+    if let Some(server_id) = admin_ctx.scope() {
+        users.retain(|user| user.server_id == server_id);
     }
 
-    tracing::error!("Failed to list users");
-    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    tracing::debug!("Listed {} users", users.len());
+    Ok(Json(users).into_response())
 }
 
 pub async fn handle_admin_add(
     State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
     Json(body): Json<AdminPostBody>,
-) -> impl IntoResponse {
-    match UserModelController::add_user(body.clone(), &app_state.db_pool).await {
-        Ok(_) => {
-            tracing::info!("User Added: {:#?}", body);
-            Json(AdminResponseBody {
-                success: true,
-                reason: None,
-                user: Some(json!({
-                    "server_id": body.server_id,
-                    "server_list": body.server_list, // FIXME: Return the actual list instead of the admin input
-                })),
-            })
-            .into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to add user: {}", e);
-            let status_code = StatusCode::CONFLICT;
-            let body = Json(AdminResponseBody {
-                success: false,
-                reason: Some(e.to_string()),
-                user: None,
-            });
-
-            (status_code, body).into_response()
-        }
-    }
+) -> Result<Response> {
+    let user = add_user(&app_state, &admin_ctx, address, body).await?;
+
+    Ok(Json(AdminResponseBody {
+        success: true,
+        reason: None,
+        user: Some(json!(user)),
+    })
+    .into_response())
 }
 
 pub async fn handle_admin_delete(
     State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
     Json(body): Json<AdminDeleteBody>,
-) -> impl IntoResponse {
-    match UserModelController::delete_user(body.clone(), &app_state.db_pool.clone()).await {
-        Ok(_) => {
-            match ConfigModelController::delete_config_by_server_id(
-                body.server_id.as_str(),
-                &app_state.db_pool,
-            )
-            .await
-            {
-                Ok(_) => {
-                    tracing::info!("User and Config deleted: {:#?}", body);
-                    Json(AdminResponseBody {
-                        success: true,
-                        reason: None,
-                        user: Some(json!(body)),
-                    })
-                    .into_response()
-                }
-                Err(e) => {
-                    tracing::error!("Failed to delete config: {}", e);
-                    Json(AdminResponseBody {
-                        success: false,
-                        reason: Some(e.to_string()),
-                        user: None,
-                    })
-                    .into_response()
-                }
+) -> Result<Response> {
+    delete_user(&app_state, &admin_ctx, address, body.server_id.clone()).await?;
+
+    Ok(Json(AdminResponseBody {
+        success: true,
+        reason: None,
+        user: Some(json!(body)),
+    })
+    .into_response())
+}
+
+pub async fn handle_admin_update(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Json(body): Json<AdminUpdateBody>,
+) -> Result<Response> {
+    let user = update_user(&app_state, &admin_ctx, address, body).await?;
+
+    Ok(Json(AdminResponseBody {
+        success: true,
+        reason: None,
+        user: Some(json!(user)),
+    })
+    .into_response())
+}
+
+/// Lists every configured client with its live and recorded connection state.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/status",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses((status = 200, body = Vec<ClientStatus>), ProblemResponses)
+)]
+pub async fn handle_admin_status(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+) -> Result<Response> {
+    let mut configs = ConfigModelController::list_configs(&app_state.db_pool).await?;
+
+    if let Some(server_id) = admin_ctx.scope() {
+        configs.retain(|config| config.identifier.server_id() == server_id);
+    }
+
+    let mut statuses = StatusModelController::list_statuses(&app_state.db_pool).await?;
+
+    let active_connections = app_state.active_connections.lock().await;
+
+    let client_statuses = configs
+        .into_iter()
+        .map(|config| {
+            let recorded = statuses.remove(&config.identifier.to_string());
+            let connected_since = active_connections
+                .iter()
+                .filter(|conn| !conn.spectator && conn.identifier == config.identifier)
+                .map(|conn| conn.connected_at)
+                .min();
+
+            ClientStatus {
+                online: connected_since.is_some(),
+                connected_since,
+                last_connected_at: recorded.as_ref().and_then(|s| s.last_connected_at),
+                last_disconnected_at: recorded.as_ref().and_then(|s| s.last_disconnected_at),
+                disconnect_reason: recorded.and_then(|s| s.disconnect_reason),
+                identifier: config.identifier,
             }
-        }
-        Err(e) => Json(AdminResponseBody {
-            success: false,
-            reason: Some(e.to_string()),
-            user: None,
         })
-        .into_response(),
+        .collect::<Vec<_>>();
+
+    Ok(Json(client_statuses).into_response())
+}
+
+/// Lists audit log entries, newest first.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/audit",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(AuditQuery),
+    responses((status = 200, body = AuditPage), ProblemResponses)
+)]
+pub async fn handle_admin_audit(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(mut query): Query<AuditQuery>,
+) -> Result<Response> {
+    match (admin_ctx.scope(), query.server_id.as_deref()) {
+        (Some(scope), Some(server_id)) if scope != server_id => return Err(forbidden(&admin_ctx)),
+        (Some(scope), _) => query.server_id = Some(scope.to_string()),
+        (None, _) => {}
     }
+
+    let page = AuditModelController::list_entries(&query, &app_state.db_pool).await?;
+
+    Ok(Json(page).into_response())
 }
 
-pub async fn handle_admin_update(
+#[utoipa::path(
+    get,
+    path = "/v1/admin/tokens",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(TokenListQuery),
+    responses((status = 200, body = Vec<ApiToken>), ProblemResponses)
+)]
+pub async fn handle_admin_token_list(
     State(app_state): State<AppState>,
-    Json(body): Json<AdminUpdateBody>,
-) -> impl IntoResponse {
-    match UserModelController::update_user(body.clone(), &app_state.db_pool, &app_state.config.SALT)
-        .await
-    {
-        Ok(user) => {
-            tracing::info!("User Updated: {:#?}", user);
-            Json(AdminResponseBody {
-                success: false,
-                reason: None,
-                user: Some(json!(user)),
-            })
-            .into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to update user: {}", e);
-            Json(AdminResponseBody {
-                success: false,
-                reason: Some(e.to_string()),
-                user: None,
-            })
-            .into_response()
-        }
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(query): Query<TokenListQuery>,
+) -> Result<Response> {
+    let server_id = match (admin_ctx.scope(), query.server_id.as_deref()) {
+        (Some(scope), Some(server_id)) if scope != server_id => return Err(forbidden(&admin_ctx)),
+        (Some(scope), _) => Some(scope),
+        (None, server_id) => server_id,
+    };
+
+    let tokens = TokenModelController::list_tokens(server_id, &app_state.db_pool).await?;
+
+    Ok(Json(tokens).into_response())
+}
+
+/// Creates a named token. Its secret is only ever returned here.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/tokens",
+    tag = "admin",
+    security(("bearer" = [])),
+    request_body = TokenCreateBody,
+    responses((status = 201, body = CreatedToken), ProblemResponses)
+)]
+pub async fn handle_admin_token_add(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Json(body): Json<TokenCreateBody>,
+) -> Result<Response> {
+    if !admin_ctx.can(AdminPermission::Manage, Some(&body.server_id)) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    UserModelController::get_user_by_id(&body.server_id, &app_state.db_pool).await?;
+
+    let (token, secret) = TokenModelController::create_token(body, &app_state.db_pool).await?;
+
+    tracing::info!("Token Added: {:#?}", token);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "token.add",
+            format!("token:{}", token.id),
+            address.ip(),
+        )
+        .server_id(&token.server_id)
+        .after(&token),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, secret })).into_response())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/v1/admin/tokens/{id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "ID of the token")),
+    request_body = TokenUpdateBody,
+    responses((status = 200, body = ApiToken), ProblemResponses)
+)]
+pub async fn handle_admin_token_update(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+    Json(body): Json<TokenUpdateBody>,
+) -> Result<Response> {
+    let before = check_token_permission(&app_state, &admin_ctx, id).await?;
+    let token = TokenModelController::update_token(id, body, &app_state.db_pool).await?;
+
+    tracing::info!("Token Updated: {:#?}", token);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "token.update",
+            format!("token:{}", token.id),
+            address.ip(),
+        )
+        .server_id(&token.server_id)
+        .before(&before)
+        .after(&token),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(token).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/tokens/{id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "ID of the token")),
+    responses((status = 200, body = ApiToken), ProblemResponses)
+)]
+pub async fn handle_admin_token_revoke(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    let before = check_token_permission(&app_state, &admin_ctx, id).await?;
+    let token = TokenModelController::revoke_token(id, &app_state.db_pool).await?;
+
+    tracing::info!("Token Revoked: {:#?}", token);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "token.revoke",
+            format!("token:{}", token.id),
+            address.ip(),
+        )
+        .server_id(&token.server_id)
+        .before(&before)
+        .after(&token),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(token).into_response())
+}
+
+/// Checks that the admin may manage the server the token `id` belongs to and returns the token.
+async fn check_token_permission(
+    app_state: &AppState,
+    admin_ctx: &AdminCtx,
+    id: i64,
+) -> Result<ApiToken> {
+    let token = TokenModelController::get_token(id, &app_state.db_pool).await?;
+
+    if !admin_ctx.can(AdminPermission::Manage, Some(&token.server_id)) {
+        return Err(forbidden(admin_ctx));
     }
+
+    Ok(token)
+}
+
+pub fn forbidden(admin_ctx: &AdminCtx) -> Error {
+    tracing::error!("Admin {} is not allowed to do this", admin_ctx.name);
+
+    Error::Forbidden("Insufficient permissions".to_string())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TokenListQuery {
+    pub server_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
-struct AdminResponseBody {
-    success: bool,
+pub struct AdminResponseBody {
+    pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    reason: Option<String>,
+    pub reason: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    user: Option<Value>,
+    pub user: Option<Value>,
 }
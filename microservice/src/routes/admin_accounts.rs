@@ -0,0 +1,240 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::error::Result;
+use crate::model::admin::{
+    Admin, AdminAccountPostBody, AdminModelController, AdminToken, AdminTokenPostBody,
+    CreatedAdminToken,
+};
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::routes::admin::forbidden;
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Routes to manage admin accounts and their tokens. Nested under `/admin` and `/v1/admin`, behind
+/// `mw_admin_auth`.
+pub fn admin_account_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/admins",
+            get(handle_admin_account_list).post(handle_admin_account_add),
+        )
+        .route("/admins/:id", delete(handle_admin_account_delete))
+        .route(
+            "/admins/:id/tokens",
+            get(handle_admin_account_token_list).post(handle_admin_account_token_add),
+        )
+        .route(
+            "/admins/:id/tokens/:token_id",
+            delete(handle_admin_account_token_revoke),
+        )
+        .with_state(app_state)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/admins",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses((status = 200, body = Vec<Admin>), ProblemResponses)
+)]
+pub async fn handle_admin_account_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+) -> Result<Response> {
+    if !admin_ctx.can(AdminPermission::ManageAdmins, None) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let admins = AdminModelController::list_admins(&app_state.db_pool).await?;
+
+    Ok(Json(admins).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/admins",
+    tag = "admin",
+    security(("bearer" = [])),
+    request_body = AdminAccountPostBody,
+    responses((status = 201, body = Admin), ProblemResponses)
+)]
+pub async fn handle_admin_account_add(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Json(body): Json<AdminAccountPostBody>,
+) -> Result<Response> {
+    if !admin_ctx.can(AdminPermission::ManageAdmins, None) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let admin = AdminModelController::add_admin(body, &app_state.db_pool).await?;
+
+    tracing::info!("Admin Added by {}: {:#?}", admin_ctx.name, admin);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "admin.add",
+            format!("admin:{}", admin.name),
+            address.ip(),
+        )
+        .after(&admin),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok((StatusCode::CREATED, Json(admin)).into_response())
+}
+
+/// Deletes an admin together with its tokens.
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/admins/{id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "ID of the admin account")),
+    responses((status = 200, body = Admin), ProblemResponses)
+)]
+pub async fn handle_admin_account_delete(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    if !admin_ctx.can(AdminPermission::ManageAdmins, None) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let admin = AdminModelController::delete_admin(id, &app_state.db_pool).await?;
+
+    tracing::info!("Admin Deleted by {}: {:#?}", admin_ctx.name, admin);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "admin.delete",
+            format!("admin:{}", admin.name),
+            address.ip(),
+        )
+        .before(&admin),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(admin).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/admins/{id}/tokens",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "ID of the admin account")),
+    responses((status = 200, body = Vec<AdminToken>), ProblemResponses)
+)]
+pub async fn handle_admin_account_token_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    if !can_manage_tokens_of(&admin_ctx, id) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let tokens = AdminModelController::list_admin_tokens(id, &app_state.db_pool).await?;
+
+    Ok(Json(tokens).into_response())
+}
+
+/// Creates a token for an admin. Its secret is only ever returned here.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/admins/{id}/tokens",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "ID of the admin account")),
+    request_body = AdminTokenPostBody,
+    responses((status = 201, body = CreatedAdminToken), ProblemResponses)
+)]
+pub async fn handle_admin_account_token_add(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+    Json(body): Json<AdminTokenPostBody>,
+) -> Result<Response> {
+    if !can_manage_tokens_of(&admin_ctx, id) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let (token, secret) =
+        AdminModelController::create_admin_token(id, body, &app_state.db_pool).await?;
+
+    tracing::info!("Admin Token Added by {}: {:#?}", admin_ctx.name, token);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "admin_token.add",
+            format!("admin_token:{}", token.id),
+            address.ip(),
+        )
+        .after(&token),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedAdminToken { token, secret }),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/admins/{id}/tokens/{token_id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "ID of the admin account"), ("token_id" = i64, Path, description = "ID of the token")),
+    responses((status = 200, body = AdminToken), ProblemResponses)
+)]
+pub async fn handle_admin_account_token_revoke(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path((id, token_id)): Path<(i64, i64)>,
+) -> Result<Response> {
+    if !can_manage_tokens_of(&admin_ctx, id) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let token = AdminModelController::revoke_admin_token(id, token_id, &app_state.db_pool).await?;
+
+    tracing::info!("Admin Token Revoked by {}: {:#?}", admin_ctx.name, token);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "admin_token.revoke",
+            format!("admin_token:{}", token.id),
+            address.ip(),
+        )
+        .after(&token),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(token).into_response())
+}
+
+/// Every admin may manage their own tokens, superadmins may manage everyone's.
+fn can_manage_tokens_of(admin_ctx: &AdminCtx, admin_id: i64) -> bool {
+    admin_ctx.admin_id == Some(admin_id) || admin_ctx.can(AdminPermission::ManageAdmins, None)
+}
@@ -0,0 +1,352 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::ctx::ctx_client::Identifier;
+use crate::error::{Error, Result};
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::config::{ClientConfig, ConfigModelController};
+use crate::routes::admin::forbidden;
+use crate::routes::config::{is_config_allowed, ConfigRequestBody};
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Routes to manage the config of any client. Nested under `/admin` and `/v1/admin`, behind
+/// `mw_admin_auth`.
+pub fn admin_config_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/configs", get(handle_admin_config_list))
+        .route(
+            "/configs/:identifier",
+            get(handle_admin_config_get)
+                .put(handle_admin_config_put)
+                .delete(handle_admin_config_delete),
+        )
+        .with_state(app_state)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/configs",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(ConfigListQuery),
+    responses((status = 200, body = Vec<ClientConfig>), ProblemResponses)
+)]
+pub async fn handle_admin_config_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(query): Query<ConfigListQuery>,
+) -> Result<Response> {
+    let server_id = query.server_id.as_deref().or(admin_ctx.scope());
+
+    if !admin_ctx.can(AdminPermission::View, server_id) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let configs = match server_id {
+        Some(server_id) => {
+            ConfigModelController::get_config_by_server_id(server_id, &app_state.db_pool).await?
+        }
+        None => ConfigModelController::list_configs(&app_state.db_pool).await?,
+    };
+
+    Ok(Json(configs).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/configs/{identifier}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("identifier" = String, Path, description = "`server_id:client_id`")),
+    responses((status = 200, body = ClientConfig), ProblemResponses)
+)]
+pub async fn handle_admin_config_get(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(identifier): Path<String>,
+) -> Result<Response> {
+    let identifier = parse_identifier(&identifier)?;
+
+    if !admin_ctx.can(AdminPermission::View, Some(identifier.server_id())) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let config =
+        ConfigModelController::get_config_by_identifier(&identifier, &app_state.db_pool).await?;
+
+    Ok(Json(config).into_response())
+}
+
+/// Creates or replaces the config of `identifier`, with the same checks as `/config/add`.
+#[utoipa::path(
+    put,
+    path = "/v1/admin/configs/{identifier}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("identifier" = String, Path, description = "`server_id:client_id`")),
+    request_body = ConfigRequestBody,
+    responses((status = 200, body = ClientConfig, description = "Replaced"), (status = 201, body = ClientConfig, description = "Created"), ProblemResponses)
+)]
+pub async fn handle_admin_config_put(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(identifier): Path<String>,
+    Json(body): Json<ConfigRequestBody>,
+) -> Result<Response> {
+    let identifier = parse_identifier(&identifier)?;
+
+    if !admin_ctx.can(AdminPermission::Manage, Some(identifier.server_id())) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    is_config_allowed(&identifier, &body, &app_state.db_pool).await?;
+
+    let before = match ConfigModelController::get_config_by_identifier(
+        &identifier,
+        &app_state.db_pool,
+    )
+    .await
+    {
+        Ok(config) => Some(config),
+        Err(Error::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let client_config = ConfigModelController::add_or_update_config(
+        &identifier,
+        &body.subscriptions,
+        &body.templates,
+        &app_state.db_pool,
+    )
+    .await?;
+
+    tracing::info!(
+        "Client Config Set by {}: {:#?}",
+        admin_ctx.name,
+        client_config
+    );
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "config.update",
+            identifier.to_string(),
+            address.ip(),
+        )
+        .server_id(identifier.server_id())
+        .before(&before)
+        .after(&client_config),
+        &app_state.db_pool,
+    )
+    .await;
+
+    let status = if before.is_some() {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+
+    Ok((status, Json(client_config)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/configs/{identifier}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("identifier" = String, Path, description = "`server_id:client_id`")),
+    responses((status = 200, body = ClientConfig), ProblemResponses)
+)]
+pub async fn handle_admin_config_delete(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(identifier): Path<String>,
+) -> Result<Response> {
+    let identifier = parse_identifier(&identifier)?;
+
+    if !admin_ctx.can(AdminPermission::Manage, Some(identifier.server_id())) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let client_config =
+        ConfigModelController::delete_config_by_identifier(&identifier, &app_state.db_pool).await?;
+
+    tracing::info!(
+        "Client Config Deleted by {}: {:#?}",
+        admin_ctx.name,
+        client_config
+    );
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "config.delete",
+            identifier.to_string(),
+            address.ip(),
+        )
+        .server_id(identifier.server_id())
+        .before(&client_config),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(client_config).into_response())
+}
+
+pub(crate) fn parse_identifier(identifier: &str) -> Result<Identifier> {
+    identifier
+        .parse()
+        .map_err(|_| Error::BadRequest(format!("Invalid identifier: {identifier}")))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ConfigListQuery {
+    pub server_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::ctx::ctx_admin::AdminRole;
+    use crate::model::user::{AdminPostBody, UserModelController};
+
+    async fn app(admin_ctx: AdminCtx) -> Router {
+        let app_state = AppState::for_tests().await;
+
+        for server_id in ["kiwitech", "other"] {
+            UserModelController::add_user(
+                AdminPostBody {
+                    server_id: server_id.to_string(),
+                    server_list: vec!["kiwitech".to_string()],
+                    auth_token: "secret".to_string(),
+                    allowed_cidrs: None,
+                },
+                &app_state.db_pool,
+            )
+            .await
+            .unwrap();
+        }
+
+        admin_config_routes(app_state)
+            .layer(Extension(admin_ctx))
+            .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+    }
+
+    fn server_admin() -> AdminCtx {
+        AdminCtx::new(
+            Some(1),
+            "alice",
+            AdminRole::ServerAdmin,
+            Some("kiwitech".to_string()),
+        )
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        subscriptions: &[&str],
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::json!({ "subscriptions": subscriptions }).to_string(),
+            ))
+            .unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[test]
+    fn parse_identifier_rejects_invalid_identifiers() {
+        assert!(parse_identifier("kiwitech:smp").is_ok());
+        assert!(matches!(
+            parse_identifier("kiwitech"),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn put_creates_then_replaces() {
+        let app = app(AdminCtx::bootstrap()).await;
+
+        let (status, config) = send(&app, "PUT", "/configs/kiwitech:smp", &["kiwitech"]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(config["subscriptions"], serde_json::json!(["kiwitech"]));
+
+        let (status, config) = send(&app, "PUT", "/configs/kiwitech:smp", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(config["subscriptions"], serde_json::json!([]));
+
+        let (status, _) = send(&app, "GET", "/configs/kiwitech:smp", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn put_checks_the_server_list() {
+        let app = app(AdminCtx::bootstrap()).await;
+
+        let (status, _) = send(&app, "PUT", "/configs/kiwitech:smp", &["other"]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&app, "PUT", "/configs/nowhere:smp", &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, "PUT", "/configs/kiwitech", &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn delete_fails_for_missing_configs() {
+        let app = app(AdminCtx::bootstrap()).await;
+
+        send(&app, "PUT", "/configs/kiwitech:smp", &[]).await;
+
+        let (status, _) = send(&app, "DELETE", "/configs/kiwitech:smp", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(&app, "DELETE", "/configs/kiwitech:smp", &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn server_admins_only_manage_their_own_server() {
+        let app = app(server_admin()).await;
+
+        let (status, _) = send(&app, "PUT", "/configs/kiwitech:smp", &[]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, "PUT", "/configs/other:smp", &[]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, "GET", "/configs/other:smp", &[]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, configs) = send(&app, "GET", "/configs", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(configs.as_array().unwrap().len(), 1);
+
+        let (status, _) = send(&app, "GET", "/configs?server_id=other", &[]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,195 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, State},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::error::{Error, Result};
+use crate::message::Event;
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::routes::admin::forbidden;
+use crate::routes::openapi::ProblemResponses;
+use crate::{AppState, ConnectionInfo};
+
+/// Routes to manage live websocket connections. Nested under `/admin` and `/v1/admin`, behind
+/// `mw_admin_auth`.
+pub fn admin_connection_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/connections", get(handle_admin_connection_list))
+        .route("/connections/:id/kick", post(handle_admin_connection_kick))
+        .route("/broadcast", post(handle_admin_broadcast))
+        .with_state(app_state)
+}
+
+/// Lists the live websocket connections the admin may see.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/connections",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses((status = 200, body = Vec<ConnectionInfo>), ProblemResponses)
+)]
+pub async fn handle_admin_connection_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+) -> Result<Response> {
+    let connections = app_state
+        .active_connections
+        .lock()
+        .await
+        .iter()
+        .filter(|conn| admin_ctx.can(AdminPermission::View, Some(conn.identifier.server_id())))
+        .map(|conn| conn.info())
+        .collect::<Vec<_>>();
+
+    Ok(Json(connections).into_response())
+}
+
+/// Closes a connection and returns it as it was.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/connections/{id}/kick",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = u64, Path, description = "ID of the connection")),
+    request_body = KickBody,
+    responses((status = 200, body = ConnectionInfo), ProblemResponses)
+)]
+pub async fn handle_admin_connection_kick(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(connection_id): Path<u64>,
+    Json(body): Json<KickBody>,
+) -> Result<Response> {
+    let Some(conn) = app_state
+        .active_connections
+        .lock()
+        .await
+        .iter()
+        .find(|conn| conn.connection_id == connection_id)
+        .cloned()
+    else {
+        return Err(Error::NotFound("Connection not found".to_string()));
+    };
+
+    if !admin_ctx.can(AdminPermission::Kick, Some(conn.identifier.server_id())) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    if !conn.kick(&body.reason) {
+        return Err(Error::NotFound("Connection not found".to_string()));
+    }
+
+    let info = conn.info();
+
+    tracing::info!(
+        "Connection {} of {} kicked by {}: {}",
+        connection_id,
+        conn.identifier,
+        admin_ctx.name,
+        body.reason
+    );
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "connection.kick",
+            conn.identifier.to_string(),
+            address.ip(),
+        )
+        .server_id(conn.identifier.server_id())
+        .before(&info)
+        .after(json!({ "reason": body.reason })),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(info).into_response())
+}
+
+/// Sends a notice to every connection, or to those of one server.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/broadcast",
+    tag = "admin",
+    security(("bearer" = [])),
+    request_body = BroadcastBody,
+    responses((status = 200, body = BroadcastResponse), ProblemResponses)
+)]
+pub async fn handle_admin_broadcast(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Json(body): Json<BroadcastBody>,
+) -> Result<Response> {
+    if !admin_ctx.can(AdminPermission::Broadcast, body.server_id.as_deref()) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    if body.message.trim().is_empty() {
+        return Err(Error::BadRequest("Message cannot be empty".to_string()));
+    }
+
+    let event = Event::Notice {
+        message: body.message.clone(),
+    };
+
+    let delivered = app_state
+        .active_connections
+        .lock()
+        .await
+        .iter()
+        .filter(|conn| {
+            body.server_id
+                .as_deref()
+                .is_none_or(|server_id| conn.identifier.server_id() == server_id)
+        })
+        .filter(|conn| conn.send_event(None, &event))
+        .count();
+
+    tracing::info!(
+        "Notice broadcast by {} to {} connections: {}",
+        admin_ctx.name,
+        delivered,
+        body.message
+    );
+
+    let target = body.server_id.clone().unwrap_or_else(|| "*".to_string());
+    let mut entry = NewAuditEntry::new(admin_ctx.actor(), "broadcast", target, address.ip())
+        .after(json!({ "message": body.message, "delivered": delivered }));
+
+    if let Some(server_id) = &body.server_id {
+        entry = entry.server_id(server_id);
+    }
+
+    AuditModelController::record(entry, &app_state.db_pool).await;
+
+    Ok(Json(BroadcastResponse { delivered }).into_response())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct KickBody {
+    /// Sent to the client in the close frame and recorded as its disconnect reason. The close
+    /// frame only carries its first 123 bytes.
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BroadcastBody {
+    pub message: String,
+    /// Only notify the clients of this server. Omit it to notify every client.
+    pub server_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BroadcastResponse {
+    /// The number of connections the notice was queued for.
+    pub delivered: usize,
+}
@@ -0,0 +1,138 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::IntoParams;
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::error::{problem_response, Result};
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::transfer::{
+    ExportDocument, ImportOptions, ImportReport, TransferModelController,
+};
+use crate::routes::admin::forbidden;
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Routes to move users and configs between hosts. Nested under `/admin` and `/v1/admin`, behind
+/// `mw_admin_auth`.
+pub fn admin_transfer_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/export", get(handle_admin_export))
+        .route("/import", post(handle_admin_import))
+        .with_state(app_state)
+}
+
+/// Exports every user and config.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/export",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(ExportQuery),
+    responses((status = 200, body = ExportDocument), ProblemResponses)
+)]
+pub async fn handle_admin_export(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response> {
+    let permission = if query.include_token_hashes {
+        AdminPermission::Manage
+    } else {
+        AdminPermission::View
+    };
+
+    if !admin_ctx.can(permission, None) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let document =
+        TransferModelController::export(query.include_token_hashes, &app_state.db_pool).await?;
+
+    tracing::info!(
+        "Export of {} users and {} configs requested by {}",
+        document.users.len(),
+        document.configs.len(),
+        admin_ctx.name
+    );
+    AuditModelController::record(
+        NewAuditEntry::new(admin_ctx.actor(), "export", "*", address.ip()).after(json!({
+            "include_token_hashes": query.include_token_hashes,
+            "users": document.users.len(),
+            "configs": document.configs.len(),
+            "webhooks": document.webhooks.len(),
+            "tokens": document.tokens.as_ref().map(Vec::len),
+        })),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(document).into_response())
+}
+
+/// Imports an export document. Conflicts fail the import with `409 Conflict` and the report
+/// as problem extensions, unless it is a dry run.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/import",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(ImportOptions),
+    request_body = ExportDocument,
+    responses((status = 200, body = ImportReport), ProblemResponses)
+)]
+pub async fn handle_admin_import(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(options): Query<ImportOptions>,
+    Json(document): Json<ExportDocument>,
+) -> Result<Response> {
+    if !admin_ctx.can(AdminPermission::Manage, None) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let report = TransferModelController::import(document, &options, &app_state.db_pool).await?;
+
+    if report.applied {
+        tracing::info!("Import applied by {}: {:?}", admin_ctx.name, report);
+        AuditModelController::record(
+            NewAuditEntry::new(admin_ctx.actor(), "import", "*", address.ip()).after(&report),
+            &app_state.db_pool,
+        )
+        .await;
+    }
+
+    if !report.conflicts.is_empty() && !report.dry_run {
+        let Value::Object(extensions) = serde_json::to_value(&report)? else {
+            unreachable!("an import report is always an object");
+        };
+
+        return Ok(problem_response(
+            StatusCode::CONFLICT,
+            Some(format!(
+                "Import has {} conflicts, nothing was applied",
+                report.conflicts.len()
+            )),
+            extensions,
+        ));
+    }
+
+    Ok(Json(report).into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ExportQuery {
+    /// Include the hashes of the primary tokens and the named tokens.
+    #[serde(default)]
+    pub include_token_hashes: bool,
+}
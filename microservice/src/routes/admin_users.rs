@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::json;
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::error::{Error, Result};
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::user::{
+    AdminDeleteBody, AdminPostBody, AdminUpdateBody, UserModelController, UserNoToken, UserPage,
+    UserPatchBody, UserQuery, DISCORD_SERVER_ID,
+};
+use crate::routes::admin::forbidden;
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Routes to manage users as resources. Nested under `/v1/admin`, behind `mw_admin_auth`.
+pub fn admin_user_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/users",
+            get(handle_admin_user_list).post(handle_admin_user_add),
+        )
+        .route(
+            "/users/:server_id",
+            get(handle_admin_user_get)
+                .patch(handle_admin_user_update)
+                .delete(handle_admin_user_delete),
+        )
+        .with_state(app_state)
+}
+
+/// Lists the users the admin may see, a page at a time, with their client and connection counts.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/users",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(UserQuery),
+    responses((status = 200, body = UserPage), ProblemResponses)
+)]
+pub async fn handle_admin_user_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(query): Query<UserQuery>,
+) -> Result<Json<UserPage>> {
+    let mut page =
+        UserModelController::list_users_page(&query, admin_ctx.scope(), &app_state.db_pool).await?;
+
+    let mut connections = HashMap::<String, usize>::new();
+    for conn in app_state.active_connections.lock().await.iter() {
+        *connections
+            .entry(conn.identifier.server_id().to_string())
+            .or_default() += 1;
+    }
+
+    for summary in page.users.iter_mut() {
+        summary.connections = connections
+            .get(&summary.user.server_id)
+            .copied()
+            .unwrap_or_default();
+    }
+
+    tracing::debug!("Listed {} users after {:?}", page.users.len(), query.cursor);
+    Ok(Json(page))
+}
+
+/// Creates a user and returns it as stored.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/users",
+    tag = "admin",
+    security(("bearer" = [])),
+    request_body = AdminPostBody,
+    responses((status = 201, body = UserNoToken), ProblemResponses)
+)]
+pub async fn handle_admin_user_add(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Json(body): Json<AdminPostBody>,
+) -> Result<Response> {
+    let user = add_user(&app_state, &admin_ctx, address, body).await?;
+
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/users/{server_id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("server_id" = String, Path, description = "ID of the server the user belongs to")),
+    responses((status = 200, body = UserNoToken), ProblemResponses)
+)]
+pub async fn handle_admin_user_get(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(server_id): Path<String>,
+) -> Result<Json<UserNoToken>> {
+    if !admin_ctx.can(AdminPermission::View, Some(&server_id)) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let user = UserModelController::get_user_by_id(&server_id, &app_state.db_pool).await?;
+
+    Ok(Json(user.into()))
+}
+
+/// Changes a user and returns it as stored.
+#[utoipa::path(
+    patch,
+    path = "/v1/admin/users/{server_id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("server_id" = String, Path, description = "ID of the server the user belongs to")),
+    request_body = UserPatchBody,
+    responses((status = 200, body = UserNoToken), ProblemResponses)
+)]
+pub async fn handle_admin_user_update(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(server_id): Path<String>,
+    Json(body): Json<UserPatchBody>,
+) -> Result<Json<UserNoToken>> {
+    let user = update_user(&app_state, &admin_ctx, address, body.for_user(server_id)).await?;
+
+    Ok(Json(user))
+}
+
+/// Deletes a user together with its tokens and configs, and returns it.
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/users/{server_id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("server_id" = String, Path, description = "ID of the server the user belongs to")),
+    responses((status = 200, body = UserNoToken), ProblemResponses)
+)]
+pub async fn handle_admin_user_delete(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(server_id): Path<String>,
+) -> Result<Json<UserNoToken>> {
+    let user = delete_user(&app_state, &admin_ctx, address, server_id).await?;
+
+    Ok(Json(user))
+}
+
+/// Adds a user on behalf of `admin_ctx`. Shared by `/admin/add` and `POST /v1/admin/users`.
+pub async fn add_user(
+    app_state: &AppState,
+    admin_ctx: &AdminCtx,
+    address: SocketAddr,
+    body: AdminPostBody,
+) -> Result<UserNoToken> {
+    if !admin_ctx.can(AdminPermission::Manage, Some(&body.server_id)) {
+        return Err(forbidden(admin_ctx));
+    }
+    ensure_can_link(admin_ctx, &body.server_list, &[])?;
+
+    let user = UserModelController::add_user(body, &app_state.db_pool).await?;
+
+    tracing::info!("User Added: {:#?}", user);
+    AuditModelController::record(
+        NewAuditEntry::new(admin_ctx.actor(), "user.add", &user.server_id, address.ip())
+            .server_id(&user.server_id)
+            .after(&user),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(user)
+}
+
+/// Fails if `server_list` adds an entry to `current` that `admin_ctx` may not link. The Discord
+/// bot is linked to every user anyway.
+fn ensure_can_link(admin_ctx: &AdminCtx, server_list: &[String], current: &[String]) -> Result<()> {
+    match server_list.iter().find(|entry| {
+        *entry != DISCORD_SERVER_ID && !current.contains(entry) && !admin_ctx.can_link(entry)
+    }) {
+        Some(entry) => Err(Error::Forbidden(format!(
+            "Admin {} may not link {entry}",
+            admin_ctx.name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Deletes a user with its tokens and configs on behalf of `admin_ctx`, and closes the
+/// connections of its clients. Shared by `/admin/delete` and `DELETE /v1/admin/users/{server_id}`.
+pub async fn delete_user(
+    app_state: &AppState,
+    admin_ctx: &AdminCtx,
+    address: SocketAddr,
+    server_id: String,
+) -> Result<UserNoToken> {
+    if !admin_ctx.can(AdminPermission::Manage, Some(&server_id)) {
+        return Err(forbidden(admin_ctx));
+    }
+
+    let user =
+        UserModelController::delete_user(AdminDeleteBody { server_id }, &app_state.db_pool).await?;
+
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "user.delete",
+            &user.server_id,
+            address.ip(),
+        )
+        .server_id(&user.server_id)
+        .before(&user),
+        &app_state.db_pool,
+    )
+    .await;
+
+    let kicked = app_state
+        .active_connections
+        .lock()
+        .await
+        .iter()
+        .filter(|conn| conn.identifier.server_id() == user.server_id)
+        .filter(|conn| conn.kick("Server deleted"))
+        .count();
+
+    tracing::info!(
+        "User and Config deleted, {} connections closed: {:#?}",
+        kicked,
+        user
+    );
+    Ok(user)
+}
+
+/// Updates a user on behalf of `admin_ctx`. Shared by `/admin/update` and
+/// `PATCH /v1/admin/users/{server_id}`.
+pub async fn update_user(
+    app_state: &AppState,
+    admin_ctx: &AdminCtx,
+    address: SocketAddr,
+    body: AdminUpdateBody,
+) -> Result<UserNoToken> {
+    if !admin_ctx.can(AdminPermission::Manage, Some(&body.server_id)) {
+        return Err(forbidden(admin_ctx));
+    }
+
+    let before = UserModelController::get_user_by_id(&body.server_id, &app_state.db_pool).await?;
+    if let Some(server_list) = &body.server_list {
+        ensure_can_link(admin_ctx, server_list, &before.server_list)?;
+    }
+
+    let auth_token_changed = body.auth_token.is_some();
+    let user = UserModelController::update_user(body, &app_state.db_pool).await?;
+
+    tracing::info!("User Updated: {:#?}", user);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "user.update",
+            &user.server_id,
+            address.ip(),
+        )
+        .server_id(&user.server_id)
+        .before(json!({
+            "server_id": before.server_id,
+            "server_list": before.server_list,
+            "allowed_cidrs": before.allowed_cidrs,
+        }))
+        .after(json!({
+            "server_id": user.server_id,
+            "server_list": user.server_list,
+            "allowed_cidrs": user.allowed_cidrs,
+            "auth_token_changed": auth_token_changed,
+        })),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::ws::Message;
+
+    use super::*;
+    use crate::ctx::ctx_admin::AdminRole;
+    use crate::ActiveConnection;
+
+    fn address() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 4000))
+    }
+
+    async fn add(app_state: &AppState, server_id: &str) {
+        UserModelController::add_user(
+            AdminPostBody {
+                server_id: server_id.to_string(),
+                server_list: vec![],
+                auth_token: "secret".to_string(),
+                allowed_cidrs: None,
+            },
+            &app_state.db_pool,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_user_closes_the_connections_of_its_clients() {
+        let app_state = AppState::for_tests().await;
+        add(&app_state, "kiwitech").await;
+        add(&app_state, "other").await;
+
+        let (deleted, mut deleted_receiver) = ActiveConnection::for_tests("kiwitech:smp", &[]);
+        let (kept, mut kept_receiver) = ActiveConnection::for_tests("other:smp", &[]);
+        app_state
+            .active_connections
+            .lock()
+            .await
+            .extend([deleted, kept]);
+
+        delete_user(
+            &app_state,
+            &AdminCtx::bootstrap(),
+            address(),
+            "kiwitech".to_string(),
+        )
+        .await
+        .unwrap();
+
+        match deleted_receiver.try_recv() {
+            Ok(Message::Close(Some(frame))) => assert_eq!(frame.reason, "Server deleted"),
+            message => panic!("Expected a close frame, got {message:?}"),
+        }
+        assert!(kept_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn delete_user_fails_for_unknown_servers() {
+        let app_state = AppState::for_tests().await;
+
+        assert!(matches!(
+            delete_user(
+                &app_state,
+                &AdminCtx::bootstrap(),
+                address(),
+                "nowhere".to_string()
+            )
+            .await,
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_counts_connections_per_server() {
+        let app_state = AppState::for_tests().await;
+        add(&app_state, "kiwitech").await;
+        add(&app_state, "other").await;
+
+        let (smp, _smp_receiver) = ActiveConnection::for_tests("kiwitech:smp", &[]);
+        let (creative, _creative_receiver) = ActiveConnection::for_tests("kiwitech:creative", &[]);
+        app_state
+            .active_connections
+            .lock()
+            .await
+            .extend([smp, creative]);
+
+        let Json(page) = handle_admin_user_list(
+            State(app_state),
+            Extension(AdminCtx::bootstrap()),
+            Query(UserQuery::default()),
+        )
+        .await
+        .unwrap();
+
+        let connections: Vec<_> = page
+            .users
+            .iter()
+            .map(|summary| (summary.user.server_id.as_str(), summary.connections))
+            .collect();
+        assert_eq!(connections, [("kiwitech", 2), ("other", 0)]);
+    }
+
+    #[tokio::test]
+    async fn server_admins_only_list_their_own_server() {
+        let app_state = AppState::for_tests().await;
+        add(&app_state, "kiwitech").await;
+        add(&app_state, "other").await;
+
+        let admin_ctx = AdminCtx::new(
+            Some(1),
+            "alice",
+            AdminRole::ServerAdmin,
+            Some("kiwitech".to_string()),
+        );
+        let Json(page) = handle_admin_user_list(
+            State(app_state),
+            Extension(admin_ctx),
+            Query(UserQuery::default()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(page.users.len(), 1);
+        assert_eq!(page.users[0].user.server_id, "kiwitech");
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, Query, State},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ctx::ctx_admin::{AdminCtx, AdminPermission};
+use crate::error::{Error, Result};
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::webhook::{
+    DeadLetter, DeadLetterPage, DeadLetterQuery, Webhook, WebhookModelController,
+};
+use crate::routes::admin::forbidden;
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Routes to inspect webhooks and the deliveries that failed for good. Nested under `/admin` and
+/// `/v1/admin`, behind `mw_admin_auth`.
+pub fn admin_webhook_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/webhooks", get(handle_admin_webhook_list))
+        .route("/webhooks/dead-letters", get(handle_admin_dead_letter_list))
+        .route(
+            "/webhooks/dead-letters/:id",
+            delete(handle_admin_dead_letter_delete),
+        )
+        .route(
+            "/webhooks/dead-letters/:id/replay",
+            post(handle_admin_dead_letter_replay),
+        )
+        .with_state(app_state)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/webhooks",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(WebhookListQuery),
+    responses((status = 200, body = Vec<Webhook>), ProblemResponses)
+)]
+pub async fn handle_admin_webhook_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(query): Query<WebhookListQuery>,
+) -> Result<Response> {
+    let server_id = query.server_id.as_deref().or(admin_ctx.scope());
+
+    if !admin_ctx.can(AdminPermission::View, server_id) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let webhooks = WebhookModelController::list_webhooks(server_id, &app_state.db_pool).await?;
+
+    Ok(Json(webhooks).into_response())
+}
+
+/// Lists deliveries that failed on every attempt, newest first.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/webhooks/dead-letters",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(DeadLetterQuery),
+    responses((status = 200, body = DeadLetterPage), ProblemResponses)
+)]
+pub async fn handle_admin_dead_letter_list(
+    State(app_state): State<AppState>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Query(mut query): Query<DeadLetterQuery>,
+) -> Result<Response> {
+    match (admin_ctx.scope(), query.server_id.as_deref()) {
+        (Some(scope), Some(server_id)) if scope != server_id => return Err(forbidden(&admin_ctx)),
+        (Some(scope), _) => query.server_id = Some(scope.to_string()),
+        (None, _) => {}
+    }
+
+    let page = WebhookModelController::list_dead_letters(&query, &app_state.db_pool).await?;
+
+    Ok(Json(page).into_response())
+}
+
+/// Makes one more attempt to deliver a dead letter, to the current URL of its webhook.
+///
+/// The dead letter is removed if the attempt succeeds, and kept with the new error if not.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/webhooks/dead-letters/{id}/replay",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "Id of the dead letter")),
+    responses((status = 200, body = ReplayResult), ProblemResponses)
+)]
+pub async fn handle_admin_dead_letter_replay(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    let dead_letter = WebhookModelController::get_dead_letter(id, &app_state.db_pool).await?;
+
+    if !admin_ctx.can(
+        AdminPermission::Manage,
+        Some(dead_letter.identifier.server_id()),
+    ) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let webhook = match WebhookModelController::get_webhook(
+        &dead_letter.identifier,
+        &app_state.db_pool,
+    )
+    .await
+    {
+        Ok(webhook) => webhook,
+        Err(Error::NotFound(_)) => {
+            return Err(Error::Conflict(format!(
+                "{} no longer has a webhook",
+                dead_letter.identifier
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    let payload = serde_json::to_string(&dead_letter.payload)?;
+    let result = match app_state
+        .webhooks
+        .deliver(&webhook.url, &webhook.secret, dead_letter.kind(), &payload)
+        .await
+    {
+        Ok(()) => {
+            WebhookModelController::delete_dead_letter(id, &app_state.db_pool).await?;
+            ReplayResult {
+                delivered: true,
+                error: None,
+            }
+        }
+        Err(e) => {
+            WebhookModelController::record_attempt(id, &e, &app_state.db_pool).await?;
+            ReplayResult {
+                delivered: false,
+                error: Some(e),
+            }
+        }
+    };
+
+    tracing::info!(
+        "Dead Letter {id} Replayed by {}: {:?}",
+        admin_ctx.name,
+        result
+    );
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "webhook.replay",
+            dead_letter.identifier.to_string(),
+            address.ip(),
+        )
+        .server_id(dead_letter.identifier.server_id())
+        .before(&dead_letter)
+        .after(&result),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(result).into_response())
+}
+
+/// Discards a dead letter without delivering it.
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/webhooks/dead-letters/{id}",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "Id of the dead letter")),
+    responses((status = 200, body = DeadLetter), ProblemResponses)
+)]
+pub async fn handle_admin_dead_letter_delete(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(admin_ctx): Extension<AdminCtx>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    let dead_letter = WebhookModelController::get_dead_letter(id, &app_state.db_pool).await?;
+
+    if !admin_ctx.can(
+        AdminPermission::Manage,
+        Some(dead_letter.identifier.server_id()),
+    ) {
+        return Err(forbidden(&admin_ctx));
+    }
+
+    let dead_letter = WebhookModelController::delete_dead_letter(id, &app_state.db_pool).await?;
+
+    tracing::info!("Dead Letter {id} Discarded by {}", admin_ctx.name);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            admin_ctx.actor(),
+            "webhook.discard",
+            dead_letter.identifier.to_string(),
+            address.ip(),
+        )
+        .server_id(dead_letter.identifier.server_id())
+        .before(&dead_letter),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(dead_letter).into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct WebhookListQuery {
+    pub server_id: Option<String>,
+}
+
+/// [ReplayResult] tells if replaying a dead letter delivered it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReplayResult {
+    pub delivered: bool,
+    /// Why the attempt failed, if it did.
+    pub error: Option<String>,
+}
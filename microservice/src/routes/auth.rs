@@ -0,0 +1,44 @@
+use axum::extract::{Extension, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{middleware, Json, Router};
+
+use crate::ctx::ctx_client::ClientCtx;
+use crate::middleware::mw_auth_client::mw_client_auth;
+use crate::model::token::{AuthenticatedToken, TokenScope};
+use crate::routes::openapi::ProblemResponses;
+use crate::websocket::ticket::IssuedTicket;
+use crate::AppState;
+
+pub fn auth_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/ticket", post(handle_ticket_post))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::Spectate),
+            mw_client_auth,
+        ))
+        .with_state(app_state)
+}
+
+/// Issues a single-use ticket that lets the client open `/ws?ticket=...` without headers.
+///
+/// Spectator tokens get a ticket for a read-only connection.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/ticket",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    responses((status = 201, body = IssuedTicket), ProblemResponses)
+)]
+pub async fn handle_ticket_post(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Extension(token): Extension<AuthenticatedToken>,
+) -> impl IntoResponse {
+    let ticket = app_state.tickets.issue(client_ctx.identifier(), &token);
+
+    tracing::info!("Websocket Ticket Issued for {}", client_ctx.identifier);
+
+    (StatusCode::CREATED, Json(ticket))
+}
@@ -1,130 +1,218 @@
+use std::net::SocketAddr;
+
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
 use axum::{
-    extract::{Extension, Json, State},
+    extract::{ConnectInfo, Extension, Json, State},
     middleware, Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use utoipa::ToSchema;
 
 use crate::config::Config;
 use crate::ctx::ctx_client::{ClientCtx, Identifier};
+use crate::error::{Error, Result};
 use crate::middleware::mw_auth_client::mw_client_auth;
-use crate::model::config::{ClientConfig, ConfigModelController};
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::config::{
+    check_subscriptions, ClientConfig, ConfigModelController, SubscriptionTemplates,
+};
+use crate::model::token::TokenScope;
 use crate::model::user::UserModelController;
+use crate::routes::openapi::ProblemResponses;
 use crate::AppState;
 
 pub fn config_routes(app_state: AppState) -> Router {
     Router::new()
         .route("/get", get(handle_config_get))
         .route("/list", get(handle_config_list))
-        .route("/add", post(handle_config_post))
         .route_layer(middleware::from_fn_with_state(
-            app_state.clone(),
+            (app_state.clone(), TokenScope::ConfigRead),
             mw_client_auth,
         ))
+        .merge(
+            Router::new()
+                .route("/add", post(handle_config_post))
+                .route_layer(middleware::from_fn_with_state(
+                    (app_state.clone(), TokenScope::ConfigWrite),
+                    mw_client_auth,
+                )),
+        )
         .with_state(app_state)
 }
 
+/// Config routes of the `/v1` API, where the config is a resource rather than a set of verbs.
+pub fn config_v1_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/config", get(handle_config_get))
+        .route("/configs", get(handle_config_list))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::ConfigRead),
+            mw_client_auth,
+        ))
+        .merge(
+            Router::new()
+                .route("/config", put(handle_config_put))
+                .route_layer(middleware::from_fn_with_state(
+                    (app_state.clone(), TokenScope::ConfigWrite),
+                    mw_client_auth,
+                )),
+        )
+        .with_state(app_state)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/config",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    responses((status = 200, body = ClientConfig), ProblemResponses)
+)]
 pub async fn handle_config_get(
     State(app_state): State<AppState>,
     Extension(client_ctx): Extension<ClientCtx>,
-) -> impl IntoResponse {
-    match ConfigModelController::get_config_by_identifier(
-        &client_ctx.identifier,
-        &app_state.db_pool,
-    )
-    .await
-    {
-        Ok(client_config) => {
-            tracing::info!("Client Config Requested: {:#?}", client_config);
-            Json(client_config).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to get config: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
-    }
+) -> Result<Response> {
+    let client_config =
+        ConfigModelController::get_config_by_identifier(&client_ctx.identifier, &app_state.db_pool)
+            .await?;
+
+    tracing::info!("Client Config Requested: {:#?}", client_config);
+    Ok(Json(client_config).into_response())
 }
 
+/// Lists the configs of every client on the caller's server.
+#[utoipa::path(
+    get,
+    path = "/v1/configs",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    responses((status = 200, body = Vec<ClientConfig>), ProblemResponses)
+)]
 pub async fn handle_config_list(
     State(app_state): State<AppState>,
     Extension(client_ctx): Extension<ClientCtx>,
-) -> impl IntoResponse {
-    match ConfigModelController::get_config_by_server_id(
+) -> Result<Response> {
+    let client_configs = ConfigModelController::get_config_by_server_id(
         client_ctx.identifier.server_id(),
         &app_state.db_pool,
     )
-    .await
-    {
-        Ok(client_configs) => {
-            tracing::info!("Client Configs Requested: {:#?}", client_configs);
-            Json(client_configs).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to list configs: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
-    }
+    .await?;
+
+    tracing::info!("Client Configs Requested: {:#?}", client_configs);
+    Ok(Json(client_configs).into_response())
 }
 
 pub async fn handle_config_post(
     State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
     Extension(client_ctx): Extension<ClientCtx>,
     Json(body): Json<ConfigRequestBody>,
-) -> impl IntoResponse {
-    if let Err(e) = is_config_allowed(client_ctx.identifier(), &body, &app_state.db_pool).await {
-        return (
-            StatusCode::FORBIDDEN,
-            format!("Not allowed to add config: {}", e),
-        )
-            .into_response();
-    }
+) -> Result<Response> {
+    let (client_config, _) = save_config(&app_state, &client_ctx, address, body).await?;
+
+    Ok((StatusCode::CREATED, Json(client_config)).into_response())
+}
 
-    match ConfigModelController::add_or_update_config(
+/// Replaces the caller's config. Answers `201 Created` if the client had none yet.
+#[utoipa::path(
+    put,
+    path = "/v1/config",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    request_body = ConfigRequestBody,
+    responses(
+        (status = 200, body = ClientConfig, description = "Config replaced"),
+        (status = 201, body = ClientConfig, description = "Config created"),
+        ProblemResponses
+    )
+)]
+pub async fn handle_config_put(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Json(body): Json<ConfigRequestBody>,
+) -> Result<Response> {
+    let (client_config, created) = save_config(&app_state, &client_ctx, address, body).await?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((status, Json(client_config)).into_response())
+}
+
+/// Validates and stores the caller's config, returning it and whether it was newly created.
+async fn save_config(
+    app_state: &AppState,
+    client_ctx: &ClientCtx,
+    address: SocketAddr,
+    body: ConfigRequestBody,
+) -> Result<(ClientConfig, bool)> {
+    is_config_allowed(client_ctx.identifier(), &body, &app_state.db_pool).await?;
+
+    let before = match ConfigModelController::get_config_by_identifier(
         client_ctx.identifier(),
-        &body.subscriptions,
         &app_state.db_pool,
     )
     .await
     {
-        Ok(client_config) => {
-            tracing::info!("Client Config Added or Updated: {:#?}", client_config);
-            (StatusCode::CREATED, Json(client_config)).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to add or update config: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
-    }
+        Ok(config) => Some(config),
+        Err(Error::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let client_config = ConfigModelController::add_or_update_config(
+        client_ctx.identifier(),
+        &body.subscriptions,
+        &body.templates,
+        &app_state.db_pool,
+    )
+    .await?;
+
+    tracing::info!("Client Config Added or Updated: {:#?}", client_config);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            client_ctx.actor(),
+            "config.update",
+            client_ctx.identifier().to_string(),
+            address.ip(),
+        )
+        .server_id(client_ctx.identifier().server_id())
+        .before(&before)
+        .after(&client_config),
+        &app_state.db_pool,
+    )
+    .await;
+
+    let created = before.is_none();
+    Ok((client_config, created))
 }
 
 pub async fn is_config_allowed(
     identifier: &Identifier,
     body: &ConfigRequestBody,
     db_pool: &SqlitePool,
-) -> anyhow::Result<()> {
+) -> Result<()> {
     let user = UserModelController::get_user_by_id(identifier.server_id(), db_pool).await?;
 
-    for sub in &body.subscriptions {
-        if !user.server_list.contains(sub) {
-            let error_msg = format!(
-                "Client {} is not allowed to subscribe to {}",
-                identifier, sub
-            );
-
-            tracing::error!(error_msg);
-            return Err(anyhow::anyhow!(error_msg));
-        }
-    }
-
-    Ok(())
+    check_subscriptions(
+        identifier,
+        &user.server_list,
+        &body.subscriptions,
+        &body.templates,
+    )
+    .inspect_err(|e| tracing::error!("{e}"))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ConfigRequestBody {
     pub subscriptions: Vec<String>,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub templates: SubscriptionTemplates,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
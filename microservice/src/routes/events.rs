@@ -0,0 +1,648 @@
+use std::convert::Infallible;
+
+use axum::extract::{Extension, Json, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::routing::{get, post};
+use axum::{middleware, Router};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ctx::ctx_client::ClientCtx;
+use crate::error::{Error, Result};
+use crate::message::{Event, Frame};
+use crate::middleware::mw_auth_client::mw_client_auth;
+use crate::model::config::{ClientConfig, ConfigModelController};
+use crate::model::history::{HistoryEntry, HistoryModelController};
+use crate::model::token::TokenScope;
+use crate::routes::openapi::ProblemResponses;
+use crate::websocket::dispatch::dispatch_event;
+use crate::websocket::template::render_event;
+use crate::AppState;
+
+/// Number of history entries read at once while a stream catches up.
+const REPLAY_PAGE_SIZE: u32 = 500;
+
+/// Events queued for a stream before it stops reading the feed until the client caught up.
+const STREAM_BUFFER: usize = 64;
+
+/// Routes for clients that publish or follow events over HTTP instead of holding a websocket
+/// open.
+pub fn event_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/", post(handle_event_post))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::WsConnect),
+            mw_client_auth,
+        ))
+        .merge(
+            Router::new()
+                .route("/stream", get(handle_event_stream))
+                .route_layer(middleware::from_fn_with_state(
+                    (app_state.clone(), TokenScope::Spectate),
+                    mw_client_auth,
+                )),
+        )
+        .with_state(app_state)
+}
+
+/// Publishes an event as if the caller had sent it over its websocket.
+///
+/// The event is checked and fanned out before the response is sent, so `202 Accepted` means
+/// it was queued for every subscriber that is connected right now. Presence events are passed
+/// on, but leave `/presence` alone, which only follows websocket connections.
+#[utoipa::path(
+    post,
+    path = "/v1/events",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    request_body = EventFrameBody,
+    responses((status = 202, body = EventAccepted), ProblemResponses)
+)]
+pub async fn handle_event_post(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Json(EventFrameBody::Event { event }): Json<EventFrameBody>,
+) -> Result<(StatusCode, Json<EventAccepted>)> {
+    let subscriptions = ConfigModelController::get_config_by_identifier(
+        client_ctx.identifier(),
+        &app_state.db_pool,
+    )
+    .await?
+    .subscriptions;
+
+    let kind = event.kind();
+    let delivered =
+        dispatch_event(&app_state, client_ctx.identifier(), &subscriptions, event).await?;
+
+    tracing::info!(
+        "{} posted a {kind} event, delivered to {delivered} connections",
+        client_ctx.identifier
+    );
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(EventAccepted { kind, delivered }),
+    ))
+}
+
+/// Streams the events the caller's config subscribes to as server-sent events.
+///
+/// Every event carries its history sequence number as `id` and a [Frame::Event] as `data`, the
+/// same frame a websocket would receive. Send the last `id` as `Last-Event-ID` to resume after
+/// it, as long as the history still has it.
+///
+/// Like a spectator connection, it needs the `spectate` scope, which `ws_connect` includes.
+#[utoipa::path(
+    get,
+    path = "/v1/events/stream",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    params(
+        StreamParams,
+        ("Last-Event-ID" = Option<i64>, Header, description = "Resume after this event")
+    ),
+    responses(
+        (status = 200, content_type = "text/event-stream", description = "The event stream"),
+        ProblemResponses
+    )
+)]
+pub async fn handle_event_stream(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Query(params): Query<StreamParams>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = std::result::Result<sse::Event, Infallible>>>> {
+    let config =
+        ConfigModelController::get_config_by_identifier(&client_ctx.identifier, &app_state.db_pool)
+            .await?;
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .ok_or_else(|| Error::BadRequest("Invalid Last-Event-ID header".to_string()))
+        })
+        .transpose()?;
+
+    // Subscribe before looking at the history, so that nothing stored in between is missed.
+    let feed = app_state.event_feed.subscribe();
+    let resume_after = match last_event_id {
+        Some(last_event_id) => last_event_id,
+        None => HistoryModelController::latest_seq(&app_state.db_pool).await?,
+    };
+
+    tracing::info!(
+        "{} opened an event stream with subscriptions: {}",
+        client_ctx.identifier,
+        config.subscriptions.join(", ")
+    );
+
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let stream = EventStream {
+        config,
+        render: params.render,
+        sender,
+    };
+    tokio::spawn(stream.run(app_state.db_pool, feed, resume_after));
+
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|event| (Ok(event), receiver))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StreamParams {
+    /// Set `?render=true` to receive events rendered with the templates of the client's config.
+    #[serde(default)]
+    pub render: bool,
+}
+
+/// [EventStream] forwards the events of the history and the feed that a config subscribes to.
+struct EventStream {
+    config: ClientConfig,
+    render: bool,
+    sender: mpsc::Sender<sse::Event>,
+}
+
+impl EventStream {
+    /// Replays the history after `resume_after`, then follows the feed until the client is gone.
+    async fn run(
+        self,
+        db_pool: SqlitePool,
+        mut feed: tokio::sync::broadcast::Receiver<HistoryEntry>,
+        resume_after: i64,
+    ) {
+        let identifier = &self.config.identifier;
+
+        // Entries up to `replayed` were read from the history and are skipped on the feed.
+        let mut replayed = match self.replay(&db_pool, resume_after).await {
+            Some(replayed) => replayed,
+            None => return,
+        };
+        let mut last_seq = replayed;
+
+        loop {
+            let entry = select! {
+                _ = self.sender.closed() => break,
+                entry = feed.recv() => entry,
+            };
+
+            match entry {
+                Ok(entry) if entry.seq <= replayed => {}
+                Ok(entry) => {
+                    last_seq = last_seq.max(entry.seq);
+                    if !self.send(&entry).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(
+                        "Event stream of {identifier} fell {skipped} events behind, catching up from the history"
+                    );
+                    replayed = match self.replay(&db_pool, last_seq).await {
+                        Some(replayed) => replayed,
+                        None => break,
+                    };
+                    last_seq = last_seq.max(replayed);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+
+        tracing::info!("Event stream of {identifier} closed");
+    }
+
+    /// Sends every stored entry after `after`. Returns the last sequence number read, or `None`
+    /// if the stream should end.
+    async fn replay(&self, db_pool: &SqlitePool, mut after: i64) -> Option<i64> {
+        loop {
+            let entries =
+                match HistoryModelController::list_after(after, REPLAY_PAGE_SIZE, db_pool).await {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        tracing::error!(
+                            "Failed to replay the history for {}: {e}",
+                            self.config.identifier
+                        );
+                        return None;
+                    }
+                };
+
+            let page_len = entries.len();
+            for entry in entries {
+                after = entry.seq;
+                if !self.send(&entry).await {
+                    return None;
+                }
+            }
+
+            if page_len < REPLAY_PAGE_SIZE as usize {
+                return Some(after);
+            }
+        }
+    }
+
+    /// Sends `entry` if the config subscribes to its source, the same way the websocket does.
+    /// Returns false if the client is gone.
+    async fn send(&self, entry: &HistoryEntry) -> bool {
+        let source = &entry.source;
+        let subscribed = self
+            .config
+            .subscriptions
+            .iter()
+            .any(|sub| source.matches_subscription(sub));
+
+        if *source == self.config.identifier || !subscribed {
+            return true;
+        }
+
+        let text = if self.render {
+            render_event(
+                &self.config.templates,
+                &self.config.subscriptions,
+                source,
+                &entry.event,
+            )
+        } else {
+            None
+        };
+
+        let frame = Frame::Event {
+            source: Some(source.clone()),
+            event: entry.event.clone(),
+            text,
+        };
+
+        match sse::Event::default()
+            .id(entry.seq.to_string())
+            .json_data(&frame)
+        {
+            Ok(event) => self.sender.send(event).await.is_ok(),
+            Err(e) => {
+                tracing::error!("Failed to serialize event {}: {e}", entry.seq);
+                true
+            }
+        }
+    }
+}
+
+/// The [Frame::Event] envelope the websocket uses, like
+/// `{"type": "event", "event": {"kind": "chat", "player": "Steve", "message": "hi"}}`.
+///
+/// A `source` is ignored, it is always the caller.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventFrameBody {
+    Event { event: Event },
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EventAccepted {
+    pub kind: &'static str,
+    /// Number of connections the event was queued for.
+    pub delivered: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use axum::body::{to_bytes, Body, BodyDataStream};
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::Request;
+    use axum::response::IntoResponse;
+    use futures_util::StreamExt;
+    use serde_json::{json, Value};
+    use tokio::sync::broadcast;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::database_utils::test_pool;
+    use crate::model::config::SubscriptionTemplates;
+    use crate::model::user::{AdminPostBody, UserModelController};
+    use crate::{queued_frame, ActiveConnection};
+
+    /// Adds the users `kiwitech` and `other`, which are linked to each other, and a config for
+    /// `kiwitech:smp` that subscribes to `other`.
+    async fn app_state() -> AppState {
+        let app_state = AppState::for_tests().await;
+
+        for server_id in ["kiwitech", "other"] {
+            UserModelController::add_user(
+                AdminPostBody {
+                    server_id: server_id.to_string(),
+                    server_list: vec!["kiwitech".to_string(), "other".to_string()],
+                    auth_token: "secret".to_string(),
+                    allowed_cidrs: None,
+                },
+                &app_state.db_pool,
+            )
+            .await
+            .unwrap();
+        }
+        ConfigModelController::add_or_update_config(
+            &"kiwitech:smp".parse().unwrap(),
+            &vec!["other".to_string()],
+            &SubscriptionTemplates::new(),
+            &app_state.db_pool,
+        )
+        .await
+        .unwrap();
+
+        app_state
+    }
+
+    fn app(app_state: &AppState) -> Router {
+        event_routes(app_state.clone())
+            .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+    }
+
+    async fn post_event(app: Router, event: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("X-Client-ID", "kiwitech:smp")
+            .header("Authorization", "Bearer secret")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({ "type": "event", "event": event }).to_string(),
+            ))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn post_publishes_to_subscribers() {
+        let app_state = app_state().await;
+        let (conn, mut receiver) = ActiveConnection::for_tests("other:smp", &["kiwitech"]);
+        app_state.active_connections.lock().await.push(conn);
+
+        let (status, accepted) = post_event(
+            app(&app_state),
+            json!({ "kind": "chat", "player": "Steve", "message": "hi" }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(accepted, json!({ "kind": "chat", "delivered": 1 }));
+        assert!(matches!(
+            queued_frame(&mut receiver),
+            Some(Frame::Event { source: Some(source), event: Event::Chat { .. }, .. })
+                if source.to_string() == "kiwitech:smp"
+        ));
+
+        let history = HistoryModelController::list_after(0, 10, &app_state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn post_rejects_system_events() {
+        let app_state = app_state().await;
+        let (conn, mut receiver) = ActiveConnection::for_tests("other:smp", &["kiwitech"]);
+        app_state.active_connections.lock().await.push(conn);
+
+        for event in [
+            json!({ "kind": "online" }),
+            json!({ "kind": "offline", "reason": "fake" }),
+            json!({ "kind": "error", "message": "fake" }),
+        ] {
+            let (status, problem) = post_event(app(&app_state), event).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(problem["detail"]
+                .as_str()
+                .unwrap()
+                .starts_with("Clients cannot publish"));
+        }
+
+        assert!(queued_frame(&mut receiver).is_none());
+        assert_eq!(
+            HistoryModelController::latest_seq(&app_state.db_pool)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn post_needs_a_config() {
+        let app_state = app_state().await;
+        let request = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("X-Client-ID", "other:smp")
+            .header("Authorization", "Bearer secret")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({ "type": "event", "event": { "kind": "join", "player": "Steve" } })
+                    .to_string(),
+            ))
+            .unwrap();
+
+        let response = app(&app_state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn chat(message: &str) -> Event {
+        Event::Chat {
+            player: "Steve".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    async fn record(source: &str, message: &str, db_pool: &SqlitePool) -> HistoryEntry {
+        HistoryModelController::record(&source.parse().unwrap(), &chat(message), db_pool)
+            .await
+            .unwrap()
+    }
+
+    /// Starts an [EventStream] for `kiwitech:smp`, which subscribes to `other`, and returns the
+    /// body a client of it would read.
+    fn follow(
+        db_pool: &SqlitePool,
+        feed: broadcast::Receiver<HistoryEntry>,
+        resume_after: i64,
+    ) -> BodyDataStream {
+        let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER);
+        let stream = EventStream {
+            config: ClientConfig {
+                identifier: "kiwitech:smp".parse().unwrap(),
+                subscriptions: vec!["other".to_string()],
+                templates: SubscriptionTemplates::new(),
+            },
+            render: false,
+            sender,
+        };
+        tokio::spawn(stream.run(db_pool.clone(), feed, resume_after));
+
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            receiver
+                .recv()
+                .await
+                .map(|event| (Ok::<_, Infallible>(event), receiver))
+        });
+        Sse::new(events)
+            .into_response()
+            .into_body()
+            .into_data_stream()
+    }
+
+    /// Reads the next event of `body` as its `id` and the message of its chat event, or `None`
+    /// if nothing arrives in time.
+    async fn next_event(body: &mut BodyDataStream) -> Option<(i64, String)> {
+        let chunk = tokio::time::timeout(Duration::from_millis(200), body.next())
+            .await
+            .ok()??
+            .unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+
+        let field = |name: &str| {
+            chunk
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap()
+                .to_string()
+        };
+        let frame: Value = serde_json::from_str(&field("data: ")).unwrap();
+
+        assert_eq!(frame["type"], "event");
+        Some((
+            field("id: ").parse().unwrap(),
+            frame["event"]["message"].as_str().unwrap().to_string(),
+        ))
+    }
+
+    async fn next_events(body: &mut BodyDataStream, count: usize) -> Vec<(i64, String)> {
+        let mut events = Vec::new();
+        for _ in 0..count {
+            events.push(next_event(body).await.expect("Expected another event"));
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn stream_resumes_after_the_last_event() {
+        let db_pool = test_pool().await;
+        let (feed, _) = broadcast::channel(16);
+
+        for message in ["one", "two", "three"] {
+            record("other:smp", message, &db_pool).await;
+        }
+
+        let mut body = follow(&db_pool, feed.subscribe(), 1);
+        assert_eq!(
+            next_events(&mut body, 2).await,
+            [(2, "two".to_string()), (3, "three".to_string())]
+        );
+
+        // Entries that were replayed already are not sent twice when they show up on the feed.
+        let replayed = HistoryModelController::list_after(2, 1, &db_pool)
+            .await
+            .unwrap();
+        feed.send(replayed[0].clone()).unwrap();
+        feed.send(record("other:smp", "four", &db_pool).await)
+            .unwrap();
+
+        assert_eq!(next_event(&mut body).await, Some((4, "four".to_string())));
+        assert_eq!(next_event(&mut body).await, None);
+    }
+
+    #[tokio::test]
+    async fn stream_only_sends_subscribed_events() {
+        let db_pool = test_pool().await;
+        let (feed, _) = broadcast::channel(16);
+
+        let mut body = follow(&db_pool, feed.subscribe(), 0);
+        for source in ["third:smp", "kiwitech:smp", "other:smp"] {
+            feed.send(record(source, source, &db_pool).await).unwrap();
+        }
+
+        assert_eq!(
+            next_event(&mut body).await,
+            Some((3, "other:smp".to_string()))
+        );
+        assert_eq!(next_event(&mut body).await, None);
+    }
+
+    #[tokio::test]
+    async fn stream_catches_up_from_the_history_when_it_lags() {
+        let db_pool = test_pool().await;
+        let (feed, _) = broadcast::channel(2);
+
+        let mut body = follow(&db_pool, feed.subscribe(), 0);
+        let mut entries = Vec::new();
+        for message in ["1", "2", "3", "4", "5"] {
+            entries.push(record("other:smp", message, &db_pool).await);
+        }
+        // Nothing is awaited in between, so the stream cannot keep up and the feed overflows.
+        for entry in entries {
+            feed.send(entry).unwrap();
+        }
+
+        let events = next_events(&mut body, 5).await;
+        assert_eq!(
+            events.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            [1, 2, 3, 4, 5]
+        );
+
+        feed.send(record("other:smp", "6", &db_pool).await).unwrap();
+        assert_eq!(next_event(&mut body).await, Some((6, "6".to_string())));
+        assert_eq!(next_event(&mut body).await, None);
+    }
+
+    #[tokio::test]
+    async fn stream_rejects_invalid_last_event_ids() {
+        let app_state = app_state().await;
+        let request = Request::builder()
+            .uri("/stream")
+            .header("X-Client-ID", "kiwitech:smp")
+            .header("Authorization", "Bearer secret")
+            .header("Last-Event-ID", "yesterday")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app(&app_state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn stream_resumes_from_the_last_event_id_header() {
+        let app_state = app_state().await;
+        for message in ["one", "two"] {
+            record("other:smp", message, &app_state.db_pool).await;
+        }
+
+        let request = Request::builder()
+            .uri("/stream")
+            .header("X-Client-ID", "kiwitech:smp")
+            .header("Authorization", "Bearer secret")
+            .header("Last-Event-ID", "1")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app(&app_state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut body = response.into_body().into_data_stream();
+        assert_eq!(next_event(&mut body).await, Some((2, "two".to_string())));
+        assert_eq!(next_event(&mut body).await, None);
+    }
+}
@@ -0,0 +1,272 @@
+use axum::body::Body;
+use axum::extract::{Extension, Json, Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{middleware, Router};
+use chrono::SecondsFormat;
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ctx::ctx_client::ClientCtx;
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth_client::mw_client_auth;
+use crate::model::history::{HistoryEntry, HistoryModelController, HistoryPage, HistoryQuery};
+use crate::model::token::TokenScope;
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Number of history entries read at once while an export is written.
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// Routes to look up what was said on the caller's server.
+pub fn history_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/search", get(handle_history_search))
+        .route("/export", get(handle_history_export))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::ConfigRead),
+            mw_client_auth,
+        ))
+        .with_state(app_state)
+}
+
+/// Searches the events published on the caller's server, newest first.
+#[utoipa::path(
+    get,
+    path = "/v1/history/search",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    params(HistoryQuery),
+    responses((status = 200, body = HistoryPage), ProblemResponses)
+)]
+pub async fn handle_history_search(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response> {
+    let page = HistoryModelController::search(
+        client_ctx.identifier.server_id(),
+        &query,
+        &app_state.db_pool,
+    )
+    .await?;
+
+    Ok(Json(page).into_response())
+}
+
+/// Exports the events published on the caller's server between `since` and `until`, oldest
+/// first. Takes the same filters as the search, `cursor` and `limit` are ignored.
+#[utoipa::path(
+    get,
+    path = "/v1/history/export",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    params(HistoryQuery, ExportParams),
+    responses(
+        (status = 200, content_type = "text/csv", description = "`seq,created_at,source,kind,player,message`"),
+        (status = 200, content_type = "application/x-ndjson", description = "One `HistoryEntry` per line"),
+        ProblemResponses
+    )
+)]
+pub async fn handle_history_export(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Query(query): Query<HistoryQuery>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response> {
+    if query.since.is_none() || query.until.is_none() {
+        return Err(Error::BadRequest(
+            "Exports need a time range, set since and until".to_string(),
+        ));
+    }
+
+    // Fail on a bad query before the response starts, not halfway through the body.
+    HistoryModelController::export_page(
+        client_ctx.identifier.server_id(),
+        &query,
+        0,
+        1,
+        &app_state.db_pool,
+    )
+    .await?;
+
+    tracing::info!(
+        "{} exported the history from {:?} until {:?} as {:?}",
+        client_ctx.identifier,
+        query.since,
+        query.until,
+        params.format
+    );
+
+    let export = Export {
+        db_pool: app_state.db_pool,
+        server_id: client_ctx.identifier.server_id,
+        query,
+        format: params.format,
+        after: Some(0),
+    };
+    let header = match export.format {
+        ExportFormat::Csv => Some("seq,created_at,source,kind,player,message\n".to_string()),
+        ExportFormat::Jsonl => None,
+    };
+
+    let body = stream::iter(header.map(Ok)).chain(stream::unfold(export, |mut export| async {
+        export.next_chunk().await.map(|chunk| (chunk, export))
+    }));
+
+    let (content_type, extension) = match params.format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        ExportFormat::Jsonl => ("application/x-ndjson", "jsonl"),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"history.{extension}\""),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ExportParams {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One row per event. Fields that start like a formula are prefixed with `'`.
+    Csv,
+    #[default]
+    Jsonl,
+}
+
+/// [Export] reads the history page by page while the response body is written.
+struct Export {
+    db_pool: SqlitePool,
+    server_id: String,
+    query: HistoryQuery,
+    format: ExportFormat,
+    /// The last sequence number written, `None` once everything was.
+    after: Option<i64>,
+}
+
+impl Export {
+    async fn next_chunk(&mut self) -> Option<Result<String>> {
+        let after = self.after?;
+
+        let entries = match HistoryModelController::export_page(
+            &self.server_id,
+            &self.query,
+            after,
+            EXPORT_PAGE_SIZE,
+            &self.db_pool,
+        )
+        .await
+        {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::error!("Failed to export the history of {}: {e}", self.server_id);
+                self.after = None;
+                return Some(Err(e));
+            }
+        };
+
+        self.after = match entries.last() {
+            Some(last) if entries.len() == EXPORT_PAGE_SIZE as usize => Some(last.seq),
+            _ => None,
+        };
+
+        if entries.is_empty() {
+            return None;
+        }
+
+        let mut chunk = String::new();
+        for entry in &entries {
+            match self.format {
+                ExportFormat::Csv => chunk.push_str(&csv_row(entry)),
+                ExportFormat::Jsonl => match serde_json::to_string(entry) {
+                    Ok(line) => chunk.push_str(&line),
+                    Err(e) => return Some(Err(e.into())),
+                },
+            }
+            chunk.push('\n');
+        }
+
+        Some(Ok(chunk))
+    }
+}
+
+fn csv_row(entry: &HistoryEntry) -> String {
+    [
+        entry.seq.to_string(),
+        entry
+            .created_at
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        entry.source.to_string(),
+        entry.event.kind().to_string(),
+        entry.event.player().unwrap_or_default().to_string(),
+        entry.event.message().unwrap_or_default().to_string(),
+    ]
+    .iter()
+    .map(|field| csv_field(field))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Quotes `field` if it contains a separator, a quote or a line break.
+///
+/// Fields that a spreadsheet would run as a formula get a leading `'`, since chat messages are
+/// written by anyone.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_quotes_when_needed() {
+        assert_eq!(csv_field("hello"), "hello");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_field_defuses_formulas() {
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("\tx"), "'\tx");
+        assert_eq!(csv_field("\rx"), "\"'\rx\"");
+        assert_eq!(
+            csv_field("=HYPERLINK(\"http://x\",\"y\")"),
+            "\"'=HYPERLINK(\"\"http://x\"\",\"\"y\"\")\""
+        );
+        assert_eq!(csv_field("a=b"), "a=b");
+    }
+}
@@ -1,4 +1,17 @@
 pub mod admin;
+pub mod admin_accounts;
+pub mod admin_configs;
+pub mod admin_connections;
+pub mod admin_transfer;
+pub mod admin_users;
+pub mod admin_webhooks;
+pub mod auth;
 pub mod config;
+pub mod events;
+pub mod history;
 pub mod not_found;
+pub mod openapi;
+pub mod presence;
+pub mod rpc;
+pub mod webhooks;
 pub mod websocket;
@@ -1,5 +1,5 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use crate::error::Error;
 
-pub async fn handle_404() -> impl IntoResponse {
-    (StatusCode::NOT_FOUND, "Nothing to see here.")
+pub async fn handle_404() -> Error {
+    Error::NotFound("Nothing to see here.".to_string())
 }
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use axum::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, ResponseBuilder};
+use utoipa::{IntoResponses, Modify, OpenApi};
+
+use crate::error::PROBLEM_JSON;
+use crate::routes::{
+    admin, admin_accounts, admin_configs, admin_connections, admin_transfer, admin_users,
+    admin_webhooks, auth, config, events, history, presence, rpc, webhooks,
+};
+
+/// [ProblemResponses] documents the `application/problem+json` answers every endpoint can give.
+/// It is never constructed, it only exists to be listed in `responses(...)` of a path.
+pub enum ProblemResponses {}
+
+impl IntoResponses for ProblemResponses {
+    fn responses() -> BTreeMap<String, RefOr<utoipa::openapi::Response>> {
+        [
+            (400, "The request is malformed or not allowed"),
+            (401, "Missing or invalid credentials"),
+            (403, "The credentials do not allow this"),
+            (404, "The resource does not exist"),
+            (409, "The request conflicts with the current state"),
+            (429, "Too many requests"),
+            (500, "Something went wrong on our side"),
+        ]
+        .into_iter()
+        .map(|(status, description)| {
+            let response = ResponseBuilder::new()
+                .description(description)
+                .content(
+                    PROBLEM_JSON,
+                    ContentBuilder::new()
+                        .schema(Ref::from_schema_name("Problem"))
+                        .build(),
+                )
+                .build();
+
+            (status.to_string(), response.into())
+        })
+        .collect()
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Chatbridge", description = "Versioned REST API of the chatbridge microservice."),
+    paths(
+        auth::handle_ticket_post,
+        config::handle_config_get,
+        config::handle_config_list,
+        config::handle_config_put,
+        webhooks::handle_webhook_get,
+        webhooks::handle_webhook_put,
+        webhooks::handle_webhook_delete,
+        presence::handle_presence_list,
+        presence::handle_presence_get,
+        rpc::handle_rpc_post,
+        events::handle_event_post,
+        events::handle_event_stream,
+        history::handle_history_search,
+        history::handle_history_export,
+        admin_users::handle_admin_user_list,
+        admin_users::handle_admin_user_add,
+        admin_users::handle_admin_user_get,
+        admin_users::handle_admin_user_update,
+        admin_users::handle_admin_user_delete,
+        admin::handle_admin_status,
+        admin::handle_admin_audit,
+        admin::handle_admin_token_list,
+        admin::handle_admin_token_add,
+        admin::handle_admin_token_update,
+        admin::handle_admin_token_revoke,
+        admin_accounts::handle_admin_account_list,
+        admin_accounts::handle_admin_account_add,
+        admin_accounts::handle_admin_account_delete,
+        admin_accounts::handle_admin_account_token_list,
+        admin_accounts::handle_admin_account_token_add,
+        admin_accounts::handle_admin_account_token_revoke,
+        admin_configs::handle_admin_config_list,
+        admin_configs::handle_admin_config_get,
+        admin_configs::handle_admin_config_put,
+        admin_configs::handle_admin_config_delete,
+        admin_connections::handle_admin_connection_list,
+        admin_connections::handle_admin_connection_kick,
+        admin_connections::handle_admin_broadcast,
+        admin_transfer::handle_admin_export,
+        admin_transfer::handle_admin_import,
+        admin_webhooks::handle_admin_webhook_list,
+        admin_webhooks::handle_admin_dead_letter_list,
+        admin_webhooks::handle_admin_dead_letter_replay,
+        admin_webhooks::handle_admin_dead_letter_delete,
+    ),
+    components(schemas(
+        crate::error::Problem,
+        crate::ctx::ctx_client::Identifier,
+        crate::ctx::ctx_admin::AdminRole,
+        crate::model::config::ClientConfig,
+        crate::model::status::ClientStatus,
+        crate::model::token::TokenScope,
+        crate::model::token::TokenCreateBody,
+        crate::model::token::TokenUpdateBody,
+        crate::model::token::CreatedToken,
+        crate::model::token::ApiToken,
+        crate::model::admin::AdminAccountPostBody,
+        crate::model::admin::AdminTokenPostBody,
+        crate::model::admin::Admin,
+        crate::model::admin::AdminToken,
+        crate::model::admin::CreatedAdminToken,
+        crate::model::audit::AuditEntry,
+        crate::model::audit::AuditPage,
+        crate::model::user::AdminPostBody,
+        crate::model::user::UserPatchBody,
+        crate::model::user::UserNoToken,
+        crate::model::user::UserSummary,
+        crate::model::user::UserPage,
+        crate::model::transfer::ExportDocument,
+        crate::model::transfer::ExportedUser,
+        crate::model::transfer::ExportedWebhook,
+        crate::model::transfer::ExportedToken,
+        crate::model::transfer::ImportMode,
+        crate::model::transfer::ImportReport,
+        crate::model::transfer::ChangeCounts,
+        crate::model::transfer::ImportConflict,
+        crate::model::webhook::WebhookBody,
+        crate::model::webhook::Webhook,
+        crate::model::webhook::DeadLetter,
+        crate::model::webhook::DeadLetterPage,
+        crate::model::history::HistoryEntry,
+        crate::model::history::HistoryPage,
+        crate::websocket::presence::ClientPresence,
+        crate::websocket::ticket::IssuedTicket,
+        crate::ConnectionInfo,
+        admin_connections::KickBody,
+        admin_connections::BroadcastBody,
+        admin_connections::BroadcastResponse,
+        admin_webhooks::ReplayResult,
+        config::ConfigRequestBody,
+        rpc::RpcRequestBody,
+        rpc::RpcResponseBody,
+        events::EventFrameBody,
+        events::EventAccepted,
+        crate::message::Event,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "client", description = "Endpoints for clients, authenticated with an API token"),
+        (name = "admin", description = "Endpoints for admins, authenticated with an admin token"),
+    )
+)]
+struct ApiDoc;
+
+/// Adds the authentication schemes referenced by `security(...)` of the paths.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "client_id",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Client-ID"))),
+        );
+    }
+}
+
+/// Serves the OpenAPI 3 document of the `/v1` API.
+pub async fn handle_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(reference)) = map.get("$ref") {
+                    refs.push(reference);
+                }
+                map.values().for_each(|value| collect_refs(value, refs));
+            }
+            Value::Array(values) => values.iter().for_each(|value| collect_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn documents_the_v1_paths() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = document["paths"].as_object().unwrap();
+
+        for path in [
+            "/v1/config",
+            "/v1/rpc",
+            "/v1/events",
+            "/v1/admin/users",
+            "/v1/admin/configs/{identifier}",
+        ] {
+            assert!(paths.contains_key(path), "{path} is not documented");
+        }
+        assert!(paths.keys().all(|path| path.starts_with("/v1/")));
+    }
+
+    #[test]
+    fn every_referenced_schema_is_registered() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+
+        let mut refs = Vec::new();
+        collect_refs(&document["paths"], &mut refs);
+        collect_refs(&document["components"], &mut refs);
+        assert!(!refs.is_empty());
+
+        for reference in refs {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            assert!(schemas.contains_key(name), "{name} is not registered");
+        }
+    }
+
+    #[test]
+    fn problems_are_documented_for_every_operation() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                let problem = &operation["responses"]["404"]["content"][PROBLEM_JSON];
+                assert!(
+                    problem.is_object(),
+                    "{method} {path} does not document problems"
+                );
+            }
+        }
+
+        let security_schemes = &document["components"]["securitySchemes"];
+        assert!(security_schemes["bearer"].is_object());
+        assert!(security_schemes["client_id"].is_object());
+    }
+}
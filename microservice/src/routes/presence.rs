@@ -0,0 +1,65 @@
+use axum::extract::{Extension, Json, Path, State};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{middleware, Router};
+
+use crate::ctx::ctx_client::{ClientCtx, Identifier};
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth_client::mw_client_auth;
+use crate::model::token::TokenScope;
+use crate::routes::openapi::ProblemResponses;
+use crate::websocket::presence::ClientPresence;
+use crate::AppState;
+
+pub fn presence_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/", get(handle_presence_list))
+        .route("/:client", get(handle_presence_get))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::ConfigRead),
+            mw_client_auth,
+        ))
+        .with_state(app_state)
+}
+
+/// Lists the online players of every connected client of the caller's server.
+#[utoipa::path(
+    get,
+    path = "/v1/presence",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    responses((status = 200, body = Vec<ClientPresence>), ProblemResponses)
+)]
+pub async fn handle_presence_list(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+) -> impl IntoResponse {
+    Json(
+        app_state
+            .presence
+            .list_by_server_id(client_ctx.identifier.server_id()),
+    )
+}
+
+/// Returns the online players of a single client of the caller's server.
+#[utoipa::path(
+    get,
+    path = "/v1/presence/{client}",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    params(("client" = String, Path, description = "Client ID on the caller's server")),
+    responses((status = 200, body = ClientPresence), ProblemResponses)
+)]
+pub async fn handle_presence_get(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Path(client): Path<String>,
+) -> Result<Json<ClientPresence>> {
+    let identifier = Identifier::new(client_ctx.identifier.server_id(), client);
+
+    app_state
+        .presence
+        .get(&identifier)
+        .map(Json)
+        .ok_or_else(|| Error::NotFound(format!("{identifier} is not connected")))
+}
@@ -0,0 +1,93 @@
+use axum::extract::{Extension, Json, State};
+use axum::routing::post;
+use axum::{middleware, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::{serde_as, DisplayFromStr};
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_client::{ClientCtx, Identifier};
+use crate::error::{Error, Result};
+use crate::message::RpcRequest;
+use crate::middleware::mw_auth_client::mw_client_auth;
+use crate::model::config::ConfigModelController;
+use crate::model::token::TokenScope;
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+pub fn rpc_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/", post(handle_rpc_post))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::WsConnect),
+            mw_client_auth,
+        ))
+        .with_state(app_state)
+}
+
+/// Sends a request to a connected client and waits for its answer.
+#[utoipa::path(
+    post,
+    path = "/v1/rpc",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    request_body = RpcRequestBody,
+    responses((status = 200, body = RpcResponseBody), ProblemResponses)
+)]
+pub async fn handle_rpc_post(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Json(body): Json<RpcRequestBody>,
+) -> Result<Json<RpcResponseBody>> {
+    let subscriptions = ConfigModelController::get_config_by_identifier(
+        client_ctx.identifier(),
+        &app_state.db_pool,
+    )
+    .await?
+    .subscriptions;
+
+    let request = RpcRequest {
+        id: String::new(),
+        source: None,
+        target: body.target,
+        method: body.method,
+        params: body.params,
+        timeout_ms: body.timeout_ms,
+    };
+
+    let result = app_state
+        .rpc_broker
+        .request(
+            &app_state.active_connections,
+            client_ctx.identifier(),
+            &subscriptions,
+            request,
+        )
+        .await
+        .map_err(|error| {
+            tracing::warn!("Request from {} failed: {}", client_ctx.identifier, error);
+            Error::Rpc(error)
+        })?;
+
+    Ok(Json(RpcResponseBody { result }))
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RpcRequestBody {
+    /// `server_id:client_id` of the client to ask.
+    #[serde_as(as = "DisplayFromStr")]
+    #[schema(value_type = String)]
+    pub target: Identifier,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RpcResponseBody {
+    /// Whatever the target client answered with.
+    #[schema(value_type = Object)]
+    pub result: Value,
+}
@@ -0,0 +1,145 @@
+use std::net::SocketAddr;
+
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{
+    extract::{ConnectInfo, Extension, Json, State},
+    middleware, Router,
+};
+
+use crate::ctx::ctx_client::ClientCtx;
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth_client::mw_client_auth;
+use crate::model::audit::{AuditModelController, NewAuditEntry};
+use crate::model::token::TokenScope;
+use crate::model::webhook::{Webhook, WebhookBody, WebhookModelController};
+use crate::routes::openapi::ProblemResponses;
+use crate::AppState;
+
+/// Routes for a client to receive its events over HTTP instead of, or next to, a websocket.
+pub fn webhook_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/config/webhook", get(handle_webhook_get))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), TokenScope::ConfigRead),
+            mw_client_auth,
+        ))
+        .merge(
+            Router::new()
+                .route(
+                    "/config/webhook",
+                    put(handle_webhook_put).delete(handle_webhook_delete),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    (app_state.clone(), TokenScope::ConfigWrite),
+                    mw_client_auth,
+                )),
+        )
+        .with_state(app_state)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/config/webhook",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    responses((status = 200, body = Webhook), ProblemResponses)
+)]
+pub async fn handle_webhook_get(
+    State(app_state): State<AppState>,
+    Extension(client_ctx): Extension<ClientCtx>,
+) -> Result<Response> {
+    let webhook =
+        WebhookModelController::get_webhook(client_ctx.identifier(), &app_state.db_pool).await?;
+
+    Ok(Json(webhook).into_response())
+}
+
+/// Points the caller's config at a webhook. Events its config subscribes to are POSTed there,
+/// signed with the secret.
+#[utoipa::path(
+    put,
+    path = "/v1/config/webhook",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    request_body = WebhookBody,
+    responses((status = 200, body = Webhook), ProblemResponses)
+)]
+pub async fn handle_webhook_put(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(client_ctx): Extension<ClientCtx>,
+    Json(body): Json<WebhookBody>,
+) -> Result<Response> {
+    app_state
+        .webhooks
+        .check_target(&body.url)
+        .await
+        .map_err(|e| Error::BadRequest(format!("Invalid webhook URL: {e}")))?;
+
+    let before = match WebhookModelController::get_webhook(
+        client_ctx.identifier(),
+        &app_state.db_pool,
+    )
+    .await
+    {
+        Ok(webhook) => Some(webhook),
+        Err(Error::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let webhook =
+        WebhookModelController::set_webhook(client_ctx.identifier(), &body, &app_state.db_pool)
+            .await?;
+    app_state.webhooks.forget_subscribers().await;
+
+    tracing::info!("Webhook Set: {} -> {}", webhook.identifier, webhook.url);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            client_ctx.actor(),
+            "webhook.update",
+            client_ctx.identifier().to_string(),
+            address.ip(),
+        )
+        .server_id(client_ctx.identifier().server_id())
+        .before(&before)
+        .after(&webhook),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(webhook).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/config/webhook",
+    tag = "client",
+    security(("bearer" = [], "client_id" = [])),
+    responses((status = 200, body = Webhook), ProblemResponses)
+)]
+pub async fn handle_webhook_delete(
+    State(app_state): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(client_ctx): Extension<ClientCtx>,
+) -> Result<Response> {
+    let webhook =
+        WebhookModelController::delete_webhook(client_ctx.identifier(), &app_state.db_pool).await?;
+    app_state.webhooks.forget_subscribers().await;
+
+    tracing::info!("Webhook Deleted: {} -> {}", webhook.identifier, webhook.url);
+    AuditModelController::record(
+        NewAuditEntry::new(
+            client_ctx.actor(),
+            "webhook.delete",
+            client_ctx.identifier().to_string(),
+            address.ip(),
+        )
+        .server_id(client_ctx.identifier().server_id())
+        .before(&webhook),
+        &app_state.db_pool,
+    )
+    .await;
+
+    Ok(Json(webhook).into_response())
+}
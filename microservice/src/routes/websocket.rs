@@ -1,20 +1,23 @@
 use std::net::SocketAddr;
 
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::routing::get;
 use axum::{
     extract::{ConnectInfo, WebSocketUpgrade},
     response::IntoResponse,
     Extension, Router,
 };
+use serde::Deserialize;
 use sqlx::SqlitePool;
 use tracing::info;
 
 use crate::config::Config;
 use crate::ctx::ctx_client::ClientCtx;
 use crate::middleware::mw_auth_websocket::mw_websocket_auth;
+use crate::model::config::ClientConfig;
+use crate::model::token::AuthenticatedToken;
 use crate::websocket::websocket_handler::handle_socket;
-use crate::{ActiveConnection, ActiveConnections, AppState};
+use crate::AppState;
 
 pub fn websocket_routes(app_state: AppState) -> Router {
     Router::new()
@@ -28,28 +31,30 @@ pub fn websocket_routes(app_state: AppState) -> Router {
 
 pub async fn handle_websocket(
     Extension(client_ctx): Extension<ClientCtx>,
-    Extension(subscriptions): Extension<Vec<String>>,
-    state: State<AppState>,
+    Extension(config): Extension<ClientConfig>,
+    Extension(token): Extension<AuthenticatedToken>,
+    Query(params): Query<WebsocketParams>,
+    State(app_state): State<AppState>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> impl IntoResponse {
+    let spectator = token.is_spectator();
+
     info!(
-        "User agent {} connected with subscriptions: {}",
+        "{} {} connected with subscriptions: {}",
+        if spectator { "Spectator" } else { "User agent" },
         client_ctx.identifier.to_string(),
-        subscriptions.join(", ")
-    );
-
-    let conn = ActiveConnection::new(client_ctx.identifier.clone(), subscriptions.clone());
-    let active_conn = state.active_connections.clone();
-
-    active_conn.clone().lock().await.push(conn.clone());
-
-    println!(
-        "Active Connections after pushing: {:#?}",
-        &state.active_connections
+        config.subscriptions.join(", ")
     );
 
     ws.on_upgrade(move |socket| {
-        handle_socket(socket, addr, subscriptions, client_ctx, conn, active_conn)
+        handle_socket(socket, addr, config, params.render, spectator, app_state)
     })
 }
+
+#[derive(Debug, Deserialize)]
+pub struct WebsocketParams {
+    /// Set `?render=true` to receive events rendered with the templates of the client's config.
+    #[serde(default)]
+    pub render: bool,
+}
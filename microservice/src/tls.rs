@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::Config;
+
+/// How often the certificate files are checked for changes.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Loads the TLS settings from `config` for the listener.
+pub fn load_rustls_config(config: &Config) -> anyhow::Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(load_server_config(
+        config,
+    )?)))
+}
+
+/// Reloads the certificates on `SIGHUP` or when one of the files changes.
+///
+/// Only new handshakes use the reloaded certificates, established connections are kept.
+pub fn spawn_reloader(config: Config, rustls_config: RustlsConfig) -> anyhow::Result<()> {
+    let mut hangup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RELOAD_POLL_INTERVAL);
+        let mut last_modified = modified_times(&config);
+
+        loop {
+            let trigger = select! {
+                _ = hangup.recv() => "SIGHUP",
+                _ = interval.tick() => {
+                    if modified_times(&config) == last_modified {
+                        continue;
+                    }
+
+                    "file change"
+                }
+            };
+
+            last_modified = modified_times(&config);
+
+            match load_server_config(&config) {
+                Ok(server_config) => {
+                    rustls_config.reload_from_config(Arc::new(server_config));
+                    tracing::info!("Reloaded TLS certificates after {trigger}");
+                }
+                Err(e) => {
+                    tracing::error!("Failed to reload TLS certificates after {trigger}, keeping the old ones: {e:#}");
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn load_server_config(config: &Config) -> anyhow::Result<ServerConfig> {
+    let (Some(cert_path), Some(key_path)) = (&config.TLS_CERT_PATH, &config.TLS_KEY_PATH) else {
+        return Err(anyhow::anyhow!("TLS is not configured"));
+    };
+
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("Failed to set up TLS protocol versions")?;
+
+    let builder = match &config.TLS_CLIENT_CA_PATH {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+
+            for cert in load_certs(ca_path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {ca_path}"))?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if config.TLS_CLIENT_AUTH_REQUIRED {
+                verifier.build()
+            } else {
+                verifier.allow_unauthenticated().build()
+            }
+            .context("Failed to set up client certificate verification")?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    builder
+        .with_single_cert(load_certs(cert_path)?, load_key(key_path)?)
+        .context("Invalid TLS certificate or key")
+}
+
+fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read certificates from {path}"))?;
+
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!("No certificates found in {path}"));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read private key from {path}"))?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {path}"))
+}
+
+fn modified_times(config: &Config) -> Vec<Option<SystemTime>> {
+    [
+        &config.TLS_CERT_PATH,
+        &config.TLS_KEY_PATH,
+        &config.TLS_CLIENT_CA_PATH,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-signed certificate for `localhost` with its key, which doubles as the client CA.
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/cert.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/key.pem");
+
+    fn tls_config(client_ca: Option<&str>, client_auth_required: bool) -> Config {
+        Config {
+            TLS_CERT_PATH: Some(CERT.to_string()),
+            TLS_KEY_PATH: Some(KEY.to_string()),
+            TLS_CLIENT_CA_PATH: client_ca.map(str::to_string),
+            TLS_CLIENT_AUTH_REQUIRED: client_auth_required,
+            ..Config::for_tests()
+        }
+    }
+
+    #[test]
+    fn loads_the_certificate_and_key() {
+        assert!(load_server_config(&tls_config(None, false)).is_ok());
+    }
+
+    #[test]
+    fn loads_optional_and_required_client_certificates() {
+        assert!(load_server_config(&tls_config(Some(CERT), false)).is_ok());
+        assert!(load_server_config(&tls_config(Some(CERT), true)).is_ok());
+    }
+
+    #[test]
+    fn fails_without_tls_settings() {
+        assert!(load_server_config(&Config::for_tests()).is_err());
+    }
+
+    #[test]
+    fn names_the_file_that_failed() {
+        let config = Config {
+            TLS_KEY_PATH: Some("/nonexistent/key.pem".to_string()),
+            ..tls_config(None, false)
+        };
+        let error = format!("{:#}", load_server_config(&config).unwrap_err());
+        assert!(error.contains("/nonexistent/key.pem"), "{error}");
+
+        // The key file holds no certificate.
+        let config = Config {
+            TLS_CERT_PATH: Some(KEY.to_string()),
+            ..tls_config(None, false)
+        };
+        let error = format!("{:#}", load_server_config(&config).unwrap_err());
+        assert!(error.contains("No certificates found"), "{error}");
+
+        let config = Config {
+            TLS_KEY_PATH: Some(CERT.to_string()),
+            ..tls_config(None, false)
+        };
+        let error = format!("{:#}", load_server_config(&config).unwrap_err());
+        assert!(error.contains("No private key found"), "{error}");
+    }
+
+    #[test]
+    fn watches_every_configured_file() {
+        let modified = modified_times(&tls_config(Some(CERT), false));
+        assert_eq!(modified.len(), 3);
+        assert!(modified.iter().all(Option::is_some));
+
+        let config = Config {
+            TLS_CLIENT_CA_PATH: Some("/nonexistent/ca.pem".to_string()),
+            ..tls_config(None, false)
+        };
+        assert_eq!(modified_times(&config)[2], None);
+    }
+}
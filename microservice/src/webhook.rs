@@ -0,0 +1,514 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use tokio::net::lookup_host;
+use tokio::sync::{Mutex as TokioMutex, Notify, Semaphore};
+use tracing::{debug, error, warn};
+
+use crate::config::Config;
+use crate::ctx::ctx_client::Identifier;
+use crate::message::{Event, Frame};
+use crate::model::webhook::{
+    NewDeadLetter, PendingDelivery, WebhookModelController, WebhookSubscriber,
+};
+use crate::websocket::template::render_event;
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}`, keyed with the secret.
+pub const SIGNATURE_HEADER: &str = "X-Chatbridge-Signature";
+/// Unix time in seconds when the request was signed. Receivers should reject old ones.
+pub const TIMESTAMP_HEADER: &str = "X-Chatbridge-Timestamp";
+/// The `kind` of the event, so receivers can route without parsing the body.
+pub const EVENT_HEADER: &str = "X-Chatbridge-Event";
+
+/// Longest wait between two attempts, no matter how many failed before.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Deliveries that are made at once, over all webhooks.
+const MAX_CONCURRENT_DELIVERIES: usize = 32;
+
+/// How often the queue is checked for deliveries that became due, when nothing else happens.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the webhooks and their subscriptions are reused before they are loaded again.
+const SUBSCRIBER_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// The webhooks with their subscriptions, and when they were loaded.
+type CachedSubscribers = (Instant, Arc<Vec<WebhookSubscriber>>);
+
+/// [WebhookDispatcher] POSTs events to the configs that subscribed with a webhook instead of, or
+/// next to, a websocket.
+///
+/// Events are queued in the database and delivered by a worker, see [WebhookDispatcher::spawn_worker],
+/// so deliveries and their retries survive a restart. Every delivery is signed, see
+/// [SIGNATURE_HEADER]. Failed deliveries are retried with exponential backoff, and stored as dead
+/// letters once `WEBHOOK_MAX_ATTEMPTS` are used up.
+///
+/// Unless `WEBHOOK_ALLOW_PRIVATE_TARGETS` is set, webhooks can only reach public addresses, see
+/// [is_public]. Redirects are never followed, so they cannot lead elsewhere either.
+#[derive(Debug, Clone)]
+pub struct WebhookDispatcher {
+    client: reqwest::Client,
+    max_attempts: u32,
+    retry_delay: Duration,
+    max_pending: u32,
+    allow_private_targets: bool,
+    subscribers: Arc<TokioMutex<Option<CachedSubscribers>>>,
+    /// Wakes the worker when a delivery was queued or finished.
+    wake: Arc<Notify>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let allow_private_targets = config.WEBHOOK_ALLOW_PRIVATE_TARGETS;
+
+        let mut client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.WEBHOOK_TIMEOUT_MS))
+            .user_agent(concat!("chatbridge/", env!("CARGO_PKG_VERSION")))
+            .redirect(Policy::none());
+        if !allow_private_targets {
+            // Checked on every connection, so a name cannot be changed to point inwards later.
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Ok(Self {
+            client: client.build()?,
+            max_attempts: config.WEBHOOK_MAX_ATTEMPTS.max(1),
+            retry_delay: Duration::from_millis(config.WEBHOOK_RETRY_DELAY_MS),
+            max_pending: config.WEBHOOK_MAX_PENDING,
+            allow_private_targets,
+            subscribers: Arc::default(),
+            wake: Arc::default(),
+        })
+    }
+
+    /// Fails if deliveries to `url` would not reach a public address.
+    pub async fn check_target(&self, url: &str) -> Result<(), String> {
+        if self.allow_private_targets {
+            return Ok(());
+        }
+
+        let url = Url::parse(url).map_err(|e| e.to_string())?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addresses: Vec<IpAddr> = match (host_address(&url), url.host_str()) {
+            (Some(ip), _) => vec![ip],
+            (None, Some(domain)) => lookup_host((domain, port))
+                .await
+                .map_err(|e| format!("Failed to resolve {domain}: {e}"))?
+                .map(|address| address.ip())
+                .collect(),
+            (None, None) => return Err("The URL has no host".to_string()),
+        };
+
+        match addresses.iter().find(|ip| !is_public(**ip)) {
+            Some(ip) => Err(format!("{ip} is not a public address")),
+            None if addresses.is_empty() => Err("The host has no addresses".to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues `event` from `source` for every webhook subscribed to it.
+    pub async fn publish(&self, db_pool: &SqlitePool, source: &Identifier, event: &Event) {
+        let subscribers = match self.subscribers(db_pool).await {
+            Ok(subscribers) => subscribers,
+            Err(e) => {
+                error!("Failed to list webhooks for an event of {source}: {e}");
+                return;
+            }
+        };
+
+        let kind = event.kind();
+        let mut queued = false;
+        for subscriber in subscribers
+            .iter()
+            .filter(|sub| sub.identifier != *source && sub.is_subscribed_to(source))
+        {
+            let frame = Frame::Event {
+                source: Some(source.clone()),
+                text: render_event(
+                    &subscriber.templates,
+                    &subscriber.subscriptions,
+                    source,
+                    event,
+                ),
+                event: event.clone(),
+            };
+
+            let payload = match serde_json::to_string(&frame) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!(
+                        "Failed to serialize frame for {}: {e}",
+                        subscriber.identifier
+                    );
+                    continue;
+                }
+            };
+
+            match WebhookModelController::queue_delivery(
+                &subscriber.identifier,
+                kind,
+                &payload,
+                self.max_pending,
+                db_pool,
+            )
+            .await
+            {
+                Ok(true) => queued = true,
+                Ok(false) => {
+                    warn!(
+                        "Too many deliveries are pending for {}, storing a {kind} event as dead letter",
+                        subscriber.identifier
+                    );
+
+                    let dead_letter = NewDeadLetter {
+                        identifier: &subscriber.identifier,
+                        url: &subscriber.url,
+                        payload: &payload,
+                        attempts: 0,
+                        last_error: "Too many deliveries were pending",
+                        created_at: Utc::now(),
+                    };
+                    if let Err(e) =
+                        WebhookModelController::add_dead_letter(dead_letter, db_pool).await
+                    {
+                        error!(
+                            "Failed to store dead letter for {}: {e}",
+                            subscriber.identifier
+                        );
+                    }
+                }
+                Err(e) => error!(
+                    "Failed to queue a {kind} event for {}: {e}",
+                    subscriber.identifier
+                ),
+            }
+        }
+
+        if queued {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Makes the queued deliveries in the background, including those left over from before a
+    /// restart.
+    ///
+    /// Every webhook has at most one delivery in flight, so an endpoint that hangs only holds up
+    /// its own events.
+    pub fn spawn_worker(&self, db_pool: &SqlitePool) {
+        let dispatcher = self.clone();
+        let db_pool = db_pool.clone();
+
+        tokio::spawn(async move {
+            let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+            let in_flight = Arc::new(Mutex::new(HashSet::<Identifier>::new()));
+
+            loop {
+                let free = permits.available_permits();
+                // Due deliveries of webhooks that are busy are skipped, so ask for enough.
+                let limit = free + in_flight.lock().unwrap_or_else(|e| e.into_inner()).len();
+
+                let deliveries = match free {
+                    0 => Vec::new(),
+                    _ => WebhookModelController::due_deliveries(Utc::now(), limit as u32, &db_pool)
+                        .await
+                        .unwrap_or_else(|e| {
+                            error!("Failed to list due webhook deliveries: {e}");
+                            Vec::new()
+                        }),
+                };
+
+                for delivery in deliveries {
+                    let Ok(permit) = permits.clone().try_acquire_owned() else {
+                        break;
+                    };
+                    if !in_flight
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(delivery.identifier.clone())
+                    {
+                        continue;
+                    }
+
+                    let dispatcher = dispatcher.clone();
+                    let db_pool = db_pool.clone();
+                    let in_flight = in_flight.clone();
+                    tokio::spawn(async move {
+                        dispatcher.attempt(&delivery, &db_pool).await;
+
+                        in_flight
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .remove(&delivery.identifier);
+                        drop(permit);
+                        dispatcher.wake.notify_one();
+                    });
+                }
+
+                _ = tokio::time::timeout(POLL_INTERVAL, dispatcher.wake.notified()).await;
+            }
+        });
+    }
+
+    /// Forgets the cached webhooks, so that a change to one applies to the next event.
+    pub async fn forget_subscribers(&self) {
+        *self.subscribers.lock().await = None;
+    }
+
+    /// Lists every webhook with its subscriptions, from the database at most every
+    /// [SUBSCRIBER_CACHE_TTL].
+    async fn subscribers(
+        &self,
+        db_pool: &SqlitePool,
+    ) -> crate::error::Result<Arc<Vec<WebhookSubscriber>>> {
+        let mut cached = self.subscribers.lock().await;
+        if let Some((loaded_at, subscribers)) = cached.as_ref() {
+            if loaded_at.elapsed() < SUBSCRIBER_CACHE_TTL {
+                return Ok(subscribers.clone());
+            }
+        }
+
+        let subscribers = Arc::new(WebhookModelController::list_subscribers(db_pool).await?);
+        *cached = Some((Instant::now(), subscribers.clone()));
+
+        Ok(subscribers)
+    }
+
+    /// Makes one attempt at `delivery`, then forgets it, schedules a retry or stores a dead letter.
+    async fn attempt(&self, delivery: &PendingDelivery, db_pool: &SqlitePool) {
+        let attempt = delivery.attempts + 1;
+
+        let result = match self
+            .deliver(
+                &delivery.url,
+                &delivery.secret,
+                &delivery.kind,
+                &delivery.payload,
+            )
+            .await
+        {
+            Ok(()) => {
+                debug!(
+                    "Delivered a {} event to {}",
+                    delivery.kind, delivery.identifier
+                );
+                WebhookModelController::delete_delivery(delivery.id, db_pool).await
+            }
+            Err(e) if attempt >= self.max_attempts => {
+                warn!(
+                    "Giving up on delivering a {} event to {} after {attempt} attempts: {e}",
+                    delivery.kind, delivery.identifier
+                );
+                WebhookModelController::give_up_delivery(delivery, &e, db_pool)
+                    .await
+                    .map(|_| ())
+            }
+            Err(e) => {
+                let delay = self.retry_delay(attempt);
+                debug!(
+                    "Attempt {attempt} to deliver to {} failed, retrying in {delay:?}: {e}",
+                    delivery.identifier
+                );
+                WebhookModelController::retry_delivery(delivery.id, &e, Utc::now() + delay, db_pool)
+                    .await
+            }
+        };
+
+        if let Err(e) = result {
+            error!(
+                "Failed to update the delivery to {}: {e}",
+                delivery.identifier
+            );
+        }
+    }
+
+    /// Makes a single signed delivery attempt, and returns why it failed.
+    pub async fn deliver(
+        &self,
+        url: &str,
+        secret: &str,
+        kind: &str,
+        payload: &str,
+    ) -> Result<(), String> {
+        // Addresses in the URL are not resolved, so the resolver cannot check them.
+        let parsed = Url::parse(url).map_err(|e| e.to_string())?;
+        if host_address(&parsed).is_some() {
+            self.check_target(url).await?;
+        }
+
+        let timestamp = Utc::now().timestamp().to_string();
+
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .header(SIGNATURE_HEADER, sign(secret, &timestamp, payload))
+            .header(EVENT_HEADER, kind)
+            .body(payload.to_string())
+            .send()
+            .await
+            .map_err(describe_error)?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format!("Endpoint answered {status}"))
+        }
+    }
+
+    /// `WEBHOOK_RETRY_DELAY_MS`, doubled for every attempt that failed before.
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        self.retry_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+/// [PublicResolver] resolves names like the system does, but drops every address that is not
+/// public.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addresses = lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addresses.is_empty() {
+                return Err(format!("{host} has no public addresses").into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Returns the host of `url` if it is an address rather than a name.
+fn host_address(url: &Url) -> Option<IpAddr> {
+    url.host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Returns false for loopback, private, link-local, shared, multicast and other addresses that
+/// do not belong on the internet.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // 0.0.0.0/8, 100.64.0.0/10 (shared address space) and 240.0.0.0/4 (reserved).
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(ip.into());
+            }
+
+            let first = ip.segments()[0];
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7 (unique local) and fe80::/10 (link-local).
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Describes a failed request together with its causes, which [reqwest::Error] leaves out.
+fn describe_error(e: reqwest::Error) -> String {
+    let e = e.without_url();
+    let mut description = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        let cause_description = cause.to_string();
+        if !description.ends_with(&cause_description) {
+            description.push_str(&format!(": {cause_description}"));
+        }
+        source = cause.source();
+    }
+    description
+}
+
+/// Signs a delivery as described in [SIGNATURE_HEADER].
+pub fn sign(secret: &str, timestamp: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_public_rejects_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} should not be public");
+        }
+    }
+
+    #[test]
+    fn is_public_accepts_internet_addresses() {
+        for ip in ["1.1.1.1", "93.184.216.34", "100.128.0.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} should be public");
+        }
+    }
+
+    #[test]
+    fn host_address_finds_literals() {
+        let host = |url: &str| host_address(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            host("http://127.0.0.1:8080/hook"),
+            Some([127, 0, 0, 1].into())
+        );
+        assert_eq!(host("https://[::1]/hook"), Some("::1".parse().unwrap()));
+        assert_eq!(host("https://example.com/hook"), None);
+    }
+}
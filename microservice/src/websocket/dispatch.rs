@@ -0,0 +1,305 @@
+use crate::ctx::ctx_client::Identifier;
+use crate::error::Error;
+use crate::message::Event;
+use crate::model::history::HistoryModelController;
+use crate::AppState;
+
+/// [DispatchError] tells a client why an event it sent was not delivered.
+#[derive(Debug, thiserror::Error)]
+pub enum DispatchError {
+    #[error("Clients cannot publish {0} events")]
+    SystemEvent(&'static str),
+    /// The source and the target of a private message share no subscription.
+    #[error("{0} is not linked to {1}")]
+    NotLinked(Identifier, Identifier),
+    #[error("{0} is offline")]
+    TargetOffline(Identifier),
+    #[error("{0} is not online on {1}")]
+    RecipientOffline(String, Identifier),
+}
+
+impl From<DispatchError> for Error {
+    fn from(e: DispatchError) -> Self {
+        match e {
+            DispatchError::SystemEvent(_) => Error::BadRequest(e.to_string()),
+            DispatchError::NotLinked(..) => Error::Forbidden(e.to_string()),
+            DispatchError::TargetOffline(_) | DispatchError::RecipientOffline(..) => {
+                Error::NotFound(e.to_string())
+            }
+        }
+    }
+}
+
+/// Handles an `event` a client sent, no matter if over its websocket or over HTTP.
+///
+/// System events are rejected, private messages go to their target only and everything else is
+/// published to the subscribers of `source`. Returns the number of connections the event was
+/// queued for.
+///
+/// Presence is not updated here, since it is only tracked for websocket connections. See
+/// [crate::websocket::presence::PresenceTracker].
+pub async fn dispatch_event(
+    app_state: &AppState,
+    source: &Identifier,
+    source_subscriptions: &[String],
+    event: Event,
+) -> Result<usize, DispatchError> {
+    if event.is_system() {
+        return Err(DispatchError::SystemEvent(event.kind()));
+    }
+
+    if let Event::PrivateMessage {
+        target, recipient, ..
+    } = &event
+    {
+        send_private_message(
+            app_state,
+            source,
+            source_subscriptions,
+            target,
+            recipient,
+            &event,
+        )
+        .await?;
+        return Ok(1);
+    }
+
+    Ok(broadcast_event(app_state, source, event).await)
+}
+
+/// Sends `event` to every connection and webhook that is subscribed to `source`, except `source`
+/// itself, and stores it in the history that event streams follow.
+///
+/// Returns the number of connections the event was queued for. Webhooks are only queued for, they
+/// are delivered to in the background and are not counted.
+pub async fn broadcast_event(app_state: &AppState, source: &Identifier, event: Event) -> usize {
+    let delivered = app_state
+        .active_connections
+        .lock()
+        .await
+        .iter()
+        .filter(|conn| conn.identifier != *source && conn.is_subscribed_to(source))
+        .filter(|conn| conn.send_event(Some(source), &event))
+        .count();
+
+    app_state
+        .webhooks
+        .publish(&app_state.db_pool, source, &event)
+        .await;
+
+    let _feed_guard = app_state.event_feed_lock.lock().await;
+    match HistoryModelController::record(source, &event, &app_state.db_pool).await {
+        // Nobody listening is not an error, streams catch up from the history anyway.
+        Ok(entry) => _ = app_state.event_feed.send(entry),
+        Err(e) => tracing::error!("Failed to store a {} event of {source}: {e}", event.kind()),
+    }
+
+    delivered
+}
+
+/// Delivers an [Event::PrivateMessage] from `source` to `recipient` on the `target` client only.
+///
+/// Fails if the two clients are not linked by a subscription in either direction, or if the
+/// target client or player is offline.
+async fn send_private_message(
+    app_state: &AppState,
+    source: &Identifier,
+    source_subscriptions: &[String],
+    target: &Identifier,
+    recipient: &str,
+    event: &Event,
+) -> Result<(), DispatchError> {
+    let active_connections = app_state.active_connections.lock().await;
+    let targets = active_connections
+        .iter()
+        .filter(|conn| !conn.spectator && conn.identifier == *target)
+        .collect::<Vec<_>>();
+
+    if targets.is_empty() {
+        return Err(DispatchError::TargetOffline(target.clone()));
+    }
+
+    let is_linked = source_subscriptions
+        .iter()
+        .any(|sub| target.matches_subscription(sub))
+        || targets.iter().any(|conn| conn.is_subscribed_to(source));
+
+    if !is_linked {
+        return Err(DispatchError::NotLinked(source.clone(), target.clone()));
+    }
+
+    if !app_state.presence.is_online(target, recipient) {
+        return Err(DispatchError::RecipientOffline(
+            recipient.to_string(),
+            target.clone(),
+        ));
+    }
+
+    if !targets
+        .iter()
+        .any(|conn| conn.send_event(Some(source), event))
+    {
+        return Err(DispatchError::TargetOffline(target.clone()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::ws::Message;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::message::Frame;
+    use crate::{queued_frame, ActiveConnection};
+
+    fn identifier(identifier: &str) -> Identifier {
+        identifier.parse().unwrap()
+    }
+
+    fn private_message(target: &str, recipient: &str) -> Event {
+        Event::PrivateMessage {
+            player: "Steve".to_string(),
+            target: identifier(target),
+            recipient: recipient.to_string(),
+            message: "psst".to_string(),
+        }
+    }
+
+    /// Connects `identifier` with `subscriptions` and puts `players` online on it.
+    async fn connect(
+        app_state: &AppState,
+        identifier: &str,
+        subscriptions: &[&str],
+        players: &[&str],
+    ) -> UnboundedReceiver<Message> {
+        let (conn, receiver) = ActiveConnection::for_tests(identifier, subscriptions);
+
+        app_state.presence.connect(&conn.identifier);
+        app_state.presence.apply(
+            &conn.identifier,
+            &Event::PlayerList {
+                players: players.iter().map(|player| player.to_string()).collect(),
+            },
+        );
+        app_state.active_connections.lock().await.push(conn);
+
+        receiver
+    }
+
+    #[tokio::test]
+    async fn delivers_private_messages_to_the_target_only() {
+        let app_state = AppState::for_tests().await;
+        let mut target = connect(&app_state, "other:smp", &[], &["Alex"]).await;
+        let mut bystander = connect(&app_state, "other:cmp", &["kiwitech"], &[]).await;
+
+        let delivered = dispatch_event(
+            &app_state,
+            &identifier("kiwitech:smp"),
+            &["other:smp".to_string()],
+            private_message("other:smp", "alex"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(delivered, 1);
+        assert!(matches!(
+            queued_frame(&mut target),
+            Some(Frame::Event {
+                event: Event::PrivateMessage { .. },
+                ..
+            })
+        ));
+        assert!(queued_frame(&mut bystander).is_none());
+    }
+
+    #[tokio::test]
+    async fn a_subscription_of_the_target_links_it_too() {
+        let app_state = AppState::for_tests().await;
+        let mut target = connect(&app_state, "other:smp", &["kiwitech:smp"], &["Alex"]).await;
+
+        send_private_message(
+            &app_state,
+            &identifier("kiwitech:smp"),
+            &[],
+            &identifier("other:smp"),
+            "Alex",
+            &private_message("other:smp", "Alex"),
+        )
+        .await
+        .unwrap();
+
+        assert!(queued_frame(&mut target).is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_private_messages_to_unlinked_clients() {
+        let app_state = AppState::for_tests().await;
+        let mut target = connect(&app_state, "other:smp", &["third"], &["Alex"]).await;
+
+        let sent = dispatch_event(
+            &app_state,
+            &identifier("kiwitech:smp"),
+            &["third".to_string()],
+            private_message("other:smp", "Alex"),
+        )
+        .await;
+
+        assert!(matches!(sent, Err(DispatchError::NotLinked(..))));
+        assert!(queued_frame(&mut target).is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_private_messages_to_offline_targets_and_recipients() {
+        let app_state = AppState::for_tests().await;
+        let source = identifier("kiwitech:smp");
+        let subscriptions = ["other".to_string()];
+        let mut spectator = connect(&app_state, "other:cmp", &[], &[]).await;
+        app_state.active_connections.lock().await[0].spectator = true;
+        let _target = connect(&app_state, "other:smp", &[], &["Alex"]).await;
+
+        let sent = dispatch_event(
+            &app_state,
+            &source,
+            &subscriptions,
+            private_message("other:cmp", "Alex"),
+        )
+        .await;
+        assert!(matches!(sent, Err(DispatchError::TargetOffline(_))));
+        assert!(queued_frame(&mut spectator).is_none());
+
+        let sent = dispatch_event(
+            &app_state,
+            &source,
+            &subscriptions,
+            private_message("other:smp", "Steve"),
+        )
+        .await;
+        assert!(matches!(sent, Err(DispatchError::RecipientOffline(..))));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_to_subscribers_but_not_back_to_the_source() {
+        let app_state = AppState::for_tests().await;
+        let mut source = connect(&app_state, "kiwitech:smp", &["kiwitech"], &[]).await;
+        let mut subscriber = connect(&app_state, "other:smp", &["kiwitech:smp"], &[]).await;
+        let mut unrelated = connect(&app_state, "other:cmp", &["third"], &[]).await;
+
+        let delivered = dispatch_event(
+            &app_state,
+            &identifier("kiwitech:smp"),
+            &["kiwitech".to_string()],
+            Event::Join {
+                player: "Steve".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(delivered, 1);
+        assert!(queued_frame(&mut subscriber).is_some());
+        assert!(queued_frame(&mut source).is_none());
+        assert!(queued_frame(&mut unrelated).is_none());
+    }
+}
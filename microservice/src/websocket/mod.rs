@@ -1 +1,6 @@
+pub mod dispatch;
+pub mod presence;
+pub mod rpc;
+pub mod template;
+pub mod ticket;
 pub mod websocket_handler;
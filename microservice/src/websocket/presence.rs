@@ -0,0 +1,190 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_client::Identifier;
+use crate::message::Event;
+
+/// [PresenceTracker] keeps the players that are online on every connected client in memory.
+///
+/// It is fed by the [Event::Join], [Event::Leave] and [Event::PlayerList] events that arrive over
+/// a websocket and forgets a client as soon as its last connection is gone.
+#[derive(Debug, Clone, Default)]
+pub struct PresenceTracker {
+    players: Arc<Mutex<HashMap<Identifier, BTreeSet<String>>>>,
+}
+
+impl PresenceTracker {
+    /// Starts tracking `identifier` with nobody online.
+    pub fn connect(&self, identifier: &Identifier) {
+        self.players().entry(identifier.clone()).or_default();
+    }
+
+    /// Stops tracking `identifier`.
+    pub fn disconnect(&self, identifier: &Identifier) {
+        self.players().remove(identifier);
+    }
+
+    /// Updates the players of `source` if `event` is a presence event.
+    pub fn apply(&self, source: &Identifier, event: &Event) {
+        let mut players = self.players();
+
+        match event {
+            Event::Join { player } => {
+                players
+                    .entry(source.clone())
+                    .or_default()
+                    .insert(player.clone());
+            }
+            Event::Leave { player } => {
+                if let Some(online) = players.get_mut(source) {
+                    online.remove(player);
+                }
+            }
+            Event::PlayerList { players: online } => {
+                players.insert(source.clone(), online.iter().cloned().collect());
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the presence of a single client, if it is connected.
+    pub fn get(&self, identifier: &Identifier) -> Option<ClientPresence> {
+        self.players()
+            .get(identifier)
+            .map(|players| ClientPresence::new(identifier, players))
+    }
+
+    /// Returns true if `player` is online on `identifier`. Player names are case-insensitive.
+    pub fn is_online(&self, identifier: &Identifier, player: &str) -> bool {
+        self.players()
+            .get(identifier)
+            .map(|players| players.iter().any(|p| p.eq_ignore_ascii_case(player)))
+            .unwrap_or(false)
+    }
+
+    /// Returns the presence of every connected client of `server_id`, sorted by identifier.
+    pub fn list_by_server_id(&self, server_id: &str) -> Vec<ClientPresence> {
+        let mut presences = self
+            .players()
+            .iter()
+            .filter(|(identifier, _)| identifier.server_id() == server_id)
+            .map(|(identifier, players)| ClientPresence::new(identifier, players))
+            .collect::<Vec<_>>();
+
+        presences.sort_by(|a, b| a.identifier.client_id.cmp(&b.identifier.client_id));
+        presences
+    }
+
+    fn players(&self) -> std::sync::MutexGuard<'_, HashMap<Identifier, BTreeSet<String>>> {
+        self.players.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ClientPresence {
+    pub identifier: Identifier,
+    pub players: Vec<String>,
+}
+
+impl ClientPresence {
+    fn new(identifier: &Identifier, players: &BTreeSet<String>) -> Self {
+        Self {
+            identifier: identifier.clone(),
+            players: players.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifier(identifier: &str) -> Identifier {
+        identifier.parse().unwrap()
+    }
+
+    fn join(player: &str) -> Event {
+        Event::Join {
+            player: player.to_string(),
+        }
+    }
+
+    #[test]
+    fn tracks_joins_and_leaves() {
+        let presence = PresenceTracker::default();
+        let smp = identifier("kiwitech:smp");
+
+        presence.connect(&smp);
+        assert_eq!(presence.get(&smp).unwrap().players, Vec::<String>::new());
+
+        presence.apply(&smp, &join("Steve"));
+        presence.apply(&smp, &join("Alex"));
+        presence.apply(
+            &smp,
+            &Event::Leave {
+                player: "Steve".to_string(),
+            },
+        );
+        presence.apply(
+            &smp,
+            &Event::Chat {
+                player: "Notch".to_string(),
+                message: "hi".to_string(),
+            },
+        );
+
+        assert_eq!(presence.get(&smp).unwrap().players, ["Alex"]);
+        assert!(presence.is_online(&smp, "alex"));
+        assert!(!presence.is_online(&smp, "Steve"));
+    }
+
+    #[test]
+    fn player_lists_replace_what_was_known() {
+        let presence = PresenceTracker::default();
+        let smp = identifier("kiwitech:smp");
+
+        presence.connect(&smp);
+        presence.apply(&smp, &join("Steve"));
+        presence.apply(
+            &smp,
+            &Event::PlayerList {
+                players: vec!["Zed".to_string(), "Alex".to_string()],
+            },
+        );
+
+        assert_eq!(presence.get(&smp).unwrap().players, ["Alex", "Zed"]);
+    }
+
+    #[test]
+    fn forgets_clients_on_disconnect() {
+        let presence = PresenceTracker::default();
+        let smp = identifier("kiwitech:smp");
+
+        presence.connect(&smp);
+        presence.apply(&smp, &join("Steve"));
+        presence.disconnect(&smp);
+
+        assert!(presence.get(&smp).is_none());
+        assert!(!presence.is_online(&smp, "Steve"));
+    }
+
+    #[test]
+    fn lists_the_clients_of_one_server_sorted() {
+        let presence = PresenceTracker::default();
+
+        for client in ["kiwitech:smp", "kiwitech:cmp", "other:smp"] {
+            presence.connect(&identifier(client));
+        }
+
+        let listed = presence
+            .list_by_server_id("kiwitech")
+            .into_iter()
+            .map(|presence| presence.identifier.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(listed, ["kiwitech:cmp", "kiwitech:smp"]);
+    }
+}
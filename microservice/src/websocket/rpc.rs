@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use crate::ctx::ctx_client::Identifier;
+use crate::message::{Frame, RpcError, RpcErrorCode, RpcRequest, RpcResponse};
+use crate::ActiveConnections;
+
+/// How long a request waits for its answer if the requester did not ask for anything else.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound for the timeout a requester may ask for.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// [RpcBroker] routes [RpcRequest]s to their target connection and hands the matching
+/// [RpcResponse] back to whoever is waiting for it.
+///
+/// Requests are forwarded with a server-generated id, so ids chosen by different requesters
+/// can never collide.
+#[derive(Debug, Clone, Default)]
+pub struct RpcBroker {
+    next_id: Arc<AtomicU64>,
+    pending: Arc<Mutex<HashMap<String, PendingRequest>>>,
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    target: Identifier,
+    reply: oneshot::Sender<RpcResponse>,
+}
+
+impl RpcBroker {
+    /// Sends `request` to its target on behalf of `origin` and waits for the answer.
+    ///
+    /// The origin may only reach clients of its own server or clients it is subscribed to.
+    pub async fn request(
+        &self,
+        active_connections: &ActiveConnections,
+        origin: &Identifier,
+        origin_subscriptions: &[String],
+        request: RpcRequest,
+    ) -> Result<Value, RpcError> {
+        let target = request.target.clone();
+
+        if origin.server_id() != target.server_id()
+            && !origin_subscriptions
+                .iter()
+                .any(|sub| target.matches_subscription(sub))
+        {
+            return Err(RpcError::new(
+                RpcErrorCode::Forbidden,
+                format!("{origin} is not allowed to send requests to {target}"),
+            ));
+        }
+
+        let timeout = request
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_TIMEOUT)
+            .min(MAX_TIMEOUT);
+
+        let Some(connection) = active_connections
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .find(|conn| !conn.spectator && conn.identifier == target)
+            .cloned()
+        else {
+            return Err(RpcError::new(
+                RpcErrorCode::TargetOffline,
+                format!("{target} is not connected"),
+            ));
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let (reply, receiver) = oneshot::channel();
+
+        self.pending().insert(
+            id.clone(),
+            PendingRequest {
+                target: target.clone(),
+                reply,
+            },
+        );
+
+        let forwarded = RpcRequest {
+            id: id.clone(),
+            source: Some(origin.clone()),
+            timeout_ms: Some(timeout.as_millis() as u64),
+            ..request
+        };
+
+        if !connection.send(&Frame::Request(forwarded)) {
+            self.pending().remove(&id);
+            return Err(RpcError::new(
+                RpcErrorCode::TargetOffline,
+                format!("{target} is not connected"),
+            ));
+        }
+
+        let result = tokio::time::timeout(timeout, receiver).await;
+        self.pending().remove(&id);
+
+        match result {
+            Ok(Ok(response)) => response.into_result(),
+            Ok(Err(_)) => Err(RpcError::new(
+                RpcErrorCode::TargetOffline,
+                format!("{target} disconnected before answering"),
+            )),
+            Err(_) => Err(RpcError::new(
+                RpcErrorCode::Timeout,
+                format!("{target} did not answer within {}ms", timeout.as_millis()),
+            )),
+        }
+    }
+
+    /// Hands a response sent by `responder` to the waiting requester.
+    ///
+    /// Returns false if nobody is waiting for it, or if it was meant to come from another client.
+    pub fn resolve(&self, responder: &Identifier, response: RpcResponse) -> bool {
+        let mut pending = self.pending();
+
+        match pending.get(&response.id) {
+            Some(request) if request.target == *responder => {}
+            _ => return false,
+        }
+
+        pending
+            .remove(&response.id)
+            .map(|request| request.reply.send(response).is_ok())
+            .unwrap_or(false)
+    }
+
+    /// Fails every request waiting on `target`, so their callers don't have to wait for the timeout.
+    pub fn cancel_for(&self, target: &Identifier) {
+        self.pending()
+            .retain(|_, request| request.target != *target);
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, HashMap<String, PendingRequest>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::ws::Message;
+    use serde_json::json;
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use tokio::sync::Mutex as TokioMutex;
+
+    use super::*;
+    use crate::{queued_frame, ActiveConnection};
+
+    fn identifier(identifier: &str) -> Identifier {
+        identifier.parse().unwrap()
+    }
+
+    fn request(target: &str, timeout_ms: Option<u64>) -> RpcRequest {
+        RpcRequest {
+            id: "mine".to_string(),
+            source: None,
+            target: identifier(target),
+            method: "list_players".to_string(),
+            params: Value::Null,
+            timeout_ms,
+        }
+    }
+
+    fn connections(targets: &[&str]) -> (ActiveConnections, Vec<UnboundedReceiver<Message>>) {
+        let (connections, receivers) = targets
+            .iter()
+            .map(|target| ActiveConnection::for_tests(target, &[]))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+        (Arc::new(TokioMutex::new(connections)), receivers)
+    }
+
+    /// Waits until a request was forwarded to `receiver` and returns it.
+    async fn forwarded(receiver: &mut UnboundedReceiver<Message>) -> RpcRequest {
+        loop {
+            match queued_frame(receiver) {
+                Some(Frame::Request(request)) => return request,
+                Some(frame) => panic!("Expected a request, got {frame:?}"),
+                None => tokio::task::yield_now().await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_requests_with_fresh_ids_and_returns_the_answer() {
+        let broker = RpcBroker::default();
+        let (connections, mut receivers) = connections(&["kiwitech:smp"]);
+        let origin = identifier("kiwitech:web");
+
+        let first = tokio::spawn({
+            let (broker, connections, origin) =
+                (broker.clone(), connections.clone(), origin.clone());
+            async move {
+                broker
+                    .request(&connections, &origin, &[], request("kiwitech:smp", None))
+                    .await
+            }
+        });
+        let first_forwarded = forwarded(&mut receivers[0]).await;
+
+        let second = tokio::spawn({
+            let (broker, connections, origin) =
+                (broker.clone(), connections.clone(), origin.clone());
+            async move {
+                broker
+                    .request(&connections, &origin, &[], request("kiwitech:smp", None))
+                    .await
+            }
+        });
+        let second_forwarded = forwarded(&mut receivers[0]).await;
+
+        assert_ne!(first_forwarded.id, "mine");
+        assert_ne!(first_forwarded.id, second_forwarded.id);
+        assert_eq!(first_forwarded.source, Some(origin));
+        assert_eq!(
+            first_forwarded.timeout_ms,
+            Some(DEFAULT_TIMEOUT.as_millis() as u64)
+        );
+
+        let responder = identifier("kiwitech:smp");
+        assert!(broker.resolve(
+            &responder,
+            RpcResponse::from_result(second_forwarded.id, Ok(json!(2)))
+        ));
+        assert!(broker.resolve(
+            &responder,
+            RpcResponse::from_result(first_forwarded.id, Ok(json!(1)))
+        ));
+
+        assert_eq!(first.await.unwrap().unwrap(), json!(1));
+        assert_eq!(second.await.unwrap().unwrap(), json!(2));
+    }
+
+    #[tokio::test]
+    async fn caps_the_timeout_and_fails_requests_when_the_target_disconnects() {
+        let broker = RpcBroker::default();
+        let (connections, mut receivers) = connections(&["kiwitech:smp"]);
+
+        let pending = tokio::spawn({
+            let broker = broker.clone();
+            async move {
+                broker
+                    .request(
+                        &connections,
+                        &identifier("kiwitech:web"),
+                        &[],
+                        request("kiwitech:smp", Some(60 * 60 * 1000)),
+                    )
+                    .await
+            }
+        });
+
+        let forwarded = forwarded(&mut receivers[0]).await;
+        assert_eq!(forwarded.timeout_ms, Some(MAX_TIMEOUT.as_millis() as u64));
+
+        broker.cancel_for(&identifier("kiwitech:smp"));
+
+        let error = pending.await.unwrap().unwrap_err();
+        assert_eq!(error.code, RpcErrorCode::TargetOffline);
+        assert!(!broker.resolve(
+            &identifier("kiwitech:smp"),
+            RpcResponse::from_result(forwarded.id, Ok(json!(1)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_the_target_can_answer() {
+        let broker = RpcBroker::default();
+        let (connections, mut receivers) = connections(&["kiwitech:smp"]);
+
+        let pending = tokio::spawn({
+            let broker = broker.clone();
+            async move {
+                broker
+                    .request(
+                        &connections,
+                        &identifier("kiwitech:web"),
+                        &[],
+                        request("kiwitech:smp", None),
+                    )
+                    .await
+            }
+        });
+        let forwarded = forwarded(&mut receivers[0]).await;
+
+        assert!(!broker.resolve(
+            &identifier("kiwitech:cmp"),
+            RpcResponse::from_result(forwarded.id.clone(), Ok(json!("forged")))
+        ));
+        assert!(broker.resolve(
+            &identifier("kiwitech:smp"),
+            RpcResponse::from_result(forwarded.id, Ok(json!("real")))
+        ));
+        assert_eq!(pending.await.unwrap().unwrap(), json!("real"));
+    }
+
+    #[tokio::test]
+    async fn rejects_unlinked_and_offline_targets() {
+        let broker = RpcBroker::default();
+        let (connections, _receivers) = connections(&["other:smp"]);
+        let origin = identifier("kiwitech:web");
+
+        let error = broker
+            .request(&connections, &origin, &[], request("other:smp", None))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, RpcErrorCode::Forbidden);
+
+        let error = broker
+            .request(
+                &connections,
+                &origin,
+                &["other".to_string()],
+                request("other:cmp", None),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, RpcErrorCode::TargetOffline);
+    }
+}
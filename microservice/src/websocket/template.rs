@@ -0,0 +1,207 @@
+//! Renders events into plain text for clients that want pre-rendered messages.
+//!
+//! Templates may use these placeholders:
+//!
+//! - `{source}`: the full identifier of the source, e.g. `kiwitech:smp`
+//! - `{server}`: the server ID of the source, e.g. `kiwitech`
+//! - `{client}`: the client ID of the source, e.g. `smp`
+//! - `{kind}`: the event kind, e.g. `chat`
+//! - `{player}`: the player the event is about
+//! - `{message}`: the chat message, or the reason of an `offline` event
+//! - `{recipient}`: the receiving player of a private message
+
+use crate::ctx::ctx_client::Identifier;
+use crate::message::Event;
+use crate::model::config::SubscriptionTemplates;
+
+/// Renders `event` from `source` with the template of the first subscription entry that covers
+/// `source`, falling back to [default_template].
+///
+/// Returns `None` for events that have no textual representation.
+pub fn render_event(
+    templates: &SubscriptionTemplates,
+    subscriptions: &[String],
+    source: &Identifier,
+    event: &Event,
+) -> Option<String> {
+    let kind = event.kind();
+
+    let template = subscriptions
+        .iter()
+        .filter(|sub| source.matches_subscription(sub))
+        .find_map(|sub| templates.get(sub).and_then(|t| t.get(kind)))
+        .map(String::as_str)
+        .or_else(|| default_template(event))?;
+
+    let (player, message, recipient) = match event {
+        Event::Chat { player, message } => (player.as_str(), message.as_str(), ""),
+        Event::Join { player } | Event::Leave { player } => (player.as_str(), "", ""),
+        Event::PrivateMessage {
+            player,
+            recipient,
+            message,
+            ..
+        } => (player.as_str(), message.as_str(), recipient.as_str()),
+        Event::Offline { reason } => ("", reason.as_str(), ""),
+        Event::Error { message } | Event::Notice { message } => ("", message.as_str(), ""),
+        Event::PlayerList { .. } | Event::Online => ("", "", ""),
+    };
+
+    let source_id = source.to_string();
+
+    Some(fill_placeholders(template, |name| match name {
+        "source" => Some(source_id.as_str()),
+        "server" => Some(source.server_id()),
+        "client" => Some(source.client_id()),
+        "kind" => Some(kind),
+        "player" => Some(player),
+        "recipient" => Some(recipient),
+        "message" => Some(message),
+        _ => None,
+    }))
+}
+
+/// Replaces every `{name}` in `template` for which `value` knows a value, in a single pass.
+///
+/// Values are never scanned for placeholders themselves, so a player named `{message}` stays
+/// `{message}`. Unknown placeholders are left as they are.
+fn fill_placeholders<'a>(template: &str, value: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let placeholder = rest
+            .find('}')
+            .and_then(|end| Some((end, value(&rest[1..end])?)));
+
+        match placeholder {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The template used when a subscription does not define one for the event kind.
+pub fn default_template(event: &Event) -> Option<&'static str> {
+    match event {
+        Event::Chat { .. } => Some("[{client}] <{player}> {message}"),
+        Event::Join { .. } => Some("[{client}] {player} joined the game"),
+        Event::Leave { .. } => Some("[{client}] {player} left the game"),
+        Event::PrivateMessage { .. } => Some("[{client}] {player} whispers to you: {message}"),
+        Event::Online => Some("[{client}] went online"),
+        Event::Offline { .. } => Some("[{client}] went offline ({message})"),
+        Event::PlayerList { .. } | Event::Error { .. } | Event::Notice { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn source() -> Identifier {
+        "kiwitech:smp".parse().unwrap()
+    }
+
+    fn chat(player: &str, message: &str) -> Event {
+        Event::Chat {
+            player: player.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn templates(sub: &str, kind: &str, template: &str) -> SubscriptionTemplates {
+        HashMap::from([(
+            sub.to_string(),
+            HashMap::from([(kind.to_string(), template.to_string())]),
+        )])
+    }
+
+    #[test]
+    fn renders_with_the_default_template() {
+        let rendered = render_event(
+            &SubscriptionTemplates::new(),
+            &["kiwitech".to_string()],
+            &source(),
+            &chat("Steve", "hi"),
+        );
+
+        assert_eq!(rendered.as_deref(), Some("[smp] <Steve> hi"));
+    }
+
+    #[test]
+    fn renders_with_the_template_of_the_matching_subscription() {
+        let templates = templates("kiwitech", "chat", "{source} {server} {kind}: {player}");
+        let subscriptions = ["other".to_string(), "kiwitech".to_string()];
+
+        let rendered = render_event(&templates, &subscriptions, &source(), &chat("Steve", "hi"));
+
+        assert_eq!(
+            rendered.as_deref(),
+            Some("kiwitech:smp kiwitech chat: Steve")
+        );
+    }
+
+    #[test]
+    fn events_without_text_are_not_rendered() {
+        let event = Event::PlayerList {
+            players: vec!["Steve".to_string()],
+        };
+
+        assert_eq!(
+            render_event(&SubscriptionTemplates::new(), &[], &source(), &event),
+            None
+        );
+    }
+
+    #[test]
+    fn values_are_not_expanded_again() {
+        let rendered = render_event(
+            &SubscriptionTemplates::new(),
+            &[],
+            &source(),
+            &chat("{message}", "{player} {client}"),
+        );
+
+        assert_eq!(
+            rendered.as_deref(),
+            Some("[smp] <{message}> {player} {client}")
+        );
+
+        let event = Event::PrivateMessage {
+            player: "Steve".to_string(),
+            target: "other:smp".parse().unwrap(),
+            recipient: "{player}".to_string(),
+            message: "hi".to_string(),
+        };
+        let templates = templates("kiwitech", "private_message", "{recipient} <- {player}");
+
+        let rendered = render_event(&templates, &["kiwitech".to_string()], &source(), &event);
+
+        assert_eq!(rendered.as_deref(), Some("{player} <- Steve"));
+    }
+
+    #[test]
+    fn unknown_placeholders_and_stray_braces_are_kept() {
+        let value = |name: &str| (name == "player").then_some("Steve");
+
+        assert_eq!(
+            fill_placeholders("{{player}} {unknown} {player", value),
+            "{Steve} {unknown} {player"
+        );
+        assert_eq!(fill_placeholders("} {} {", value), "} {} {");
+    }
+}
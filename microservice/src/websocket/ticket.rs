@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use sha2::Sha256;
+use utoipa::ToSchema;
+
+use crate::ctx::ctx_client::Identifier;
+use crate::database_utils::generate_secret;
+use crate::model::token::{AuthenticatedToken, TokenScope};
+
+/// How long a ticket can be redeemed after it was issued.
+pub const TICKET_LIFETIME: Duration = Duration::from_secs(30);
+
+/// [TicketIssuer] hands out signed, single-use tickets that authenticate a websocket handshake.
+///
+/// Browsers cannot set headers on a websocket handshake, so they fetch a ticket with their token
+/// first and pass it as `/ws?ticket=...`. Tickets are signed with a key that is generated on
+/// startup, so they do not survive a restart.
+#[derive(Clone)]
+pub struct TicketIssuer {
+    key: Arc<[u8; 32]>,
+    /// Nonces of redeemed tickets and when they would have expired.
+    redeemed: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl Default for TicketIssuer {
+    fn default() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+
+        Self {
+            key: Arc::new(key),
+            redeemed: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for TicketIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketIssuer").finish_non_exhaustive()
+    }
+}
+
+impl TicketIssuer {
+    /// Issues a ticket for `identifier`, authenticated with `token`.
+    ///
+    /// The ticket only carries the websocket scopes of the token, so a spectator token stays
+    /// read-only.
+    pub fn issue(&self, identifier: &Identifier, token: &AuthenticatedToken) -> IssuedTicket {
+        let expires_at = Utc::now() + TICKET_LIFETIME;
+        let scope = if token.is_spectator() {
+            TokenScope::Spectate
+        } else {
+            TokenScope::WsConnect
+        };
+
+        let claims = TicketClaims {
+            identifier: identifier.clone(),
+            token_id: token.token_id,
+            scopes: vec![scope],
+            expires_at: expires_at.timestamp(),
+            nonce: generate_secret(),
+        };
+
+        let payload = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&claims).expect("ticket claims are always serializable"));
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(payload.as_bytes()));
+
+        IssuedTicket {
+            ticket: format!("{payload}.{signature}"),
+            expires_at,
+        }
+    }
+
+    /// Checks the signature and expiry of `ticket` and marks it as used.
+    ///
+    /// Returns `None` if the ticket is forged, expired or was redeemed before.
+    pub fn redeem(&self, ticket: &str) -> Option<TicketClaims> {
+        let (payload, signature) = ticket.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        self.mac()
+            .chain_update(payload.as_bytes())
+            .verify_slice(&signature)
+            .ok()?;
+
+        let claims: TicketClaims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        let expires_at = DateTime::from_timestamp(claims.expires_at, 0)?;
+        let now = Utc::now();
+
+        if expires_at <= now {
+            return None;
+        }
+
+        let mut redeemed = self.redeemed.lock().unwrap_or_else(|e| e.into_inner());
+        redeemed.retain(|_, expires_at| *expires_at > now);
+
+        if redeemed.insert(claims.nonce.clone(), expires_at).is_some() {
+            return None;
+        }
+
+        Some(claims)
+    }
+
+    fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        self.mac()
+            .chain_update(payload)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.key.as_slice()).expect("HMAC accepts any key length")
+    }
+}
+
+/// What a ticket grants, signed by the [TicketIssuer].
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketClaims {
+    #[serde_as(as = "DisplayFromStr")]
+    pub identifier: Identifier,
+    pub token_id: Option<i64>,
+    pub scopes: Vec<TokenScope>,
+    /// Unix timestamp in seconds.
+    pub expires_at: i64,
+    pub nonce: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IssuedTicket {
+    pub ticket: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifier() -> Identifier {
+        "kiwitech:smp".parse().unwrap()
+    }
+
+    fn token(scopes: &[TokenScope]) -> AuthenticatedToken {
+        AuthenticatedToken {
+            token_id: Some(1),
+            scopes: scopes.to_vec(),
+        }
+    }
+
+    /// Signs `claims` the way [TicketIssuer::issue] does.
+    fn sign_claims(issuer: &TicketIssuer, claims: &TicketClaims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(issuer.sign(payload.as_bytes()));
+        format!("{payload}.{signature}")
+    }
+
+    #[test]
+    fn redeem_accepts_issued_ticket() {
+        let issuer = TicketIssuer::default();
+        let issued = issuer.issue(&identifier(), &token(&[TokenScope::WsConnect]));
+
+        let claims = issuer.redeem(&issued.ticket).unwrap();
+
+        assert_eq!(claims.identifier, identifier());
+        assert_eq!(claims.token_id, Some(1));
+        assert_eq!(claims.scopes, vec![TokenScope::WsConnect]);
+    }
+
+    #[test]
+    fn redeem_rejects_replayed_ticket() {
+        let issuer = TicketIssuer::default();
+        let issued = issuer.issue(&identifier(), &token(&[TokenScope::WsConnect]));
+
+        assert!(issuer.redeem(&issued.ticket).is_some());
+        assert!(issuer.redeem(&issued.ticket).is_none());
+    }
+
+    #[test]
+    fn redeem_rejects_expired_ticket() {
+        let issuer = TicketIssuer::default();
+        let claims = TicketClaims {
+            identifier: identifier(),
+            token_id: Some(1),
+            scopes: vec![TokenScope::WsConnect],
+            expires_at: Utc::now().timestamp() - 1,
+            nonce: generate_secret(),
+        };
+
+        assert!(issuer.redeem(&sign_claims(&issuer, &claims)).is_none());
+    }
+
+    #[test]
+    fn redeem_rejects_tampered_ticket() {
+        let issuer = TicketIssuer::default();
+        let issued = issuer.issue(&identifier(), &token(&[TokenScope::Spectate]));
+        let (payload, signature) = issued.ticket.split_once('.').unwrap();
+
+        // Claims that were changed after signing.
+        let mut claims: TicketClaims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        claims.scopes = vec![TokenScope::WsConnect];
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        assert!(issuer
+            .redeem(&format!("{forged_payload}.{signature}"))
+            .is_none());
+
+        // A ticket signed by another issuer, for example before a restart.
+        let other = TicketIssuer::default();
+        assert!(issuer.redeem(&sign_claims(&other, &claims)).is_none());
+
+        assert!(issuer.redeem("garbage").is_none());
+        assert!(issuer.redeem(&format!("{payload}.")).is_none());
+    }
+
+    #[test]
+    fn spectator_ticket_stays_read_only() {
+        let issuer = TicketIssuer::default();
+        let issued = issuer.issue(&identifier(), &token(&[TokenScope::Spectate]));
+
+        let claims = issuer.redeem(&issued.ticket).unwrap();
+        let redeemed = AuthenticatedToken {
+            token_id: claims.token_id,
+            scopes: claims.scopes,
+        };
+
+        assert!(redeemed.is_spectator());
+        assert!(!redeemed.has_scope(TokenScope::WsConnect));
+    }
+
+    #[test]
+    fn ticket_carries_only_websocket_scopes() {
+        let issuer = TicketIssuer::default();
+        let issued = issuer.issue(
+            &identifier(),
+            &token(&[TokenScope::WsConnect, TokenScope::ConfigWrite]),
+        );
+
+        let claims = issuer.redeem(&issued.ticket).unwrap();
+
+        assert_eq!(claims.scopes, vec![TokenScope::WsConnect]);
+    }
+}
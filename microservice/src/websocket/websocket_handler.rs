@@ -1,28 +1,215 @@
 use std::net::SocketAddr;
+use std::ops::ControlFlow;
 
-use axum::extract::ws::WebSocket;
-use futures_util::StreamExt;
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
 
-use crate::ctx::ctx_client::ClientCtx;
-use crate::message::process_message;
-use crate::{ActiveConnection, ActiveConnections};
+use crate::ctx::ctx_client::Identifier;
+use crate::message::{process_message, Event, Frame, RpcResponse};
+use crate::model::config::ClientConfig;
+use crate::model::status::StatusModelController;
+use crate::websocket::dispatch::{broadcast_event, dispatch_event, DispatchError};
+use crate::{ActiveConnection, AppState};
+
+/// Disconnect reason for connections that ended without a close frame.
+const CONNECTION_LOST: &str = "connection lost";
 
 pub async fn handle_socket(
     socket: WebSocket,
     address: SocketAddr,
-    subscriptions: Vec<String>,
-    client_ctx: ClientCtx,
-    conn: ActiveConnection,
-    active_connections: ActiveConnections,
+    config: ClientConfig,
+    render: bool,
+    spectator: bool,
+    app_state: AppState,
 ) {
-    let receive_task = tokio::spawn(async move {
-        let (mut ws_sender, mut ws_receiver) = socket.split();
-        while let Some(Ok(msg)) = ws_receiver.next().await {
-            if process_message(msg.clone(), address).is_break() {
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+
+    let conn = ActiveConnection::new(config, render, spectator, address, sender);
+    let is_first_connection = {
+        let mut active_connections = app_state.active_connections.lock().await;
+        let is_first = !is_online(&active_connections, &conn.identifier);
+
+        active_connections.push(conn.clone());
+        is_first && !conn.spectator
+    };
+
+    if !conn.spectator {
+        app_state.presence.connect(&conn.identifier);
+
+        if let Err(e) =
+            StatusModelController::record_connected(&conn.identifier, &app_state.db_pool).await
+        {
+            error!("Failed to record connect of {}: {e}", conn.identifier);
+        }
+    }
+
+    if is_first_connection {
+        broadcast_event(&app_state, &conn.identifier, Event::Online).await;
+    }
+
+    let mut send_task = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let is_close = matches!(message, Message::Close(_));
+
+            if ws_sender.send(message).await.is_err() || is_close {
                 break;
             }
         }
     });
+
+    let receive_conn = conn.clone();
+    let receive_state = app_state.clone();
+    let mut receive_task = tokio::spawn(async move {
+        while let Some(Ok(msg)) = ws_receiver.next().await {
+            match process_message(msg, address) {
+                ControlFlow::Break(reason) => return reason,
+                ControlFlow::Continue(Some(frame)) => {
+                    receive_conn.record_received();
+                    handle_frame(frame, &receive_conn, &receive_state).await
+                }
+                ControlFlow::Continue(None) => {}
+            }
+        }
+
+        CONNECTION_LOST.to_string()
+    });
+
+    let reason = tokio::select! {
+        _ = &mut send_task => {
+            receive_task.abort();
+            CONNECTION_LOST.to_string()
+        }
+        reason = &mut receive_task => {
+            send_task.abort();
+            reason.unwrap_or_else(|_| CONNECTION_LOST.to_string())
+        }
+    };
+    let reason = conn.kick_reason().unwrap_or(reason);
+
+    let is_last_connection = {
+        let mut active_connections = app_state.active_connections.lock().await;
+        active_connections.retain(|c| c.connection_id != conn.connection_id);
+
+        !conn.spectator && !is_online(&active_connections, &conn.identifier)
+    };
+
+    if is_last_connection {
+        app_state.rpc_broker.cancel_for(&conn.identifier);
+        app_state.presence.disconnect(&conn.identifier);
+
+        broadcast_event(
+            &app_state,
+            &conn.identifier,
+            Event::Offline {
+                reason: reason.clone(),
+            },
+        )
+        .await;
+    }
+
+    if !conn.spectator {
+        if let Err(e) = StatusModelController::record_disconnected(
+            &conn.identifier,
+            &reason,
+            &app_state.db_pool,
+        )
+        .await
+        {
+            error!("Failed to record disconnect of {}: {e}", conn.identifier);
+        }
+    }
+
+    info!(
+        "{} at {} disconnected: {}",
+        conn.identifier, address, reason
+    );
+}
+
+/// Returns true if a connection other than a spectator is open for `identifier`.
+fn is_online(active_connections: &[ActiveConnection], identifier: &Identifier) -> bool {
+    active_connections
+        .iter()
+        .any(|c| !c.spectator && c.identifier == *identifier)
+}
+
+async fn handle_frame(frame: Frame, conn: &ActiveConnection, app_state: &AppState) {
+    if conn.spectator {
+        warn!("Spectator {} tried to publish a frame", conn.identifier);
+        conn.send_event(
+            None,
+            &Event::Error {
+                message: "Spectators cannot publish".to_string(),
+            },
+        );
+        return;
+    }
+
+    match frame {
+        Frame::Event { event, .. } => {
+            // Presence ends with the connection, so only events that arrive over one count.
+            app_state.presence.apply(&conn.identifier, &event);
+
+            match dispatch_event(app_state, &conn.identifier, &conn.subscriptions, event).await {
+                Ok(_) => {}
+                Err(e @ DispatchError::SystemEvent(_)) => {
+                    warn!("{} tried to publish a system event: {e}", conn.identifier);
+                }
+                Err(e) => {
+                    conn.send_event(
+                        None,
+                        &Event::Error {
+                            message: e.to_string(),
+                        },
+                    );
+                }
+            }
+        }
+        Frame::Request(request) => {
+            let conn = conn.clone();
+            let app_state = app_state.clone();
+
+            tokio::spawn(async move {
+                let id = request.id.clone();
+                let result = app_state
+                    .rpc_broker
+                    .request(
+                        &app_state.active_connections,
+                        &conn.identifier,
+                        &conn.subscriptions,
+                        request,
+                    )
+                    .await;
+
+                conn.send(&Frame::Response(RpcResponse::from_result(id, result)));
+            });
+        }
+        Frame::Response(response) => {
+            if !app_state.rpc_broker.resolve(&conn.identifier, response) {
+                debug!("{} sent a response nobody was waiting for", conn.identifier);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spectators_do_not_count_as_online() {
+        let (smp, _receiver) = ActiveConnection::for_tests("kiwitech:smp", &[]);
+        let (mut spectator, _spectator_receiver) = ActiveConnection::for_tests("kiwitech:cmp", &[]);
+        spectator.spectator = true;
+
+        let connections = [smp, spectator];
+
+        assert!(is_online(&connections, &"kiwitech:smp".parse().unwrap()));
+        assert!(!is_online(&connections, &"kiwitech:cmp".parse().unwrap()));
+        assert!(!is_online(&connections, &"other:smp".parse().unwrap()));
+    }
 }
 
 // #[derive(Debug)]